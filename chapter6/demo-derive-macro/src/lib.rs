@@ -1,6 +1,6 @@
 use std::collections::BTreeMap;
 
-use mini_lust_chap6::{OrigType, TType};
+use mini_lust_chap6::{Bytes, OrigType, TType};
 
 #[derive(::mini_lust_macros::Message)]
 pub struct Friend {
@@ -24,6 +24,14 @@ pub struct TestUser {
 
 impl OrigType for TestUser {}
 
+#[derive(::mini_lust_macros::Message)]
+pub struct Blob {
+    #[mini_lust(field_id = 1, required = "true", field_type = "bytes")]
+    data: Bytes,
+}
+
+impl OrigType for Blob {}
+
 #[derive(::mini_lust_macros::Message)]
 #[mini_lust(dispatch_only = true)]
 pub enum MyArgs {
@@ -39,3 +47,29 @@ pub enum MyResult {
     Exception(TestUser),
 }
 
+#[cfg(test)]
+mod tests {
+    use bytes::BytesMut;
+    use mini_lust_chap6::{
+        Bytes, Message, MsgContext, TBinaryInputProtocol, TBinaryOutputProtocol,
+    };
+
+    use crate::Blob;
+
+    #[test]
+    fn test_bytes_field_zero_copy() {
+        let blob = Blob {
+            data: Bytes::from_static(b"blob"),
+        };
+        let mut buf = BytesMut::new();
+        let mut protocol = TBinaryOutputProtocol::new(&mut buf, true);
+        blob.encode(&MsgContext::default(), &mut protocol).unwrap();
+        let input = buf.as_ptr_range();
+
+        let mut protocol = TBinaryInputProtocol::new(&mut buf, true);
+        let decoded = Blob::decode(&mut MsgContext::default(), &mut protocol).unwrap();
+        assert_eq!(&decoded.data[..], b"blob");
+        // the field is a slice of the input buffer
+        assert!(input.contains(&decoded.data.as_ptr()));
+    }
+}
@@ -98,13 +98,26 @@ pub mod demo {
             &mut self,
             req: GetUserRequest,
             shuffle: bool,
+        ) -> ::mini_lust_chap6::Result<AnonymousItemServiceGetUserResult> {
+            self.GetUser_with_options(req, shuffle, ::mini_lust_chap6::CallOptions::default())
+                .await
+        }
+        #[allow(non_snake_case)]
+        pub async fn GetUser_with_options(
+            &mut self,
+            req: GetUserRequest,
+            shuffle: bool,
+            options: ::mini_lust_chap6::CallOptions,
         ) -> ::mini_lust_chap6::Result<AnonymousItemServiceGetUserResult> {
             let anonymous_request =
                 AnonymousItemServiceRequest::GetUser(AnonymousItemServiceGetUserArgs {
                     req: Some(req),
                     shuffle: Some(shuffle),
                 });
-            let resp = self.inner_client.call("GetUser", anonymous_request).await?;
+            let resp = self
+                .inner_client
+                .call_with_options("GetUser", anonymous_request, options)
+                .await?;
             #[allow(irrefutable_let_patterns)]
             if let AnonymousItemServiceResponse::GetUser(r) = resp {
                 return Ok(r);
@@ -133,6 +146,13 @@ pub mod demo {
             }
         }
     }
+    impl<S> Clone for ItemServiceServer<S> {
+        fn clone(&self) -> Self {
+            Self {
+                inner: self.inner.clone(),
+            }
+        }
+    }
     impl<S>
         ::tower::Service<(
             ::mini_lust_chap6::MsgContext,
@@ -644,13 +644,26 @@ impl ItemServiceClient {
         &mut self,
         req: GetUserRequest,
         shuffle: bool,
+    ) -> ::mini_lust_chap6::Result<AnonymousItemServiceGetUserResult> {
+        self.get_user_with_options(req, shuffle, ::mini_lust_chap6::CallOptions::default())
+            .await
+    }
+
+    pub async fn get_user_with_options(
+        &mut self,
+        req: GetUserRequest,
+        shuffle: bool,
+        options: ::mini_lust_chap6::CallOptions,
     ) -> ::mini_lust_chap6::Result<AnonymousItemServiceGetUserResult> {
         let anonymous_request =
             AnonymousItemServiceRequest::GetUser(AnonymousItemServiceGetUserArgs {
                 req: Some(req),
                 shuffle: Some(shuffle),
             });
-        let resp = self.inner_client.call("GetUser", anonymous_request).await?;
+        let resp = self
+            .inner_client
+            .call_with_options("GetUser", anonymous_request, options)
+            .await?;
 
         #[allow(irrefutable_let_patterns)]
         if let AnonymousItemServiceResponse::GetUser(r) = resp {
@@ -684,6 +697,14 @@ impl<S> ItemServiceServer<S> {
     }
 }
 
+impl<S> Clone for ItemServiceServer<S> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
 impl<S>
     tower::Service<(
         ::mini_lust_chap6::MsgContext,
@@ -145,6 +145,14 @@ impl CodeGen for Service {
                     }
                 }
             }
+
+            impl<S> Clone for #server_name<S> {
+                fn clone(&self) -> Self {
+                    Self {
+                        inner: self.inner.clone(),
+                    }
+                }
+            }
         });
 
         let mut server_match_arms = Vec::new();
@@ -235,4 +243,14 @@ mod tests {
         .1;
         assert_eq!(s.gen_token().unwrap().to_string(), "# [derive (:: mini_lust_macros :: Message , Debug , Clone , PartialEq)] pub struct my_struct { # [mini_lust (field_id = 1i16 , required = \"true\" , field_type = \"i32\")] pub count : i32 , # [mini_lust (field_id = 2i16 , required = \"false\" , field_type = \"map(string, string)\")] pub user_list : :: std :: option :: Option < :: std :: collections :: BTreeMap < :: std :: string :: String , :: std :: string :: String > > , } impl :: mini_lust_chap6 :: OrigType for my_struct { }");
     }
+
+    #[test]
+    fn test_gen_uuid_field() {
+        // #[derive(Debug)]
+        // pub struct Session {
+        //     pub id: [u8; 16],
+        // }
+        let s = Struct::parse("struct Session {1: required uuid id}").unwrap().1;
+        assert_eq!(s.gen_token().unwrap().to_string(), "# [derive (:: mini_lust_macros :: Message , Debug , Clone , PartialEq)] pub struct Session { # [mini_lust (field_id = 1i16 , required = \"true\" , field_type = \"uuid\")] pub id : [u8 ; 16] , } impl :: mini_lust_chap6 :: OrigType for Session { }");
+    }
 }
@@ -36,6 +36,7 @@ impl FormatFieldType for FieldType {
                 format!("set({})", inner.format())
             }
             FieldType::Binary => "binary".to_string(),
+            FieldType::Uuid => "uuid".to_string(),
         }
     }
 }
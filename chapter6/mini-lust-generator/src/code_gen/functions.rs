@@ -60,12 +60,25 @@ impl FunctionGen for Function {
     //         req: GetUserRequest,
     //         shuffle: bool,
     //     ) -> ::mini_lust_chap6::Result<AnonymousItemServiceGetUserResult> {
+    //         self.get_user_with_options(req, shuffle, ::mini_lust_chap6::CallOptions::default())
+    //             .await
+    //     }
+    //
+    // pub async fn get_user_with_options(
+    //         &mut self,
+    //         req: GetUserRequest,
+    //         shuffle: bool,
+    //         options: ::mini_lust_chap6::CallOptions,
+    //     ) -> ::mini_lust_chap6::Result<AnonymousItemServiceGetUserResult> {
     //         let anonymous_request =
     //             AnonymousItemServiceRequest::GetUser(AnonymousItemServiceGetUserArgs {
     //                 req: Some(req),
     //                 shuffle: Some(shuffle),
     //             });
-    //         let resp = self.inner_client.call("GetUser", anonymous_request).await?;
+    //         let resp = self
+    //             .inner_client
+    //             .call_with_options("GetUser", anonymous_request, options)
+    //             .await?;
     //
     //         #[allow(irrefutable_let_patterns)]
     //         if let AnonymousItemServiceResponse::GetUser(r) = resp {
@@ -82,13 +95,16 @@ impl FunctionGen for Function {
         let anonymous_request = quote::format_ident!("Anonymous{}Request", service_ident);
         let anonymous_response = quote::format_ident!("Anonymous{}Response", service_ident);
         let func_name = quote::format_ident!("{}", self.name.clone().into_inner());
+        let func_name_with_options = quote::format_ident!("{}_with_options", self.name.clone().into_inner());
         let func_name_string = self.name.clone().into_inner();
 
         let mut named_parameters = Vec::new();
         let mut assignments = Vec::new();
+        let mut parameter_names = Vec::new();
         for field in self.parameters.iter() {
             named_parameters.push(field.gen_name_type(true)?);
             let field_name = quote::format_ident!("{}", field.name.clone().into_inner());
+            parameter_names.push(field_name.clone());
             if field.required == None {
                 assignments.push(quote::quote! { #field_name: Some(#field_name), })
             } else {
@@ -101,12 +117,27 @@ impl FunctionGen for Function {
             pub async fn #func_name(
                 &mut self,
                 #(#named_parameters)*
+            ) -> ::mini_lust_chap6::Result<#anonymous_result> {
+                self.#func_name_with_options(
+                    #(#parameter_names,)*
+                    ::mini_lust_chap6::CallOptions::default(),
+                ).await
+            }
+
+            #[allow(non_snake_case)]
+            pub async fn #func_name_with_options(
+                &mut self,
+                #(#named_parameters)*
+                options: ::mini_lust_chap6::CallOptions,
             ) -> ::mini_lust_chap6::Result<#anonymous_result> {
                 let anonymous_request =
-                    #anonymous_request::GetUser(#anonymous_args {
+                    #anonymous_request::#func_name(#anonymous_args {
                         #(#assignments)*
                     });
-                let resp = self.inner_client.call(#func_name_string, anonymous_request).await?;
+                let resp = self
+                    .inner_client
+                    .call_with_options(#func_name_string, anonymous_request, options)
+                    .await?;
 
                 #[allow(irrefutable_let_patterns)]
                 if let #anonymous_response::#func_name(r) = resp {
@@ -18,6 +18,7 @@ impl CodeGen for FieldType {
             FieldType::Double => quote::quote! { f64 },
             FieldType::String => quote::quote! { ::std::string::String },
             FieldType::Binary => quote::quote! { ::std::vec::Vec<u8> },
+            FieldType::Uuid => quote::quote! { [u8; 16] },
             FieldType::Map(k, v) => {
                 let k = k.gen_token()?;
                 let v = v.gen_token()?;
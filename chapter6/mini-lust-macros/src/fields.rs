@@ -57,9 +57,81 @@ pub(crate) fn encode_content(type_: &FieldType, ident: &Ident) -> TokenStream {
         FieldType::Void => {
             quote::quote! { ().encode(cx, protocol)?; }
         }
-        FieldType::Binary => {
+        FieldType::Binary | FieldType::Bytes => {
             quote::quote! { protocol.write_bytes(&#ident)?; }
         }
+        FieldType::Uuid => quote::quote! { protocol.write_uuid(*#ident)?; },
+    }
+}
+
+pub(crate) fn encoded_size_content(type_: &FieldType, ident: &Ident) -> TokenStream {
+    match type_ {
+        FieldType::String => quote::quote! { protocol.string_len(#ident) },
+        FieldType::Bool => quote::quote! { protocol.bool_len(*#ident) },
+        FieldType::I8 => quote::quote! { protocol.i8_len(*#ident) },
+        FieldType::I16 => quote::quote! { protocol.i16_len(*#ident) },
+        FieldType::I32 => quote::quote! { protocol.i32_len(*#ident) },
+        FieldType::I64 => quote::quote! { protocol.i64_len(*#ident) },
+        FieldType::Double => quote::quote! { protocol.double_len(*#ident) },
+        FieldType::Byte => quote::quote! { protocol.byte_len(*#ident) },
+        FieldType::Ident(_) => {
+            quote::quote! { ::mini_lust_chap6::Message::encoded_size(#ident, cx, protocol) }
+        }
+        FieldType::List(val) => {
+            let inner = encoded_size_content(val, &quote::format_ident!("val"));
+            quote::quote! {
+                {
+                    let mut size = protocol.list_begin_len(&::mini_lust_chap6::TListIdentifier {
+                        element_type: #val,
+                        size: #ident.len() as i32,
+                    });
+                    for val in #ident.iter() {
+                        size += #inner;
+                    }
+                    size + protocol.list_end_len()
+                }
+            }
+        }
+        FieldType::Map(key, value) => {
+            let key_inner = encoded_size_content(key, &quote::format_ident!("key"));
+            let value_inner = encoded_size_content(value, &quote::format_ident!("value"));
+            quote::quote! {
+                {
+                    let mut size = protocol.map_begin_len(&::mini_lust_chap6::TMapIdentifier {
+                        key_type: Some(#key),
+                        value_type: Some(#value),
+                        size: #ident.len() as i32,
+                    });
+                    for (key, value) in #ident.iter() {
+                        size += #key_inner;
+                        size += #value_inner;
+                    }
+                    size + protocol.map_end_len()
+                }
+            }
+        }
+        FieldType::Set(val) => {
+            let inner = encoded_size_content(val, &quote::format_ident!("val"));
+            quote::quote! {
+                {
+                    let mut size = protocol.set_begin_len(&::mini_lust_chap6::TSetIdentifier {
+                        element_type: #val,
+                        size: #ident.len() as i32,
+                    });
+                    for val in #ident.iter() {
+                        size += #inner;
+                    }
+                    size + protocol.set_end_len()
+                }
+            }
+        }
+        FieldType::Void => {
+            quote::quote! { ::mini_lust_chap6::Message::encoded_size(&(), cx, protocol) }
+        }
+        FieldType::Binary | FieldType::Bytes => {
+            quote::quote! { protocol.bytes_len(&#ident) }
+        }
+        FieldType::Uuid => quote::quote! { protocol.uuid_len(*#ident) },
     }
 }
 
@@ -129,6 +201,8 @@ pub(crate) fn decode_content(type_: &FieldType) -> TokenStream {
                 }
             }
         },
-        FieldType::Binary => quote::quote! { protocol.read_bytes()? }
+        FieldType::Binary => quote::quote! { protocol.read_bytes()? },
+        FieldType::Bytes => quote::quote! { protocol.read_binary()? },
+        FieldType::Uuid => quote::quote! { protocol.read_uuid()? },
     }
 }
\ No newline at end of file
@@ -15,14 +15,14 @@ pub fn message(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let name = receiver.ident;
     let generics = receiver.generics;
 
-    let (tok_enc, tok_dec) = if receiver.data.is_struct() {
+    let (tok_enc, tok_dec, tok_size) = if receiver.data.is_struct() {
         let struct_stream = receiver.data.take_struct().unwrap();
         fields_to_message(name.clone(), struct_stream.fields)
     } else if receiver.data.is_enum() {
         let enum_stream = receiver.data.take_enum().unwrap();
         enum_to_message(name.clone(), enum_stream, receiver.dispatch_only)
     } else {
-        (TokenStream::new(), TokenStream::new())
+        (TokenStream::new(), TokenStream::new(), TokenStream::new())
     };
 
     let ts2 = quote::quote! {
@@ -34,6 +34,10 @@ pub fn message(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
             fn decode<T: ::mini_lust_chap6::TInputProtocol>(cx: &mut ::mini_lust_chap6::MsgContext, protocol: &mut T) -> ::mini_lust_chap6::Result<Self> {
                 #tok_dec
             }
+
+            fn encoded_size<T: ::mini_lust_chap6::TLengthProtocol>(&self, cx: &::mini_lust_chap6::MsgContext, protocol: &mut T) -> usize {
+                #tok_size
+            }
         }
     };
     proc_macro::TokenStream::from(ts2)
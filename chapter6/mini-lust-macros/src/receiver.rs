@@ -2,7 +2,7 @@ use darling::{ast, FromDeriveInput, FromField, FromMeta, FromVariant};
 use proc_macro2::TokenStream;
 use quote::ToTokens;
 
-use crate::fields::{decode_content, encode_content};
+use crate::fields::{decode_content, encode_content, encoded_size_content};
 use crate::types::FieldType;
 
 #[derive(Debug, Clone, Copy, FromMeta)]
@@ -103,6 +103,40 @@ impl FieldReceiver {
         conditional_encode
     }
 
+    fn to_encoded_size_tokens(&self) -> TokenStream {
+        let ident = self.ident.clone().expect("field ident is required");
+        let field_id = self.field_id as i16;
+        let field_type = FieldType::parse(&self.field_type).expect("unable to parse field type");
+
+        let inner = encoded_size_content(&field_type, &quote::format_ident!("inner"));
+        let encoded_size = quote::quote! {
+            size += protocol.field_begin_len(&::mini_lust_chap6::TFieldIdentifier {
+                name: Some(stringify!(#ident).to_string()),
+                field_type: #field_type,
+                id: Some(#field_id),
+            });
+            size += #inner;
+            size += protocol.field_end_len();
+        };
+
+        match self.required {
+            Required::True => {
+                quote::quote! {
+                    let inner = &self.#ident;
+                    #encoded_size
+                }
+            }
+            // a missing field is reported by encode
+            Required::False | Required::Unspecified => {
+                quote::quote! {
+                    if let Some(inner) = self.#ident.as_ref() {
+                        #encoded_size
+                    }
+                }
+            }
+        }
+    }
+
     fn to_decode_option_declare(&self) -> TokenStream {
         let ident = self.ident.clone().expect("field ident is required");
         let field_prefix_name = quote::format_ident!("field_{}", ident);
@@ -172,6 +206,28 @@ impl EnumReceiver {
         }
     }
 
+    pub fn to_encoded_size_arms(&self) -> TokenStream {
+        let ident = self.ident.clone();
+        let field_id = self.field_id.expect("field id is required");
+        let inner_name =if let syn::Type::Path(p) = self.fields.fields.first().expect("enum inner is required").ty.clone() {
+            p.path.segments.last().expect("no ident found for enum inner").clone().ident
+        } else {
+            panic!("enum inner is not Path");
+        };
+
+        quote::quote! {
+            Self::#ident(inner) => {
+                size += protocol.field_begin_len(&::mini_lust_chap6::TFieldIdentifier {
+                    name: Some(stringify!(#inner_name).to_string()),
+                    field_type: ::mini_lust_chap6::TType::Struct,
+                    id: Some(#field_id),
+                });
+                size += ::mini_lust_chap6::Message::encoded_size(inner, cx, protocol);
+                size += protocol.field_end_len();
+            }
+        }
+    }
+
     pub fn to_decode_arms(&self) -> TokenStream {
         let ident = self.ident.clone();
         let field_id = self.field_id.expect("field id is required");
@@ -192,6 +248,14 @@ impl EnumReceiver {
         }
     }
 
+    pub fn to_encoded_size_dispatch_arms(&self) -> TokenStream {
+        let ident = self.ident.clone();
+
+        quote::quote! {
+            Self::#ident(req) => ::mini_lust_chap6::Message::encoded_size(req, cx, protocol),
+        }
+    }
+
     pub fn to_decode_dispatch_arms(&self) -> TokenStream {
         let ident = self.ident.clone();
         let ident_name = self.ident.clone().to_string();
@@ -207,7 +271,7 @@ impl EnumReceiver {
 pub(crate) fn fields_to_message(
     struct_ident: syn::Ident,
     fields: Vec<FieldReceiver>,
-) -> (proc_macro2::TokenStream, proc_macro2::TokenStream) {
+) -> (proc_macro2::TokenStream, proc_macro2::TokenStream, proc_macro2::TokenStream) {
     let inner_encode = fields
         .iter()
         .map(FieldReceiver::to_encode_tokens)
@@ -222,6 +286,19 @@ pub(crate) fn fields_to_message(
         Ok(())
     };
 
+    let inner_encoded_size = fields
+        .iter()
+        .map(FieldReceiver::to_encoded_size_tokens)
+        .collect::<Vec<_>>();
+    let encoded_size = quote::quote! {
+        let mut size = protocol.struct_begin_len(&::mini_lust_chap6::TStructIdentifier {
+            name: stringify!(#struct_ident).to_string(),
+        });
+        #(#inner_encoded_size)*
+        size += protocol.field_stop_len();
+        size + protocol.struct_end_len()
+    };
+
     let decode_option_declare = fields
         .iter()
         .map(FieldReceiver::to_decode_option_declare)
@@ -260,17 +337,18 @@ pub(crate) fn fields_to_message(
 
         Ok(output)
     };
-    (encode, decode)
+    (encode, decode, encoded_size)
 }
 
 pub(crate) fn enum_to_message(
     enum_ident: syn::Ident,
     enums: Vec<EnumReceiver>,
     dispatch_only: bool
-) -> (proc_macro2::TokenStream, proc_macro2::TokenStream) {
+) -> (proc_macro2::TokenStream, proc_macro2::TokenStream, proc_macro2::TokenStream) {
     if dispatch_only {
         let encode_dispatch_arms = enums.iter().map(EnumReceiver::to_encode_dispatch_arms).collect::<Vec<_>>();
         let decode_dispatch_arms = enums.iter().map(EnumReceiver::to_decode_dispatch_arms).collect::<Vec<_>>();
+        let encoded_size_dispatch_arms = enums.iter().map(EnumReceiver::to_encoded_size_dispatch_arms).collect::<Vec<_>>();
 
         let encode = quote::quote! {
             match self {
@@ -286,12 +364,18 @@ pub(crate) fn enum_to_message(
                 )),
             }
         };
-        return (encode, decode);
+        let encoded_size = quote::quote! {
+            match self {
+                #(#encoded_size_dispatch_arms)*
+            }
+        };
+        return (encode, decode, encoded_size);
     }
 
     // Non-dispatch code
     let encode_arms = enums.iter().map(EnumReceiver::to_encode_arms).collect::<Vec<_>>();
     let decode_arms = enums.iter().map(EnumReceiver::to_decode_arms).collect::<Vec<_>>();
+    let encoded_size_arms = enums.iter().map(EnumReceiver::to_encoded_size_arms).collect::<Vec<_>>();
 
     let encode = quote::quote! {
         protocol.write_struct_begin(&::mini_lust_chap6::TStructIdentifier {
@@ -305,6 +389,17 @@ pub(crate) fn enum_to_message(
         Ok(())
     };
 
+    let encoded_size = quote::quote! {
+        let mut size = protocol.struct_begin_len(&::mini_lust_chap6::TStructIdentifier {
+            name: stringify!(#enum_ident).to_string(),
+        });
+        match self {
+            #(#encoded_size_arms)*
+        }
+        size += protocol.field_stop_len();
+        size + protocol.struct_end_len()
+    };
+
     let decode = quote::quote! {
         protocol.read_struct_begin()?;
         let ident = protocol.read_field_begin()?;
@@ -323,5 +418,5 @@ pub(crate) fn enum_to_message(
         Ok(output)
     };
 
-    (encode, decode)
+    (encode, decode, encoded_size)
 }
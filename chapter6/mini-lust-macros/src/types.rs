@@ -19,6 +19,10 @@ pub(crate) enum FieldType {
     Set(Box<FieldType>),
     Void,
     Binary,
+    /// `binary` decoded as `bytes::Bytes` without copying.
+    Bytes,
+    /// `uuid` as its 16 bytes, `[u8; 16]`.
+    Uuid,
 }
 
 impl ToTokens for FieldType {
@@ -69,9 +73,12 @@ impl FieldType {
             FieldType::Void => {
                 quote::quote! { ::mini_lust_chap6::TType::Void }
             }
-            FieldType::Binary => {
+            FieldType::Binary | FieldType::Bytes => {
                 quote::quote! { ::mini_lust_chap6::TType::String }
             }
+            FieldType::Uuid => {
+                quote::quote! { ::mini_lust_chap6::TType::Uuid }
+            }
         }
     }
 
@@ -93,6 +100,8 @@ impl FieldType {
                 Some(seg) if seg.ident == "double" => Ok(Self::Double),
                 Some(seg) if seg.ident == "byte" => Ok(Self::Byte),
                 Some(seg) if seg.ident == "void" => Ok(Self::Void),
+                Some(seg) if seg.ident == "bytes" => Ok(Self::Bytes),
+                Some(seg) if seg.ident == "uuid" => Ok(Self::Uuid),
                 _ => {
                     return Err(syn::Error::new(span, ""));
                 }
@@ -167,9 +176,22 @@ mod tests {
         println!("{:?}", FieldType::parse("ident(my_name)").unwrap());
         println!("{:?}", FieldType::parse("map(string, byte)").unwrap());
         println!("{:?}", FieldType::parse("list(i32)").unwrap());
+        println!("{:?}", FieldType::parse("bytes").unwrap());
         println!(
             "{:?}",
             FieldType::parse("list(map(i8, map(i16, i32)))").unwrap()
         );
     }
+
+    #[test]
+    fn test_parse_uuid() {
+        let t = FieldType::parse("uuid").unwrap();
+        assert!(matches!(t, FieldType::Uuid));
+        assert_eq!(
+            t.to_token_stream().to_string(),
+            ":: mini_lust_chap6 :: TType :: Uuid"
+        );
+        let t = FieldType::parse("list(uuid)").unwrap();
+        assert!(matches!(t, FieldType::List(inner) if matches!(*inner, FieldType::Uuid)));
+    }
 }
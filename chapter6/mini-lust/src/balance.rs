@@ -0,0 +1,553 @@
+//! Load balancing of the calls of a client over its discovered endpoints.
+//!
+//! An endpoint whose call fails with a transport error is ejected: it takes
+//! no call until its ejection time is over, or one of its calls in flight
+//! succeeds.
+//!
+//! Mini-lust note: all the endpoints share one transport client, so the
+//! balancers pick the target of each call rather than a service per endpoint
+//! as `tower::balance` does.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use tower::{BoxError, Service, ServiceExt};
+
+use crate::connection::SocketOrUnix;
+use crate::context::MsgContext;
+use crate::discover::Endpoints;
+use crate::retry::unmarked;
+use crate::utils::{random, BoxFuture};
+use crate::{new_transport_error, TransportErrorKind};
+
+pub(crate) const DEFAULT_EJECTION_TIME: Duration = Duration::from_secs(5);
+
+// Points of each endpoint on the hash ring.
+const VIRTUAL_NODES: usize = 64;
+
+/// How a client with discovered endpoints picks the endpoint of each call.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Balance {
+    /// Each endpoint in turn. The default.
+    #[default]
+    RoundRobin,
+    /// Of two endpoints picked at random, the one with fewer calls pending.
+    PowerOfTwoChoices,
+    /// Each endpoint in turn, as many times in a row as its weight, spread
+    /// smoothly. Endpoints not listed weigh 1, the others at least 1.
+    Weighted(HashMap<SocketOrUnix, u32>),
+    /// The endpoint of `CallOptions::hash_key` on a hash ring, so that calls
+    /// with the same key go to the same endpoint while it is healthy, and
+    /// only the keys of an endpoint move when it comes or goes. Calls
+    /// without a key go round robin.
+    ConsistentHash,
+}
+
+struct Endpoint {
+    target: SocketOrUnix,
+    pending: AtomicUsize,
+    ejected_until: Mutex<Option<Instant>>,
+}
+
+impl Endpoint {
+    fn is_healthy(&self, now: Instant) -> bool {
+        match *self.ejected_until.lock().unwrap() {
+            Some(until) => until <= now,
+            None => true,
+        }
+    }
+}
+
+/// An endpoint picked for a call, pending until dropped.
+pub(crate) struct Picked {
+    endpoint: Arc<Endpoint>,
+    ejection_time: Duration,
+}
+
+impl Picked {
+    pub(crate) fn target(&self) -> &SocketOrUnix {
+        &self.endpoint.target
+    }
+
+    /// Eject the endpoint if the call failed with a transport error, restore
+    /// it if it succeeded.
+    pub(crate) fn report<T>(&self, result: &Result<T, BoxError>) {
+        let failed = match result {
+            Ok(_) => false,
+            Err(e) => match e.downcast_ref::<crate::Error>().map(unmarked) {
+                Some(crate::Error::Transport(_)) => true,
+                _ => return,
+            },
+        };
+        let mut ejected_until = self.endpoint.ejected_until.lock().unwrap();
+        if failed {
+            if ejected_until.is_none() {
+                log::warn!("endpoint {:?} ejected", self.endpoint.target);
+            }
+            *ejected_until = Some(Instant::now() + self.ejection_time);
+        } else {
+            *ejected_until = None;
+        }
+    }
+}
+
+impl Drop for Picked {
+    fn drop(&mut self) {
+        self.endpoint.pending.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// The endpoints of a client, and the state of their balancing.
+pub(crate) struct Balancer {
+    balance: Balance,
+    ejection_time: Duration,
+    endpoints: Vec<Arc<Endpoint>>,
+    // Current weights of the smooth weighted round robin, by endpoint.
+    current_weights: Vec<i64>,
+    // Points of the endpoints on the hash ring, sorted.
+    ring: Vec<(u64, usize)>,
+    next: usize,
+}
+
+impl Balancer {
+    pub(crate) fn new(balance: Balance, ejection_time: Duration) -> Self {
+        Self {
+            balance,
+            ejection_time,
+            endpoints: Vec::new(),
+            current_weights: Vec::new(),
+            ring: Vec::new(),
+            next: 0,
+        }
+    }
+
+    pub(crate) fn insert(&mut self, target: SocketOrUnix) {
+        self.endpoints.push(Arc::new(Endpoint {
+            target,
+            pending: AtomicUsize::new(0),
+            ejected_until: Mutex::new(None),
+        }));
+        self.current_weights.push(0);
+        self.build_ring();
+    }
+
+    /// Remove `target` once, returns whether it is gone.
+    pub(crate) fn remove(&mut self, target: &SocketOrUnix) -> bool {
+        if let Some(i) = self.endpoints.iter().position(|e| e.target == *target) {
+            self.endpoints.remove(i);
+            self.current_weights.remove(i);
+            self.build_ring();
+        }
+        self.endpoints.iter().all(|e| e.target != *target)
+    }
+
+    fn build_ring(&mut self) {
+        if self.balance != Balance::ConsistentHash {
+            return;
+        }
+        self.ring.clear();
+        for (i, endpoint) in self.endpoints.iter().enumerate() {
+            for node in 0..VIRTUAL_NODES {
+                self.ring.push((hash(&ring_key(&endpoint.target, node)), i));
+            }
+        }
+        self.ring.sort_unstable();
+    }
+
+    /// The endpoint of a call keyed `hash_key`, among the healthy ones, or
+    /// among all of them when none is.
+    pub(crate) fn pick(&mut self, hash_key: Option<u64>) -> crate::Result<Picked> {
+        if self.endpoints.is_empty() {
+            return Err(new_transport_error(
+                TransportErrorKind::NotOpen,
+                "no endpoint discovered",
+            ));
+        }
+        let now = Instant::now();
+        let healthy: Vec<usize> = (0..self.endpoints.len())
+            .filter(|&i| self.endpoints[i].is_healthy(now))
+            .collect();
+        // Ejecting every endpoint would fail all the calls until one is back,
+        // one of them may well be up again.
+        let healthy = if healthy.is_empty() {
+            (0..self.endpoints.len()).collect()
+        } else {
+            healthy
+        };
+        let i = match (&self.balance, hash_key) {
+            (Balance::PowerOfTwoChoices, _) => self.pick_less_loaded(&healthy),
+            (Balance::Weighted(weights), _) => pick_weighted(
+                &self.endpoints,
+                &mut self.current_weights,
+                &healthy,
+                weights,
+            ),
+            (Balance::ConsistentHash, Some(key)) => self.pick_hashed(key, now),
+            _ => {
+                let i = healthy[self.next % healthy.len()];
+                self.next = self.next.wrapping_add(1);
+                i
+            }
+        };
+        let endpoint = self.endpoints[i].clone();
+        endpoint.pending.fetch_add(1, Ordering::Relaxed);
+        Ok(Picked {
+            endpoint,
+            ejection_time: self.ejection_time,
+        })
+    }
+
+    fn pick_less_loaded(&self, healthy: &[usize]) -> usize {
+        if healthy.len() == 1 {
+            return healthy[0];
+        }
+        let first = random() as usize % healthy.len();
+        let mut second = random() as usize % (healthy.len() - 1);
+        if second >= first {
+            second += 1;
+        }
+        let pending = |i: usize| self.endpoints[healthy[i]].pending.load(Ordering::Relaxed);
+        if pending(second) < pending(first) {
+            healthy[second]
+        } else {
+            healthy[first]
+        }
+    }
+
+    fn pick_hashed(&self, key: u64, now: Instant) -> usize {
+        let point = hash(&key.to_be_bytes());
+        let start = self.ring.partition_point(|&(p, _)| p < point) % self.ring.len();
+        (0..self.ring.len())
+            .map(|offset| self.ring[(start + offset) % self.ring.len()].1)
+            .find(|&i| self.endpoints[i].is_healthy(now))
+            .unwrap_or(self.ring[start].1)
+    }
+}
+
+/// Smooth weighted round robin: the endpoint furthest behind its share of
+/// the calls.
+fn pick_weighted(
+    endpoints: &[Arc<Endpoint>],
+    current_weights: &mut [i64],
+    healthy: &[usize],
+    weights: &HashMap<SocketOrUnix, u32>,
+) -> usize {
+    let mut total = 0;
+    let mut best = healthy[0];
+    for &i in healthy {
+        let weight = weights
+            .get(&endpoints[i].target)
+            .map_or(1, |&weight| weight.max(1));
+        current_weights[i] += weight as i64;
+        total += weight as i64;
+        if current_weights[i] > current_weights[best] {
+            best = i;
+        }
+    }
+    current_weights[best] -= total;
+    best
+}
+
+/// FNV-1a, so that clients of other processes, or built with other Rust
+/// versions, place the endpoints and the keys alike. Its last bytes barely
+/// move the high bits, they are mixed as in MurmurHash3 to spread close keys
+/// over the ring.
+fn hash(bytes: &[u8]) -> u64 {
+    let mut hash = bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    });
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    hash ^ (hash >> 33)
+}
+
+/// The bytes hashed to place `node` of `target` on the ring.
+fn ring_key(target: &SocketOrUnix, node: usize) -> Vec<u8> {
+    let mut key = match target {
+        SocketOrUnix::Socket(addr) => addr.to_string().into_bytes(),
+        SocketOrUnix::Host(host, port) => format!("{}:{}", host, port).into_bytes(),
+        #[cfg(unix)]
+        SocketOrUnix::Unix(path) => {
+            use std::os::unix::ffi::OsStrExt;
+            path.as_os_str().as_bytes().to_vec()
+        }
+        SocketOrUnix::Memory(name) => name.clone().into_bytes(),
+    };
+    key.push(b'#');
+    key.extend_from_slice(&(node as u32).to_be_bytes());
+    key
+}
+
+/// Where the calls of a client go.
+#[derive(Clone)]
+pub(crate) enum Targets {
+    Endpoint(SocketOrUnix),
+    Discovered(Arc<Endpoints>),
+}
+
+/// Send each call to the target picked for it, reporting how it went.
+#[derive(Clone)]
+pub(crate) struct Balanced<S> {
+    inner: S,
+    targets: Targets,
+}
+
+impl<S> Balanced<S> {
+    pub(crate) fn new(inner: S, targets: Targets) -> Self {
+        Self { inner, targets }
+    }
+}
+
+impl<S, Req> Service<(MsgContext, Req)> for Balanced<S>
+where
+    S: Service<(MsgContext, Req), Error = BoxError> + Clone + Send + 'static,
+    S::Response: Send,
+    S::Future: Send,
+    Req: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = BoxError;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        // The inner service is made ready for each call once its target is
+        // picked.
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, (mut cx, req): (MsgContext, Req)) -> Self::Future {
+        let inner = self.inner.clone();
+        let targets = self.targets.clone();
+        Box::pin(async move {
+            match targets {
+                Targets::Endpoint(target) => {
+                    cx.target = Some(target);
+                    inner.oneshot((cx, req)).await
+                }
+                Targets::Discovered(endpoints) => {
+                    let picked = endpoints.pick(cx.hash_key).await?;
+                    cx.target = Some(picked.target().clone());
+                    let resp = inner.oneshot((cx, req)).await;
+                    picked.report(&resp);
+                    resp
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    use tower::{service_fn, BoxError};
+
+    use crate::balance::{hash, Balance, Balancer, Picked};
+    use crate::connection::SocketOrUnix;
+    use crate::context::MsgContext;
+    use crate::memory::MemoryAddr;
+    use crate::retry::unsent;
+    use crate::testing::serve_in_memory;
+    use crate::{
+        new_protocol_error, new_transport_error, ApplicationResult, CallOptions, Client,
+        ClientBuilder, ProtocolErrorKind, RetryPolicy, Server, StaticList, TransportErrorKind,
+    };
+
+    fn memory(name: &str) -> SocketOrUnix {
+        SocketOrUnix::Memory(name.to_string())
+    }
+
+    fn balancer(balance: Balance, names: &[&str]) -> Balancer {
+        let mut balancer = Balancer::new(balance, Duration::from_millis(20));
+        for name in names {
+            balancer.insert(memory(name));
+        }
+        balancer
+    }
+
+    fn name(picked: &Picked) -> &str {
+        match picked.target() {
+            SocketOrUnix::Memory(name) => name,
+            target => panic!("unexpected {:?}", target),
+        }
+    }
+
+    fn pick(balancer: &mut Balancer, hash_key: Option<u64>) -> String {
+        name(&balancer.pick(hash_key).unwrap()).to_string()
+    }
+
+    fn picks(balancer: &mut Balancer, n: usize) -> Vec<String> {
+        (0..n).map(|_| pick(balancer, None)).collect()
+    }
+
+    fn fail(picked: Picked, e: crate::Error) {
+        picked.report::<()>(&Err(BoxError::from(e)));
+    }
+
+    fn transport_error() -> crate::Error {
+        new_transport_error(TransportErrorKind::NotOpen, "connection refused")
+    }
+
+    #[test]
+    fn test_round_robin_and_ejection() {
+        let mut balancer = balancer(Balance::RoundRobin, &["a", "b", "c"]);
+        assert_eq!(picks(&mut balancer, 4), ["a", "b", "c", "a"]);
+
+        // other errors are the call's own
+        let b = balancer.pick(None).unwrap();
+        assert_eq!(name(&b), "b");
+        fail(b, new_protocol_error(ProtocolErrorKind::InvalidData, "bad"));
+        assert!(picks(&mut balancer, 3).contains(&"b".to_string()));
+
+        for _ in 0..3 {
+            let picked = balancer.pick(None).unwrap();
+            if name(&picked) == "b" {
+                fail(picked, unsent(transport_error()));
+            }
+        }
+        assert!(!picks(&mut balancer, 4).contains(&"b".to_string()));
+        for _ in 0..2 {
+            fail(balancer.pick(None).unwrap(), transport_error());
+        }
+        // all ejected, all are tried
+        let mut picked = picks(&mut balancer, 3);
+        picked.sort();
+        assert_eq!(picked, ["a", "b", "c"]);
+
+        // back once ejected long enough
+        std::thread::sleep(Duration::from_millis(30));
+        let mut in_flight: Vec<Picked> = (0..6).map(|_| balancer.pick(None).unwrap()).collect();
+        in_flight.sort_by(|x, y| name(x).cmp(name(y)));
+        assert_eq!(
+            in_flight.iter().map(name).collect::<Vec<_>>(),
+            ["a", "a", "b", "b", "c", "c"]
+        );
+
+        // or once one of its calls succeeds
+        let a = in_flight.remove(0);
+        fail(a, transport_error());
+        assert!(!picks(&mut balancer, 4).contains(&"a".to_string()));
+        in_flight[0].report::<()>(&Ok(()));
+        assert!(picks(&mut balancer, 3).contains(&"a".to_string()));
+    }
+
+    #[test]
+    fn test_power_of_two_choices() {
+        let mut balancer = balancer(Balance::PowerOfTwoChoices, &["a", "b"]);
+        let busy = balancer.pick(None).unwrap();
+        let idle = if name(&busy) == "a" { "b" } else { "a" };
+        for _ in 0..10 {
+            assert_eq!(pick(&mut balancer, None), idle);
+        }
+        drop(busy);
+        let mut picked = picks(&mut balancer, 20);
+        picked.dedup();
+        assert!(picked.len() > 1);
+    }
+
+    #[test]
+    fn test_weighted() {
+        let weights = HashMap::from([(memory("a"), 3), (memory("c"), 0)]);
+        let mut balancer = balancer(Balance::Weighted(weights), &["a", "b"]);
+        assert_eq!(picks(&mut balancer, 8), ["a", "a", "b", "a"].repeat(2));
+        // weighing 0 is weighing 1
+        balancer.insert(memory("c"));
+        let picked = picks(&mut balancer, 10);
+        assert_eq!(picked.iter().filter(|name| *name == "c").count(), 2);
+    }
+
+    #[test]
+    fn test_consistent_hash() {
+        let mut balancer = balancer(Balance::ConsistentHash, &["a", "b", "c"]);
+        let owners = |balancer: &mut Balancer| -> Vec<String> {
+            (0..100).map(|key| pick(balancer, Some(key))).collect()
+        };
+        // the ring is the same in every process
+        assert_eq!(hash(b"thrift"), 0xca30_1b1b_c0e5_b211);
+        let before = owners(&mut balancer);
+        assert_eq!(owners(&mut balancer), before);
+        for name in ["a", "b", "c"] {
+            assert!(before.contains(&name.to_string()));
+        }
+
+        // only the keys of c move
+        balancer.remove(&memory("c"));
+        let after = owners(&mut balancer);
+        for (before, after) in before.iter().zip(&after) {
+            assert!(before == after || before == "c");
+        }
+        assert!(!after.contains(&"c".to_string()));
+
+        // nor while an endpoint is ejected
+        balancer.insert(memory("c"));
+        assert_eq!(owners(&mut balancer), before);
+        let key = before.iter().position(|name| name == "a").unwrap() as u64;
+        fail(balancer.pick(Some(key)).unwrap(), transport_error());
+        for (key, owner) in owners(&mut balancer).iter().enumerate() {
+            assert!(*owner == before[key] || before[key] == "a");
+            assert_ne!(owner, "a");
+        }
+
+        // calls without a key go round robin
+        assert_eq!(picks(&mut balancer, 2), ["b", "c"]);
+    }
+
+    /// Replies to every call with `name`.
+    async fn serve(name: &'static str) {
+        let service = service_fn(
+            move |(cx, _): (MsgContext, ApplicationResult<String>)| async move {
+                Ok::<_, crate::Error>(Some((cx, Ok(name.to_string()))))
+            },
+        );
+        let server = Server::<_, MemoryAddr, String, String>::new(service);
+        serve_in_memory(server, name).await;
+    }
+
+    #[tokio::test]
+    async fn test_client_balances_healthy_endpoints() {
+        serve("balance-a").await;
+        serve("balance-b").await;
+        let endpoints = || {
+            let names = ["balance-a", "balance-down", "balance-b"];
+            StaticList::new(names.iter().map(|name| memory(name)))
+        };
+
+        let mut client: Client<String, String> = ClientBuilder::discover(endpoints()).build();
+        assert!(client.call("name", String::new()).await.is_ok());
+        assert!(client.call("name", String::new()).await.is_err());
+        for _ in 0..4 {
+            assert!(client.call("name", String::new()).await.is_ok());
+        }
+
+        // retries go to another endpoint
+        let mut client: Client<String, String> = ClientBuilder::discover(endpoints())
+            .build()
+            .with_retry(RetryPolicy::new().backoff(Duration::ZERO, Duration::ZERO));
+        for _ in 0..6 {
+            assert!(client.call("name", String::new()).await.is_ok());
+        }
+
+        let mut client: Client<String, String> = ClientBuilder::discover(endpoints())
+            .balance(Balance::ConsistentHash)
+            .build()
+            .with_retry(RetryPolicy::new().backoff(Duration::ZERO, Duration::ZERO));
+        for key in 0..10 {
+            let options = CallOptions {
+                hash_key: Some(key),
+                ..CallOptions::default()
+            };
+            let owner = client.call_with_options("name", String::new(), options);
+            let owner = owner.await.unwrap();
+            for _ in 0..3 {
+                let name = client.call_with_options("name", String::new(), options);
+                assert_eq!(name.await.unwrap(), owner);
+            }
+        }
+    }
+}
@@ -1,12 +1,18 @@
 use std::convert::TryFrom;
+use std::sync::Arc;
 
 use byteorder::{BigEndian, ByteOrder};
+use bytes::{Bytes, BytesMut};
 
+use crate::limits::DecodeLimits;
 use crate::protocol::{
-    field_type_from_u8, TFieldIdentifier, TInputProtocol, TListIdentifier, TMapIdentifier,
-    TMessageIdentifier, TMessageType, TOutputProtocol, TSetIdentifier, TStructIdentifier, TType,
+    field_type_from_u8, TFieldIdentifier, TInputProtocol, TLengthProtocol, TListIdentifier,
+    TMapIdentifier, TMessageIdentifier, TMessageType, TOutputProtocol, TProtocolFactory,
+    TSetIdentifier, TStructIdentifier, TType,
+};
+use crate::{
+    new_protocol_error, new_transport_error, ProtocolError, ProtocolErrorKind, TransportErrorKind,
 };
-use crate::{new_protocol_error, ProtocolError, ProtocolErrorKind};
 
 const BINARY_PROTOCOL_VERSION_1: u32 = 0x8001_0000;
 
@@ -18,7 +24,6 @@ pub struct TBinaryOutputProtocol<T> {
 }
 
 impl<T> TBinaryOutputProtocol<T> {
-    #[allow(dead_code)]
     pub fn new(buf: T, strict: bool) -> TBinaryOutputProtocol<T> {
         TBinaryOutputProtocol { buf, strict }
     }
@@ -141,6 +146,12 @@ where
         Ok(())
     }
 
+    #[inline]
+    fn write_uuid(&mut self, uuid: [u8; 16]) -> crate::Result<()> {
+        self.buf.put_slice(&uuid);
+        Ok(())
+    }
+
     #[inline]
     fn write_list_begin(&mut self, identifier: &TListIdentifier) -> crate::Result<()> {
         self.write_byte(identifier.element_type.into())?;
@@ -196,18 +207,179 @@ where
     }
 }
 
+/// Computes the lengths written by `TBinaryOutputProtocol`.
+#[derive(Debug)]
+pub struct TBinaryLengthProtocol {
+    strict: bool,
+}
+
+impl TBinaryLengthProtocol {
+    pub fn new(strict: bool) -> TBinaryLengthProtocol {
+        TBinaryLengthProtocol { strict }
+    }
+}
+
+impl TLengthProtocol for TBinaryLengthProtocol {
+    #[inline]
+    fn message_begin_len(&mut self, identifier: &TMessageIdentifier) -> usize {
+        if self.strict {
+            4 + self.string_len(&identifier.name) + 4
+        } else {
+            self.string_len(&identifier.name) + 1 + 4
+        }
+    }
+
+    #[inline]
+    fn message_end_len(&mut self) -> usize {
+        0
+    }
+
+    #[inline]
+    fn struct_begin_len(&mut self, _: &TStructIdentifier) -> usize {
+        0
+    }
+
+    #[inline]
+    fn struct_end_len(&mut self) -> usize {
+        0
+    }
+
+    #[inline]
+    fn field_begin_len(&mut self, identifier: &TFieldIdentifier) -> usize {
+        match identifier.id {
+            Some(_) => 1 + 2,
+            None => 1,
+        }
+    }
+
+    #[inline]
+    fn field_end_len(&mut self) -> usize {
+        0
+    }
+
+    #[inline]
+    fn field_stop_len(&mut self) -> usize {
+        1
+    }
+
+    #[inline]
+    fn bool_len(&mut self, _: bool) -> usize {
+        1
+    }
+
+    #[inline]
+    fn bytes_len(&mut self, b: &[u8]) -> usize {
+        4 + b.len()
+    }
+
+    #[inline]
+    fn i8_len(&mut self, _: i8) -> usize {
+        1
+    }
+
+    #[inline]
+    fn i16_len(&mut self, _: i16) -> usize {
+        2
+    }
+
+    #[inline]
+    fn i32_len(&mut self, _: i32) -> usize {
+        4
+    }
+
+    #[inline]
+    fn i64_len(&mut self, _: i64) -> usize {
+        8
+    }
+
+    #[inline]
+    fn double_len(&mut self, _: f64) -> usize {
+        8
+    }
+
+    #[inline]
+    fn string_len(&mut self, s: &str) -> usize {
+        4 + s.len()
+    }
+
+    #[inline]
+    fn uuid_len(&mut self, _: [u8; 16]) -> usize {
+        16
+    }
+
+    #[inline]
+    fn list_begin_len(&mut self, _: &TListIdentifier) -> usize {
+        1 + 4
+    }
+
+    #[inline]
+    fn list_end_len(&mut self) -> usize {
+        0
+    }
+
+    #[inline]
+    fn set_begin_len(&mut self, _: &TSetIdentifier) -> usize {
+        1 + 4
+    }
+
+    #[inline]
+    fn set_end_len(&mut self) -> usize {
+        0
+    }
+
+    #[inline]
+    fn map_begin_len(&mut self, _: &TMapIdentifier) -> usize {
+        1 + 1 + 4
+    }
+
+    #[inline]
+    fn map_end_len(&mut self) -> usize {
+        0
+    }
+
+    #[inline]
+    fn byte_len(&mut self, _: u8) -> usize {
+        1
+    }
+}
+
 // Mini-lust note: warp a BufMut to reuse buffer and do buffered packet parsing.
 #[derive(Debug)]
 pub struct TBinaryInputProtocol<T> {
     buf: T,
     strict: bool,
+    limits: DecodeLimits,
 }
 
 // Mini-lust note: Mainly copied from official thrift code
 impl<T> TBinaryInputProtocol<T> {
-    #[allow(unused)]
     pub fn new(buf: T, strict: bool) -> TBinaryInputProtocol<T> {
-        TBinaryInputProtocol { buf, strict }
+        TBinaryInputProtocol {
+            buf,
+            strict,
+            limits: DecodeLimits::default(),
+        }
+    }
+}
+
+impl<T> TBinaryInputProtocol<T>
+where
+    T: bytes::Buf,
+{
+    /// Read the length prefix of a string or binary, checking it against the
+    /// limits and the remaining data.
+    #[inline]
+    fn read_length(&mut self) -> crate::Result<usize> {
+        let len = self.read_i32()?;
+        if len < 0 {
+            return Err(new_protocol_error(
+                ProtocolErrorKind::NegativeSize,
+                format!("negative string length {}", len),
+            ));
+        }
+        self.limits.check_string_length(len as usize)?;
+        protocol_len_check(&self.buf, len as usize)?;
+        Ok(len as usize)
     }
 }
 
@@ -218,6 +390,7 @@ where
     #[allow(clippy::collapsible_if)]
     #[inline]
     fn read_message_begin(&mut self) -> crate::Result<TMessageIdentifier> {
+        protocol_len_check(&self.buf, 4)?;
         let first_bytes = self.buf.copy_to_bytes(4);
 
         // the thrift version header is intentionally negative
@@ -252,6 +425,8 @@ where
                 // is the message name. strings (byte arrays) are length-prefixed,
                 // so we've just read the length in the first 4 bytes
                 let name_size = BigEndian::read_i32(&first_bytes) as usize;
+                self.limits.check_string_length(name_size)?;
+                protocol_len_check(&self.buf, name_size)?;
                 let mut name_buf: Vec<u8> = vec![0; name_size];
                 self.buf.copy_to_slice(&mut name_buf);
                 let name = String::from_utf8(name_buf)?;
@@ -308,14 +483,18 @@ where
 
     #[inline]
     fn read_bytes(&mut self) -> crate::Result<Vec<u8>> {
-        protocol_len_check(&self.buf, 4)?;
-        let num_bytes = self.buf.get_i32() as usize;
+        let num_bytes = self.read_length()?;
         let mut output = vec![0; num_bytes];
-        protocol_len_check(&self.buf, num_bytes)?;
         self.buf.copy_to_slice(&mut output);
         Ok(output)
     }
 
+    #[inline]
+    fn read_binary(&mut self) -> crate::Result<Bytes> {
+        let num_bytes = self.read_length()?;
+        Ok(self.buf.copy_to_bytes(num_bytes))
+    }
+
     #[inline]
     fn read_i8(&mut self) -> crate::Result<i8> {
         protocol_len_check(&self.buf, 1)?;
@@ -352,10 +531,20 @@ where
         String::from_utf8(bytes).map_err(From::from)
     }
 
+    #[inline]
+    fn read_uuid(&mut self) -> crate::Result<[u8; 16]> {
+        protocol_len_check(&self.buf, 16)?;
+        let mut uuid = [0; 16];
+        self.buf.copy_to_slice(&mut uuid);
+        Ok(uuid)
+    }
+
     #[inline]
     fn read_list_begin(&mut self) -> crate::Result<TListIdentifier> {
         let element_type: TType = self.read_byte().and_then(field_type_from_u8)?;
         let size = self.read_i32()?;
+        self.limits
+            .check_container_bytes(size, self.buf.remaining())?;
         Ok(TListIdentifier::new(element_type, size))
     }
 
@@ -368,6 +557,8 @@ where
     fn read_set_begin(&mut self) -> crate::Result<TSetIdentifier> {
         let element_type: TType = self.read_byte().and_then(field_type_from_u8)?;
         let size = self.read_i32()?;
+        self.limits
+            .check_container_bytes(size, self.buf.remaining())?;
         Ok(TSetIdentifier::new(element_type, size))
     }
 
@@ -381,6 +572,8 @@ where
         let key_type: TType = self.read_byte().and_then(field_type_from_u8)?;
         let value_type: TType = self.read_byte().and_then(field_type_from_u8)?;
         let size = self.read_i32()?;
+        self.limits
+            .check_container_bytes(size, self.buf.remaining())?;
         Ok(TMapIdentifier::new(key_type, value_type, size))
     }
 
@@ -397,10 +590,81 @@ where
         protocol_len_check(&self.buf, 1)?;
         Ok(self.buf.get_u8())
     }
+
+    fn set_decode_limits(&mut self, limits: DecodeLimits) {
+        self.limits = limits;
+    }
+}
+
+/// Factory for binary protocol instances.
+#[derive(Debug, Clone, Copy)]
+pub struct TBinaryProtocolFactory {
+    strict_read: bool,
+    strict_write: bool,
+}
+
+impl TBinaryProtocolFactory {
+    /// Create a factory whose protocols read and write with the same strictness.
+    ///
+    /// A strict protocol writes the protocol-version header and refuses to read
+    /// messages without one.
+    pub fn new(strict: bool) -> TBinaryProtocolFactory {
+        Self::with_strictness(strict, strict)
+    }
+
+    /// Create a factory with separate read and write strictness.
+    ///
+    /// A non-strict reader accepts messages with or without the header, so
+    /// `(false, true)` is the most tolerant setting.
+    pub fn with_strictness(strict_read: bool, strict_write: bool) -> TBinaryProtocolFactory {
+        TBinaryProtocolFactory {
+            strict_read,
+            strict_write,
+        }
+    }
+}
+
+impl Default for TBinaryProtocolFactory {
+    fn default() -> Self {
+        Self::new(true)
+    }
+}
+
+impl TProtocolFactory for TBinaryProtocolFactory {
+    fn input_protocol<'a>(&self, buf: &'a mut BytesMut) -> Box<dyn TInputProtocol + 'a> {
+        Box::new(TBinaryInputProtocol::new(buf, self.strict_read))
+    }
+
+    fn slice_input_protocol<'a>(&self, buf: &'a mut &[u8]) -> Box<dyn TInputProtocol + 'a> {
+        Box::new(TBinaryInputProtocol::new(buf, self.strict_read))
+    }
+
+    fn output_protocol<'a>(&self, buf: &'a mut BytesMut) -> Box<dyn TOutputProtocol + 'a> {
+        Box::new(TBinaryOutputProtocol::new(buf, self.strict_write))
+    }
+
+    fn length_protocol(&self) -> Box<dyn TLengthProtocol> {
+        Box::new(TBinaryLengthProtocol::new(self.strict_write))
+    }
+
+    fn header_protocol_id(&self) -> Option<i32> {
+        Some(crate::header::HEADER_PROTOCOL_BINARY)
+    }
+
+    fn strictness(
+        &self,
+        strict_read: Option<bool>,
+        strict_write: Option<bool>,
+    ) -> Option<Arc<dyn TProtocolFactory>> {
+        Some(Arc::new(TBinaryProtocolFactory::with_strictness(
+            strict_read.unwrap_or(self.strict_read),
+            strict_write.unwrap_or(self.strict_write),
+        )))
+    }
 }
 
 #[inline]
-fn protocol_len_check<T>(buf: &T, required_len: usize) -> crate::Result<()>
+pub(crate) fn protocol_len_check<T>(buf: &T, required_len: usize) -> crate::Result<()>
 where
     T: bytes::Buf,
 {
@@ -412,8 +676,34 @@ where
     if std::intrinsics::likely(buf.remaining() >= required_len) {
         return Ok(());
     }
-    Err(new_protocol_error(
-        ProtocolErrorKind::InvalidData,
+    // Running out of data is reported like the official library does, so that
+    // unframed codecs can tell it apart from invalid data and wait for more.
+    Err(new_transport_error(
+        TransportErrorKind::EndOfFile,
         "unexpected data length",
     ))
 }
+
+#[cfg(test)]
+mod tests {
+    use bytes::BytesMut;
+
+    use crate::binary::{TBinaryInputProtocol, TBinaryOutputProtocol};
+    use crate::protocol::{TInputProtocol, TOutputProtocol};
+
+    #[test]
+    fn test_read_binary_zero_copy() {
+        let mut buf = BytesMut::new();
+        let mut protocol = TBinaryOutputProtocol::new(&mut buf, true);
+        protocol.write_bytes(b"blob").unwrap();
+        protocol.write_i32(7).unwrap();
+        let payload = buf[4..].as_ptr();
+
+        let mut protocol = TBinaryInputProtocol::new(&mut buf, true);
+        let binary = protocol.read_binary().unwrap();
+        assert_eq!(&binary[..], b"blob");
+        // the payload is a slice of the input buffer
+        assert_eq!(binary.as_ptr(), payload);
+        assert_eq!(protocol.read_i32().unwrap(), 7);
+    }
+}
@@ -1,39 +1,221 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+use std::sync::Arc;
 use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 
 use futures::sink::SinkExt;
 use futures::stream::TryStreamExt;
 use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::codec::Framed;
 use tower::buffer::Buffer;
+use tower::discover::Discover;
+use tower::retry::Retry;
 use tower::util::BoxService;
 use tower::{Service, ServiceExt};
 
-use crate::codec::MakeCodec;
+use crate::balance::{Balance, Balanced, Balancer, Targets, DEFAULT_EJECTION_TIME};
+use crate::codec::{CodecOptions, Framing, MakeCodec};
 use crate::connection::SocketOrUnix;
 use crate::context::MsgContext;
-use crate::protocol::{TMessageIdentifier, TMessageType};
+use crate::discover::{Discovery, Endpoints};
+use crate::multiplex::MULTIPLEXED_SEPARATOR;
+use crate::pipeline::PipelinedClient;
+use crate::pool::{Close, Pool, PoolConfig};
+use crate::protocol::{
+    verify_expected_message_type, verify_expected_sequence_number, verify_expected_service_call,
+    TMessageIdentifier, TMessageType, TProtocolFactory,
+};
+use crate::resolve::{DnsResolver, Resolve, DEFAULT_RE_RESOLVE_INTERVAL};
+use crate::retry::{strip_unsent, unsent, Attempt, RetryPolicy};
+use crate::tls::{ClientTlsConfig, TlsMakeConnection};
 use crate::utils::BoxFuture;
-use crate::{ApplicationResult, DefaultMakeCodec, DefaultMakeConnection, FramedMakeTransport};
+use crate::{
+    new_transport_error, ApplicationResult, DecodeLimits, DefaultMakeCodec, DefaultMakeConnection,
+    FramedMakeTransport, TransportErrorKind,
+};
+
+/// Where the calls of a client go, until it is built.
+enum Target {
+    Endpoint(SocketOrUnix),
+    Discovery(Discovery),
+}
 
 pub struct ClientBuilder<MCC> {
-    target: SocketOrUnix,
+    target: Target,
     make_codec: MCC,
+    service_name: Option<String>,
+    tls: Option<ClientTlsConfig>,
+    resolver: Arc<dyn Resolve>,
+    re_resolve_interval: Duration,
+    pool_config: PoolConfig,
+    max_in_flight: Option<usize>,
+    connect_timeout: Option<Duration>,
+    timeout: Option<Duration>,
+    balance: Balance,
+    ejection_time: Duration,
 }
 
 impl<E, D> ClientBuilder<DefaultMakeCodec<E, D>> {
     pub fn new(target: SocketOrUnix) -> Self {
+        Self::with_target(Target::Endpoint(target))
+    }
+
+    /// Balance the calls over the endpoints found by `discover`, such as a
+    /// `StaticList` or an `EndpointsFile`.
+    ///
+    /// Calls wait for the endpoints first discovered, and go to any of them
+    /// while there are no healthy ones.
+    pub fn discover<S>(discover: S) -> Self
+    where
+        S: Discover<Service = SocketOrUnix> + Send + 'static,
+        S::Key: Hash + Send,
+        S::Error: fmt::Display,
+    {
+        Self::with_target(Target::Discovery(Discovery::new(discover)))
+    }
+
+    fn with_target(target: Target) -> Self {
         Self {
             target,
             make_codec: DefaultMakeCodec::new(),
+            service_name: None,
+            tls: None,
+            resolver: Arc::new(DnsResolver),
+            re_resolve_interval: DEFAULT_RE_RESOLVE_INTERVAL,
+            pool_config: PoolConfig::default(),
+            max_in_flight: None,
+            connect_timeout: None,
+            timeout: None,
+            balance: Balance::default(),
+            ejection_time: DEFAULT_EJECTION_TIME,
+        }
+    }
+
+    /// Speak the given protocol instead of the default strict binary protocol.
+    pub fn protocol<P: TProtocolFactory + 'static>(self, protocol: P) -> Self {
+        Self {
+            make_codec: self.make_codec.protocol(protocol),
+            ..self
+        }
+    }
+
+    /// Delimit messages with the given framing instead of the default framed transport.
+    pub fn framing(self, framing: Framing) -> Self {
+        Self {
+            make_codec: self.make_codec.framing(framing),
+            ..self
+        }
+    }
+
+    /// Bound what the server may make the client decode.
+    pub fn decode_limits(self, limits: DecodeLimits) -> Self {
+        Self {
+            make_codec: self.make_codec.decode_limits(limits),
+            ..self
+        }
+    }
+
+    /// Frame size, binary strictness, buffer sizing and compression of the
+    /// connection.
+    pub fn codec_options(self, options: CodecOptions) -> Self {
+        Self {
+            make_codec: self.make_codec.options(options),
+            ..self
+        }
+    }
+
+    /// Post calls to `path` over HTTP/1.1, as `THttpClient` does. The `Host`
+    /// header is the target address, `localhost` with discovered endpoints.
+    pub fn http(self, path: impl Into<String>) -> Self {
+        let host = match &self.target {
+            Target::Endpoint(SocketOrUnix::Socket(addr)) => addr.to_string(),
+            Target::Endpoint(SocketOrUnix::Host(host, port)) => format!("{}:{}", host, port),
+            _ => "localhost".to_string(),
+        };
+        Self {
+            make_codec: self
+                .make_codec
+                .framing(Framing::Http)
+                .http_path(path)
+                .http_host(host),
+            ..self
         }
     }
 }
 
 impl<MCC> ClientBuilder<MCC> {
     pub fn make_codec(self, make_codec: MCC) -> Self {
-        Self {
-            target: self.target,
-            make_codec,
-        }
+        Self { make_codec, ..self }
+    }
+
+    /// Prefix method names with `"service_name:"`, to call a service served
+    /// by a multiplexed server.
+    pub fn service_name(mut self, service_name: impl Into<String>) -> Self {
+        self.service_name = Some(service_name.into());
+        self
+    }
+
+    /// Connect over TLS.
+    pub fn tls(mut self, config: ClientTlsConfig) -> Self {
+        self.tls = Some(config);
+        self
+    }
+
+    /// Resolve `SocketOrUnix::Host` targets with `resolver` instead of the
+    /// resolver of the system.
+    pub fn resolver<R: Resolve>(mut self, resolver: R) -> Self {
+        self.resolver = Arc::new(resolver);
+        self
+    }
+
+    /// Resolve `SocketOrUnix::Host` targets again once their addresses are
+    /// older than `interval`, 30 seconds by default.
+    pub fn re_resolve_interval(mut self, interval: Duration) -> Self {
+        self.re_resolve_interval = interval;
+        self
+    }
+
+    /// How many connections are kept to the target, and for how long.
+    pub fn pool_config(mut self, config: PoolConfig) -> Self {
+        self.pool_config = config;
+        self
+    }
+
+    /// Share one connection per target between concurrent calls, up to
+    /// `max_in_flight` of them, replies being matched by sequence number.
+    /// Oneway calls still use the pool.
+    pub fn pipelined(mut self, max_in_flight: usize) -> Self {
+        self.max_in_flight = Some(max_in_flight);
+        self
+    }
+
+    /// Give up connecting after `timeout`.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Fail calls not done after `timeout`, connecting and waiting for a
+    /// connection included. `CallOptions` may override it.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Pick the endpoint of each call with `balance`, round robin by default.
+    /// Only used with discovered endpoints.
+    pub fn balance(mut self, balance: Balance) -> Self {
+        self.balance = balance;
+        self
+    }
+
+    /// Take no calls to a discovered endpoint for `ejection_time` after one
+    /// of its calls fails with a transport error, 5 seconds by default.
+    pub fn ejection_time(mut self, ejection_time: Duration) -> Self {
+        self.ejection_time = ejection_time;
+        self
     }
 }
 
@@ -52,79 +234,356 @@ where
     MCC::Codec: Send + 'static,
 {
     pub fn build(self) -> Client<Req, Resp> {
-        let make_connection = DefaultMakeConnection;
         let make_codec = self.make_codec;
-        let transport_client = TransportClient::new(make_connection, make_codec);
-        let inner = Buffer::new(BoxService::new(transport_client), DEFAULT_BUFFER);
+        let make_connection =
+            DefaultMakeConnection::with_resolver(self.resolver, self.re_resolve_interval);
+        let (transport_client, pool) = match &self.tls {
+            Some(config) => {
+                let make_connection = TlsMakeConnection::new(make_connection, config);
+                let client = TransportClient::new(make_connection, make_codec, self.pool_config)
+                    .connect_timeout(self.connect_timeout);
+                boxed(client, self.max_in_flight)
+            }
+            None => {
+                let client = TransportClient::new(make_connection, make_codec, self.pool_config)
+                    .connect_timeout(self.connect_timeout);
+                boxed(client, self.max_in_flight)
+            }
+        };
+        let inner = Buffer::new(transport_client, DEFAULT_BUFFER);
+        let target = match self.target {
+            Target::Endpoint(target) => Targets::Endpoint(target),
+            Target::Discovery(discovery) => {
+                let balancer = Balancer::new(self.balance, self.ejection_time);
+                Targets::Discovered(Endpoints::start(discovery, balancer, pool.clone()))
+            }
+        };
         Client {
             inner,
-            target: self.target,
+            pool,
+            target,
+            service_name: self.service_name,
+            timeout: self.timeout,
+            retry: None,
+        }
+    }
+}
+
+type BoxTransportClient<Req, Resp> = BoxService<
+    (MsgContext, ApplicationResult<Req>),
+    Option<(MsgContext, ApplicationResult<Resp>)>,
+    crate::Error,
+>;
+
+/// Box `client`, pipelined if `max_in_flight` is set, along with what closes
+/// its connections.
+fn boxed<MCN, MCC, Req, Resp>(
+    client: TransportClient<MCN, MCC>,
+    max_in_flight: Option<usize>,
+) -> (BoxTransportClient<Req, Resp>, Arc<dyn Close>)
+where
+    MCN: Service<SocketOrUnix> + Clone + Send + 'static,
+    MCN::Response: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    MCN::Error: std::error::Error + Send + Sync + 'static,
+    MCN::Future: Send,
+    Req: Send + 'static,
+    Resp: Send + 'static,
+    MCC: MakeCodec<
+            EncodeItem = (MsgContext, ApplicationResult<Req>),
+            DecodeItem = (MsgContext, ApplicationResult<Resp>),
+            Error = crate::Error,
+        > + Send
+        + 'static,
+    MCC::Codec: Send + 'static,
+    crate::Error: From<<MCN as tower::Service<SocketOrUnix>>::Error>,
+{
+    match max_in_flight {
+        Some(max_in_flight) => {
+            let client = PipelinedClient::new(client, max_in_flight);
+            let connections = client.connections();
+            (BoxService::new(client), connections)
+        }
+        None => {
+            let pool = client.pool();
+            (BoxService::new(client), pool)
+        }
+    }
+}
+
+/// Bounds of a single call.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CallOptions {
+    /// Fail the call if not done after this long, instead of after the
+    /// timeout of the client.
+    pub timeout: Option<Duration>,
+    /// Fail the call if not done by then, whatever the timeouts.
+    pub deadline: Option<Instant>,
+    /// Key of the call for `Balance::ConsistentHash`.
+    pub hash_key: Option<u64>,
+}
+
+impl CallOptions {
+    /// When the call fails, if ever, with a client timing out after
+    /// `client_timeout`.
+    fn deadline(&self, client_timeout: Option<Duration>) -> Option<Instant> {
+        let timeout = self
+            .timeout
+            .or(client_timeout)
+            .map(|timeout| Instant::now() + timeout);
+        match (timeout, self.deadline) {
+            (Some(timeout), Some(deadline)) => Some(timeout.min(deadline)),
+            (timeout, deadline) => timeout.or(deadline),
         }
     }
 }
 
 #[derive(Clone)]
 pub struct Client<Req, Resp> {
-    inner: Buffer<
-        BoxService<
-            (MsgContext, ApplicationResult<Req>),
-            Option<(MsgContext, ApplicationResult<Resp>)>,
-            crate::Error,
-        >,
-        (MsgContext, ApplicationResult<Req>),
-    >,
-    target: SocketOrUnix,
+    inner: Buffer<BoxTransportClient<Req, Resp>, (MsgContext, ApplicationResult<Req>)>,
+    pool: Arc<dyn Close>,
+    target: Targets,
+    service_name: Option<String>,
+    timeout: Option<Duration>,
+    retry: Option<Attempt<Req>>,
+}
+
+impl<Req: Clone, Resp> Client<Req, Resp> {
+    /// Retry failed calls as `policy` allows.
+    pub fn with_retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Some(Attempt::new(policy, Req::clone));
+        self
+    }
 }
 
-impl<Req, Resp> Client<Req, Resp> {
+impl<Req, Resp> Client<Req, Resp>
+where
+    Req: Send + 'static,
+    Resp: Send + 'static,
+{
+    fn method_name(&self, method: &str) -> String {
+        match &self.service_name {
+            Some(service_name) => {
+                format!("{}{}{}", service_name, MULTIPLEXED_SEPARATOR, method)
+            }
+            None => method.to_string(),
+        }
+    }
+
+    /// Send `(cx, req)` to the target picked for it, failing with
+    /// `TransportErrorKind::TimedOut` once the deadline of `options` has
+    /// passed.
+    async fn send(
+        &mut self,
+        cx: MsgContext,
+        req: Req,
+        options: CallOptions,
+    ) -> crate::Result<Option<(MsgContext, ApplicationResult<Resp>)>> {
+        let deadline = options.deadline(self.timeout);
+        // Retries pick their target again.
+        let balanced = Balanced::new(self.inner.clone(), self.target.clone());
+        let retry = self.retry.clone();
+        let send = async move {
+            let resp = match retry {
+                Some(policy) => Retry::new(policy, balanced).oneshot((cx, Ok(req))).await,
+                None => balanced.oneshot((cx, Ok(req))).await,
+            };
+            resp.map_err(|e| strip_unsent(e.into()))
+        };
+        match deadline {
+            Some(deadline) => tokio::time::timeout_at(deadline.into(), send)
+                .await
+                .map_err(|_| new_transport_error(TransportErrorKind::TimedOut, "call timed out"))?,
+            None => send.await,
+        }
+    }
+
     /// Call with method and Req and returns Result<Resp>
     pub async fn call(&mut self, method: &'static str, req: Req) -> crate::Result<Resp> {
+        self.call_with_options(method, req, CallOptions::default())
+            .await
+    }
+
+    /// Call with method and Req within the bounds of `options`.
+    pub async fn call_with_options(
+        &mut self,
+        method: &'static str,
+        req: Req,
+        options: CallOptions,
+    ) -> crate::Result<Resp> {
+        self.call_with_headers_and_options(method, req, HashMap::new(), options)
+            .await
+            .map(|(resp, _)| resp)
+    }
+
+    /// Call with method, Req and request headers, returns Resp and the
+    /// response headers.
+    ///
+    /// Headers are only carried on the wire by `Framing::Header`.
+    pub async fn call_with_headers(
+        &mut self,
+        method: &'static str,
+        req: Req,
+        headers: HashMap<String, String>,
+    ) -> crate::Result<(Resp, HashMap<String, String>)> {
+        self.call_with_headers_and_options(method, req, headers, CallOptions::default())
+            .await
+    }
+
+    /// `call_with_headers` within the bounds of `options`.
+    pub async fn call_with_headers_and_options(
+        &mut self,
+        method: &'static str,
+        req: Req,
+        headers: HashMap<String, String>,
+        options: CallOptions,
+    ) -> crate::Result<(Resp, HashMap<String, String>)> {
         let context = MsgContext {
             identifier: TMessageIdentifier {
-                name: method.to_string(),
+                name: self.method_name(method),
                 message_type: TMessageType::Call,
 
                 ..TMessageIdentifier::default()
             },
-            target: Some(self.target.clone()),
+            headers,
+            hash_key: options.hash_key,
+            ..MsgContext::default()
         };
-        let req = (context, Ok(req));
         // Option<(MsgContext, ApplicationResult<Resp>)>
-        let resp = self.inner.ready().await?.call(req).await?;
-        resp.expect("returning resp is expected")
-            .1
-            .map_err(Into::into)
+        let resp = self.send(context, req, options).await?;
+        let (cx, resp) = resp.ok_or_else(closed_before_reply)?;
+        resp.map(|resp| (resp, cx.headers)).map_err(Into::into)
     }
 
     pub async fn oneway(&mut self, method: &'static str, req: Req) -> crate::Result<()> {
         let context = MsgContext {
             identifier: TMessageIdentifier {
-                name: method.to_string(),
+                name: self.method_name(method),
                 message_type: TMessageType::OneWay,
 
                 ..TMessageIdentifier::default()
             },
-            target: Some(self.target.clone()),
+            ..MsgContext::default()
         };
-        let req = (context, Ok(req));
-        self.inner.ready().await?.call(req).await?;
+        self.send(context, req, CallOptions::default()).await?;
         Ok(())
     }
+
+    /// Close the pooled connections: idle ones now, the others once their
+    /// call is done. Later calls, from this client or its clones, fail.
+    pub fn close(&self) {
+        if let Targets::Discovered(endpoints) = &self.target {
+            endpoints.stop();
+        }
+        self.pool.close();
+    }
+}
+
+pub(crate) fn closed_before_reply() -> crate::Error {
+    new_transport_error(
+        TransportErrorKind::EndOfFile,
+        "connection closed before the reply",
+    )
+}
+
+/// Check that `reply` answers `call`.
+pub(crate) fn verify_reply(
+    call: &TMessageIdentifier,
+    reply: &TMessageIdentifier,
+) -> crate::Result<()> {
+    verify_expected_sequence_number(call.sequence_number, reply.sequence_number)?;
+    // Multiplexed servers reply with the bare method name.
+    let method = match call.name.split_once(MULTIPLEXED_SEPARATOR) {
+        Some((_, method)) if reply.name == method => method,
+        _ => &call.name,
+    };
+    verify_expected_service_call(method, &reply.name)?;
+    if reply.message_type != TMessageType::Exception {
+        verify_expected_message_type(TMessageType::Reply, reply.message_type)?;
+    }
+    Ok(())
 }
 
-pub(crate) struct TransportClient<MCN, MCC> {
+pub(crate) type Transport<MCN, MCC> =
+    Framed<<MCN as Service<SocketOrUnix>>::Response, <MCC as MakeCodec>::Codec>;
+
+pub(crate) struct TransportClient<MCN, MCC>
+where
+    MCN: Service<SocketOrUnix>,
+    MCC: MakeCodec,
+{
     make_transport: FramedMakeTransport<MCC, MCN>,
+    pool: Arc<Pool<Transport<MCN, MCC>>>,
+    connect_timeout: Option<Duration>,
     seq_id: i32,
 }
 
-impl<MCN, MCC> TransportClient<MCN, MCC> {
+impl<MCN, MCC> TransportClient<MCN, MCC>
+where
+    MCN: Service<SocketOrUnix>,
+    MCC: MakeCodec,
+{
     #[allow(unused)]
-    pub fn new(make_connection: MCN, make_codec: MCC) -> Self {
+    pub fn new(make_connection: MCN, make_codec: MCC, pool_config: PoolConfig) -> Self {
         Self {
             make_transport: FramedMakeTransport::new(make_connection, make_codec),
+            pool: Arc::new(Pool::new(pool_config)),
+            connect_timeout: None,
             seq_id: 1,
         }
     }
+
+    pub(crate) fn pool(&self) -> Arc<dyn Close>
+    where
+        Transport<MCN, MCC>: Send + 'static,
+    {
+        self.pool.clone()
+    }
+
+    pub(crate) fn connect_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+}
+
+impl<MCN, MCC> TransportClient<MCN, MCC>
+where
+    MCN: Service<SocketOrUnix> + Send + 'static,
+    MCN::Response: AsyncRead + AsyncWrite + Unpin + Send,
+    MCN::Error: std::error::Error + Send + Sync + 'static,
+    MCN::Future: Send,
+    MCC: MakeCodec,
+    MCC::Codec: Send + 'static,
+    crate::Error: From<<MCN as tower::Service<SocketOrUnix>>::Error>,
+{
+    /// A new connection to `target`, given up after the connect timeout.
+    /// Errors are marked as the request was not written.
+    ///
+    /// Mini-lust note: connecting is lazy, nothing is dialed until the future
+    /// is polled. Callers make it up front and drop it untouched when an
+    /// existing connection serves the call.
+    pub(crate) fn connect(
+        &mut self,
+        target: SocketOrUnix,
+    ) -> BoxFuture<Transport<MCN, MCC>, crate::Error> {
+        let transport_fut = self.make_transport.call(target);
+        let connect_timeout = self.connect_timeout;
+        Box::pin(async move {
+            let transport = match connect_timeout {
+                Some(timeout) => {
+                    tokio::time::timeout(timeout, transport_fut)
+                        .await
+                        .map_err(|_| {
+                            unsent(new_transport_error(
+                                TransportErrorKind::TimedOut,
+                                "connect timed out",
+                            ))
+                        })?
+                }
+                None => transport_fut.await,
+            };
+            transport.map_err(|e| unsent(e.into()))
+        })
+    }
 }
 
 /// Call with (MsgContext, ApplicationResult<Req>) returns
@@ -156,23 +615,176 @@ where
     }
 
     fn call(&mut self, (mut cx, req): (MsgContext, ApplicationResult<Req>)) -> Self::Future {
-        // TODO: use service discovery
         let target = cx
             .target
             .clone()
             .expect("unable to retrieve target from context");
-        let transport_fut = self.make_transport.call(target);
+        let pool = self.pool.target(&target);
+        let transport_fut = self.connect(target);
 
         self.seq_id += 1;
         cx.identifier.sequence_number = self.seq_id;
         let oneway = cx.identifier.message_type == TMessageType::OneWay;
+        let call = cx.identifier.clone();
         Box::pin(async move {
-            let mut transport = transport_fut.await?;
+            // A connection failing is dropped rather than returned to the pool.
+            let mut transport = pool.checkout::<_, crate::Error>(transport_fut).await?;
             transport.send((cx, req)).await?;
             if oneway {
+                pool.checkin(transport);
                 return Ok(None);
             }
-            transport.try_next().await.map_err(Into::into)
+            let resp = transport
+                .try_next()
+                .await?
+                .ok_or_else(closed_before_reply)?;
+            // A connection whose reply is off may have more of them queued.
+            verify_reply(&call, &resp.0.identifier)?;
+            pool.checkin(transport);
+            Ok(Some(resp))
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::net::SocketAddr;
+    use std::time::{Duration, Instant};
+
+    use futures::{SinkExt, StreamExt, TryStreamExt};
+    use tokio_util::codec::Framed;
+
+    use crate::client::verify_reply;
+    use crate::codec::MakeCodec;
+    use crate::memory::MemoryAddr;
+    use crate::protocol::{TMessageIdentifier, TMessageType};
+    use crate::server::Listenable;
+    use crate::utils::BoxFuture;
+    use crate::{
+        ApplicationErrorKind, CallOptions, Client, ClientBuilder, DefaultMakeCodec, Resolve,
+        SocketOrUnix, TransportErrorKind,
+    };
+
+    fn application_error_kind(result: crate::Result<()>) -> ApplicationErrorKind {
+        match result {
+            Err(crate::Error::Application(e)) => e.kind,
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_verify_reply() {
+        let call = TMessageIdentifier::new("Calculator:add", TMessageType::Call, 3);
+        let reply = |name: &str, message_type, sequence_number| {
+            verify_reply(
+                &call,
+                &TMessageIdentifier::new(name, message_type, sequence_number),
+            )
+        };
+        assert!(reply("Calculator:add", TMessageType::Reply, 3).is_ok());
+        assert!(reply("add", TMessageType::Reply, 3).is_ok());
+        assert!(reply("add", TMessageType::Exception, 3).is_ok());
+        assert_eq!(
+            application_error_kind(reply("add", TMessageType::Reply, 4)),
+            ApplicationErrorKind::BadSequenceId
+        );
+        assert_eq!(
+            application_error_kind(reply("sub", TMessageType::Reply, 3)),
+            ApplicationErrorKind::WrongMethodName
+        );
+        for message_type in [TMessageType::Call, TMessageType::OneWay] {
+            assert_eq!(
+                application_error_kind(reply("add", message_type, 3)),
+                ApplicationErrorKind::InvalidMessageType
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_bad_replies_are_errors() {
+        let mut listener = MemoryAddr::new("bad-replies").bind().await.unwrap();
+        tokio::spawn(async move {
+            let mut codec = DefaultMakeCodec::<String, String>::new();
+            // a reply to another call
+            let mut conn = Framed::new(listener.next().await.unwrap().unwrap(), codec.make_codec());
+            let (mut cx, req) = conn.try_next().await.unwrap().unwrap();
+            cx.identifier.message_type = TMessageType::Reply;
+            cx.identifier.sequence_number += 1;
+            conn.send((cx, req)).await.unwrap();
+            // no reply at all
+            let mut conn = Framed::new(listener.next().await.unwrap().unwrap(), codec.make_codec());
+            let (_, req) = conn.try_next().await.unwrap().unwrap();
+            assert!(req.is_ok());
+        });
+
+        let mut client: Client<String, String> =
+            ClientBuilder::new(SocketOrUnix::Memory("bad-replies".to_string())).build();
+        let err = client.call("echo", "hello".to_string()).await.unwrap_err();
+        assert!(matches!(
+            err,
+            crate::Error::Application(e) if e.kind == ApplicationErrorKind::BadSequenceId
+        ));
+        let err = client.call("echo", "hello".to_string()).await.unwrap_err();
+        assert!(matches!(err, crate::Error::Transport(_)));
+    }
+
+    /// Never resolves host names.
+    struct HangingResolver;
+
+    impl Resolve for HangingResolver {
+        fn resolve(&self, _host: &str, _port: u16) -> BoxFuture<Vec<SocketAddr>, io::Error> {
+            Box::pin(futures::future::pending())
+        }
+    }
+
+    fn timed_out(result: crate::Result<String>) -> bool {
+        matches!(
+            result,
+            Err(crate::Error::Transport(e)) if e.kind == TransportErrorKind::TimedOut
+        )
+    }
+
+    #[tokio::test]
+    async fn test_timeouts() {
+        // the server reads calls but never replies
+        let mut listener = MemoryAddr::new("silent").bind().await.unwrap();
+        tokio::spawn(async move {
+            let mut conns = Vec::new();
+            while let Some(conn) = listener.next().await {
+                conns.push(conn.unwrap());
+            }
+        });
+
+        let mut client: Client<String, String> =
+            ClientBuilder::new(SocketOrUnix::Memory("silent".to_string()))
+                .timeout(Duration::from_millis(20))
+                .build();
+        assert!(timed_out(client.call("echo", "a".to_string()).await));
+
+        // call options override the timeout of the client
+        let long = CallOptions {
+            timeout: Some(Duration::from_secs(60)),
+            ..CallOptions::default()
+        };
+        let call = client.call_with_options("echo", "a".to_string(), long);
+        assert!(tokio::time::timeout(Duration::from_millis(50), call)
+            .await
+            .is_err());
+        let deadline = CallOptions {
+            deadline: Some(Instant::now() + Duration::from_millis(10)),
+            ..long
+        };
+        let result = client
+            .call_with_options("echo", "a".to_string(), deadline)
+            .await;
+        assert!(timed_out(result));
+
+        let mut client: Client<String, String> =
+            ClientBuilder::new(SocketOrUnix::Host("hanging.example".to_string(), 9090))
+                .resolver(HangingResolver)
+                .connect_timeout(Duration::from_millis(20))
+                .build();
+        assert!(timed_out(client.call("echo", "a".to_string()).await));
+    }
+}
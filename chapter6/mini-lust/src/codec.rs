@@ -1,53 +1,111 @@
 use std::marker::PhantomData;
+use std::sync::Arc;
 
 use bytes::{Buf, BufMut, BytesMut};
-use tokio_util::codec::{Decoder, Encoder};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::codec::{Decoder, Encoder, Framed};
 
-use crate::binary::{TBinaryInputProtocol, TBinaryOutputProtocol};
+use crate::binary::TBinaryProtocolFactory;
+use crate::compression::{CompressedCodec, CompressionOptions};
 use crate::context::MsgContext;
+use crate::detect::detect_codec;
+use crate::header::THeaderCodec;
+use crate::http::HttpCodec;
+use crate::limits::{DecodeLimits, TLimitedInputProtocol};
 use crate::message::Message;
-use crate::protocol::{TInputProtocol, TMessageType, TOutputProtocol};
-use crate::{new_protocol_error, ApplicationError, ApplicationResult, ProtocolErrorKind};
+use crate::protocol::{TInputProtocol, TMessageIdentifier, TMessageType, TProtocolFactory};
+use crate::{
+    new_protocol_error, ApplicationError, ApplicationResult, ProtocolErrorKind, TransportErrorKind,
+};
 
-pub struct FramedCodec<C>(C);
+pub struct FramedCodec<C> {
+    inner: C,
+    max_frame_length: usize,
+}
 
 impl<C> FramedCodec<C> {
     #[allow(dead_code)]
     pub fn new(c: C) -> Self {
-        FramedCodec(c)
+        FramedCodec {
+            inner: c,
+            max_frame_length: MAX_MESSAGE_SIZE,
+        }
+    }
+
+    /// Refuse to read or write frames longer than `max_frame_length`.
+    pub fn with_max_frame_length(mut self, max_frame_length: usize) -> Self {
+        self.max_frame_length = max_frame_length;
+        self
     }
 }
 
-// The max message size is limited to 16M.
-const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+// The max message size is limited to 16M by default.
+pub(crate) const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+// Same as the initial buffer capacity of `Framed`.
+pub(crate) const DEFAULT_BUFFER_CAPACITY: usize = 8 * 1024;
+
+const DEFAULT_HTTP_PATH: &str = "/";
+const DEFAULT_HTTP_HOST: &str = "localhost";
+
+/// An encoder telling how many bytes an item encodes to before encoding it,
+/// so that framing codecs can write the length prefix first.
+pub trait SizedEncoder<T>: Encoder<T> {
+    fn encoded_size(&self, item: &T) -> usize;
+}
 
 impl<C, T> Encoder<T> for FramedCodec<C>
 where
-    C: Encoder<T>,
+    C: SizedEncoder<T>,
     crate::Error: From<C::Error>,
 {
     type Error = crate::Error;
 
     fn encode(&mut self, item: T, dst: &mut BytesMut) -> Result<(), Self::Error> {
-        let zero_index = dst.len();
-        // Alloc 4-byte space
-        dst.reserve(4);
-        unsafe {
-            dst.advance_mut(4);
+        let size = self.inner.encoded_size(&item);
+        if size > self.max_frame_length {
+            return Err(new_protocol_error(
+                ProtocolErrorKind::SizeLimit,
+                format!("Frame of length {} is too large.", size),
+            ));
         }
+        dst.reserve(4 + size);
+        let start = dst.len();
+        dst.put_u32(size as u32);
         // Call inner encoder
-        self.0.encode(item, dst).map_err(Into::into)?;
-        let written = dst.len() - 4 - zero_index;
-        if written > MAX_MESSAGE_SIZE {
+        encode_sized(&mut self.inner, item, size, dst, start)
+    }
+}
+
+/// Encode `item` after the head written from `start`, making sure it takes
+/// the `size` computed beforehand. On error `dst` is truncated back to
+/// `start`, so that no head or partial message is flushed with the next one.
+pub(crate) fn encode_sized<C, T>(
+    inner: &mut C,
+    item: T,
+    size: usize,
+    dst: &mut BytesMut,
+    start: usize,
+) -> crate::Result<()>
+where
+    C: Encoder<T>,
+    crate::Error: From<C::Error>,
+{
+    let zero_index = dst.len();
+    let result = inner.encode(item, dst).map_err(Into::into).and_then(|_| {
+        let written = dst.len() - zero_index;
+        if written != size {
             return Err(new_protocol_error(
-                ProtocolErrorKind::SizeLimit,
-                format!("Frame of length {} is too large.", written),
+                ProtocolErrorKind::InvalidData,
+                format!("encoded {} bytes, {} bytes expected", written, size),
             ));
         }
-        let mut buf = &mut dst[zero_index..zero_index + 4];
-        buf.put_u32(written as u32);
         Ok(())
+    });
+    if result.is_err() {
+        dst.truncate(start);
     }
+    result
 }
 
 impl<C> Decoder for FramedCodec<C>
@@ -71,7 +129,7 @@ where
 
         // Check that the length is not too large to avoid a denial of
         // service attack where the server runs out of memory.
-        if length > MAX_MESSAGE_SIZE {
+        if length > self.max_frame_length {
             return Err(new_protocol_error(
                 ProtocolErrorKind::SizeLimit,
                 format!("Frame of length {} is too large.", length),
@@ -92,7 +150,7 @@ where
 
         // Skip the 4-byte length.
         src.advance(4);
-        let decoded = self.0.decode(src)?;
+        let decoded = self.inner.decode(src)?;
         match decoded {
             None => Err(new_protocol_error(
                 ProtocolErrorKind::InvalidData,
@@ -103,17 +161,145 @@ where
     }
 }
 
-#[derive(Debug)]
+/// Codec for messages sent back to back without a length prefix.
+///
+/// Decoding is attempted on whatever has been received so far. Running out
+/// of data (a `TransportErrorKind::EndOfFile` from the protocol) means more
+/// bytes are needed: nothing is consumed and the decode is attempted again
+/// once they arrive. Other errors are returned as is.
+pub struct UnframedCodec<C> {
+    inner: C,
+    max_frame_length: usize,
+}
+
+impl<C> UnframedCodec<C> {
+    pub fn new(c: C) -> Self {
+        UnframedCodec {
+            inner: c,
+            max_frame_length: MAX_MESSAGE_SIZE,
+        }
+    }
+
+    /// Give up on messages longer than `max_frame_length`.
+    pub fn with_max_frame_length(mut self, max_frame_length: usize) -> Self {
+        self.max_frame_length = max_frame_length;
+        self
+    }
+}
+
+impl<C, T> Encoder<T> for UnframedCodec<C>
+where
+    C: SizedEncoder<T>,
+    crate::Error: From<C::Error>,
+{
+    type Error = crate::Error;
+
+    fn encode(&mut self, item: T, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let size = self.inner.encoded_size(&item);
+        if size > self.max_frame_length {
+            return Err(new_protocol_error(
+                ProtocolErrorKind::SizeLimit,
+                format!("Message of length {} is too large.", size),
+            ));
+        }
+        dst.reserve(size);
+        let start = dst.len();
+        encode_sized(&mut self.inner, item, size, dst, start)
+    }
+}
+
+impl<C> Decoder for UnframedCodec<C>
+where
+    C: SliceDecoder,
+    crate::Error: From<C::Error>,
+{
+    type Item = C::Item;
+    type Error = crate::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if src.is_empty() {
+            return Ok(None);
+        }
+
+        // Mini-lust note: the inner decoder cannot resume, so we decode from a
+        // view of the buffer and start over once more data has arrived. Binary
+        // fields are copied out of the view instead of shared with the buffer.
+        let mut view = &src[..];
+        match self.inner.decode_slice(&mut view) {
+            Ok(Some(item)) => {
+                let consumed = src.len() - view.len();
+                src.advance(consumed);
+                Ok(Some(item))
+            }
+            Ok(None) => Ok(None),
+            Err(e) => match crate::Error::from(e) {
+                crate::Error::Transport(e) if e.kind == TransportErrorKind::EndOfFile => {
+                    if src.len() > self.max_frame_length {
+                        return Err(new_protocol_error(
+                            ProtocolErrorKind::SizeLimit,
+                            format!("Message of length {} is too large.", src.len()),
+                        ));
+                    }
+                    Ok(None)
+                }
+                e => Err(e),
+            },
+        }
+    }
+}
+
+/// A decoder that can decode from a borrowed view of the buffer, leaving the
+/// buffer itself untouched.
+pub trait SliceDecoder: Decoder {
+    /// Decode from `src`, advancing it past what is consumed.
+    fn decode_slice(&mut self, src: &mut &[u8]) -> Result<Option<Self::Item>, Self::Error>;
+}
+
+/// Decodes the body of a message whose identifier is already read, in place
+/// of `Message::decode`. It is used when decoding depends on runtime state,
+/// e.g. the services registered in a `MultiplexedServer`.
+pub trait MessageDecoder<D>: Send + Sync {
+    fn decode(
+        &self,
+        cx: &mut MsgContext,
+        protocol: &mut dyn TInputProtocol,
+    ) -> crate::Result<ApplicationResult<D>>;
+}
+
 pub struct MessageCodec<E, D> {
-    strict: bool,
+    protocol: Arc<dyn TProtocolFactory>,
+    decoder: Option<Arc<dyn MessageDecoder<D>>>,
+    limits: DecodeLimits,
     _phantom: PhantomData<fn(E, D)>,
 }
 
 impl<E, D> MessageCodec<E, D> {
     #[allow(unused)]
-    pub fn new(strict: bool) -> Self {
+    pub fn new(protocol: Arc<dyn TProtocolFactory>) -> Self {
+        Self {
+            protocol,
+            decoder: None,
+            limits: DecodeLimits::default(),
+            _phantom: PhantomData,
+        }
+    }
+
+    pub(crate) fn with_decoder(mut self, decoder: Option<Arc<dyn MessageDecoder<D>>>) -> Self {
+        self.decoder = decoder;
+        self
+    }
+
+    pub(crate) fn with_limits(mut self, limits: DecodeLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// A codec configured like this one, speaking another protocol.
+    pub(crate) fn clone_with_protocol(&self, protocol: Arc<dyn TProtocolFactory>) -> Self {
         Self {
-            strict,
+            protocol,
+            decoder: self.decoder.clone(),
+            limits: self.limits,
             _phantom: PhantomData,
         }
     }
@@ -130,8 +316,7 @@ where
         item: (MsgContext, ApplicationResult<E>),
         dst: &mut BytesMut,
     ) -> Result<(), Self::Error> {
-        // We hard code BinaryProtocol for now. TODO: fix it
-        let mut protocol = TBinaryOutputProtocol::new(dst, self.strict);
+        let mut protocol = self.protocol.output_protocol(dst);
         let (mut cx, item) = item;
         match item {
             Ok(it) => {
@@ -149,6 +334,29 @@ where
     }
 }
 
+impl<E, D> SizedEncoder<(MsgContext, ApplicationResult<E>)> for MessageCodec<E, D>
+where
+    E: Message,
+{
+    fn encoded_size(&self, item: &(MsgContext, ApplicationResult<E>)) -> usize {
+        let mut protocol = self.protocol.length_protocol();
+        let (cx, item) = item;
+        let size = match item {
+            Ok(it) => {
+                protocol.message_begin_len(&cx.identifier) + it.encoded_size(cx, &mut protocol)
+            }
+            Err(err) => {
+                let identifier = TMessageIdentifier {
+                    message_type: TMessageType::Exception,
+                    ..cx.identifier.clone()
+                };
+                protocol.message_begin_len(&identifier) + err.encoded_size(cx, &mut protocol)
+            }
+        };
+        size + protocol.message_end_len()
+    }
+}
+
 impl<E, D> Decoder for MessageCodec<E, D>
 where
     D: Message,
@@ -157,8 +365,30 @@ where
     type Error = crate::Error;
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        // We hard code BinaryProtocol for now. TODO: fix it
-        let mut protocol = TBinaryInputProtocol::new(src, self.strict);
+        self.decode_from(self.protocol.input_protocol(src))
+            .map(Some)
+    }
+}
+
+impl<E, D> SliceDecoder for MessageCodec<E, D>
+where
+    D: Message,
+{
+    fn decode_slice(&mut self, src: &mut &[u8]) -> Result<Option<Self::Item>, Self::Error> {
+        self.decode_from(self.protocol.slice_input_protocol(src))
+            .map(Some)
+    }
+}
+
+impl<E, D> MessageCodec<E, D>
+where
+    D: Message,
+{
+    fn decode_from(
+        &self,
+        protocol: Box<dyn TInputProtocol + '_>,
+    ) -> crate::Result<(MsgContext, ApplicationResult<D>)> {
+        let mut protocol = TLimitedInputProtocol::new(protocol, self.limits);
         let mut cx = MsgContext::default();
 
         cx.identifier = protocol.read_message_begin()?;
@@ -166,11 +396,14 @@ where
             // TODO: decode to Exception
             let exception = ApplicationError::decode(&mut cx, &mut protocol)?;
             protocol.read_message_end()?;
-            return Ok(Some((cx, Err(exception))));
+            return Ok((cx, Err(exception)));
         }
-        let item = D::decode(&mut cx, &mut protocol)?;
+        let item = match &self.decoder {
+            Some(decoder) => decoder.decode(&mut cx, &mut protocol)?,
+            None => Ok(D::decode(&mut cx, &mut protocol)?),
+        };
         protocol.read_message_end()?;
-        Ok(Some((cx, Ok(item))))
+        Ok((cx, item))
     }
 }
 
@@ -183,20 +416,257 @@ pub trait MakeCodec {
         + Decoder<Item = Self::DecodeItem, Error = Self::Error>;
 
     fn make_codec(&mut self) -> Self::Codec;
+
+    /// Initial capacities of the read and write buffers of a connection, in
+    /// this order.
+    fn buffer_capacity(&self) -> (usize, usize) {
+        (DEFAULT_BUFFER_CAPACITY, DEFAULT_BUFFER_CAPACITY)
+    }
 }
 
-pub struct DefaultMakeCodec<E, D>(PhantomData<fn((E, D))>);
+/// Wrap a connection with `codec` and buffers of the given capacities.
+pub(crate) fn framed<T, C>(io: T, codec: C, (read, write): (usize, usize)) -> Framed<T, C>
+where
+    T: AsyncRead + AsyncWrite,
+{
+    let mut framed = Framed::with_capacity(io, codec, read);
+    framed.write_buffer_mut().reserve(write);
+    framed
+}
+
+/// Options of the codecs made by `DefaultMakeCodec`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CodecOptions {
+    /// Max length of a frame, or of a message when unframed.
+    pub max_frame_length: usize,
+    /// Whether the binary protocol refuses messages without the version
+    /// header. `None` keeps the setting of the protocol.
+    pub strict_read: Option<bool>,
+    /// Whether the binary protocol writes the version header. `None` keeps
+    /// the setting of the protocol.
+    pub strict_write: Option<bool>,
+    /// Initial capacity of the read buffer of a connection.
+    pub read_buffer_capacity: usize,
+    /// Initial capacity of the write buffer of a connection.
+    pub write_buffer_capacity: usize,
+    /// Compress large messages, only with `Framing::Framed`. Compressed and
+    /// plain frames are then both accepted.
+    pub compression: Option<CompressionOptions>,
+}
+
+impl Default for CodecOptions {
+    fn default() -> Self {
+        Self {
+            max_frame_length: MAX_MESSAGE_SIZE,
+            strict_read: None,
+            strict_write: None,
+            read_buffer_capacity: DEFAULT_BUFFER_CAPACITY,
+            write_buffer_capacity: DEFAULT_BUFFER_CAPACITY,
+            compression: None,
+        }
+    }
+}
+
+/// How messages are delimited on the wire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Framing {
+    /// Each message is prefixed with its 4-byte length.
+    #[default]
+    Framed,
+    /// THeader transport, fbthrift and Kitex compatible.
+    Header,
+    /// Messages are sent back to back without a length prefix, as with
+    /// `TBufferedTransport`.
+    Unframed,
+    /// Each message is the body of an HTTP/1.1 `POST` or of its response, as
+    /// with `THttpClient`.
+    Http,
+}
+
+/// Codec made by `DefaultMakeCodec`, dispatching on the selected `Framing`.
+pub enum DefaultCodec<E, D> {
+    Framed(FramedCodec<MessageCodec<E, D>>),
+    Header(THeaderCodec<MessageCodec<E, D>>),
+    Unframed(UnframedCodec<MessageCodec<E, D>>),
+    Compressed(CompressedCodec<MessageCodec<E, D>>),
+    Http(HttpCodec<MessageCodec<E, D>>),
+    /// Waiting for the first bytes sent by the peer to pick one of the above.
+    Detecting {
+        codec: MessageCodec<E, D>,
+        max_frame_length: usize,
+    },
+}
+
+impl<E, D> DefaultCodec<E, D> {
+    /// See [`HttpCodec::oneway_response`], other framings answer nothing.
+    pub fn oneway_response(&self) -> Option<&'static [u8]> {
+        match self {
+            DefaultCodec::Http(c) => c.oneway_response(),
+            _ => None,
+        }
+    }
+
+    /// See [`HttpCodec::take_rejection`].
+    pub fn take_rejection(&mut self) -> Option<&'static [u8]> {
+        match self {
+            DefaultCodec::Http(c) => c.take_rejection(),
+            _ => None,
+        }
+    }
+}
+
+impl<E, D> Encoder<(MsgContext, ApplicationResult<E>)> for DefaultCodec<E, D>
+where
+    E: Message,
+{
+    type Error = crate::Error;
+
+    fn encode(
+        &mut self,
+        item: (MsgContext, ApplicationResult<E>),
+        dst: &mut BytesMut,
+    ) -> Result<(), Self::Error> {
+        match self {
+            DefaultCodec::Framed(c) => c.encode(item, dst),
+            DefaultCodec::Header(c) => c.encode(item, dst),
+            DefaultCodec::Unframed(c) => c.encode(item, dst),
+            DefaultCodec::Compressed(c) => c.encode(item, dst),
+            DefaultCodec::Http(c) => c.encode(item, dst),
+            DefaultCodec::Detecting { .. } => Err(new_protocol_error(
+                ProtocolErrorKind::Unknown,
+                "cannot encode before the codec is detected",
+            )),
+        }
+    }
+}
+
+impl<E, D> Decoder for DefaultCodec<E, D>
+where
+    D: Message,
+{
+    type Item = (MsgContext, ApplicationResult<D>);
+    type Error = crate::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        match self {
+            DefaultCodec::Framed(c) => c.decode(src),
+            DefaultCodec::Header(c) => c.decode(src),
+            DefaultCodec::Unframed(c) => c.decode(src),
+            DefaultCodec::Compressed(c) => c.decode(src),
+            DefaultCodec::Http(c) => c.decode(src),
+            DefaultCodec::Detecting {
+                codec,
+                max_frame_length,
+            } => match detect_codec(src, codec, *max_frame_length)? {
+                Some(codec) => {
+                    *self = codec;
+                    self.decode(src)
+                }
+                None => Ok(None),
+            },
+        }
+    }
+}
+
+pub struct DefaultMakeCodec<E, D> {
+    protocol: Arc<dyn TProtocolFactory>,
+    framing: Framing,
+    auto_detect: bool,
+    decoder: Option<Arc<dyn MessageDecoder<D>>>,
+    limits: DecodeLimits,
+    options: CodecOptions,
+    http_path: String,
+    http_host: String,
+    server: bool,
+    _phantom: PhantomData<fn((E, D))>,
+}
 
 impl<E, D> DefaultMakeCodec<E, D> {
     #[allow(unused)]
     pub fn new() -> Self {
         Self::default()
     }
+
+    /// Create a DefaultMakeCodec whose codecs speak the given protocol.
+    pub fn with_protocol<P: TProtocolFactory + 'static>(protocol: P) -> Self {
+        Self::with_shared_protocol(Arc::new(protocol))
+    }
+
+    pub(crate) fn with_shared_protocol(protocol: Arc<dyn TProtocolFactory>) -> Self {
+        DefaultMakeCodec {
+            protocol,
+            framing: Framing::default(),
+            auto_detect: false,
+            decoder: None,
+            limits: DecodeLimits::default(),
+            options: CodecOptions::default(),
+            http_path: DEFAULT_HTTP_PATH.to_string(),
+            http_host: DEFAULT_HTTP_HOST.to_string(),
+            server: false,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Speak the given protocol.
+    pub fn protocol<P: TProtocolFactory + 'static>(mut self, protocol: P) -> Self {
+        self.protocol = Arc::new(protocol);
+        self
+    }
+
+    /// Delimit messages with the given framing.
+    pub fn framing(mut self, framing: Framing) -> Self {
+        self.framing = framing;
+        self
+    }
+
+    /// Sniff the first bytes sent by the peer to pick the framing and the
+    /// protocol, instead of using the configured ones. Only suitable for servers.
+    pub fn auto_detect(mut self, auto_detect: bool) -> Self {
+        self.auto_detect = auto_detect;
+        self
+    }
+
+    /// Bound what a peer may make the codecs decode.
+    pub fn decode_limits(mut self, limits: DecodeLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Frame size, strictness and buffer sizing of the codecs.
+    pub fn options(mut self, options: CodecOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub(crate) fn decoder(mut self, decoder: Option<Arc<dyn MessageDecoder<D>>>) -> Self {
+        self.decoder = decoder;
+        self
+    }
+
+    /// Path the calls are posted to with `Framing::Http`.
+    pub fn http_path(mut self, path: impl Into<String>) -> Self {
+        self.http_path = path.into();
+        self
+    }
+
+    /// `Host` header of the calls posted with `Framing::Http`.
+    pub fn http_host(mut self, host: impl Into<String>) -> Self {
+        self.http_host = host.into();
+        self
+    }
+
+    /// Make codecs for the server side of connections: they only compress
+    /// once the peer did, see `CompressedCodec::follow_peer`, and read HTTP
+    /// requests rather than responses.
+    pub(crate) fn server(mut self, server: bool) -> Self {
+        self.server = server;
+        self
+    }
 }
 
 impl<E, D> Default for DefaultMakeCodec<E, D> {
     fn default() -> Self {
-        DefaultMakeCodec(PhantomData)
+        Self::with_protocol(TBinaryProtocolFactory::default())
     }
 }
 
@@ -209,25 +679,85 @@ where
     type DecodeItem = (MsgContext, ApplicationResult<D>);
     type Error = crate::Error;
 
-    type Codec = FramedCodec<MessageCodec<E, D>>;
+    type Codec = DefaultCodec<E, D>;
 
     fn make_codec(&mut self) -> Self::Codec {
-        FramedCodec::new(MessageCodec::new(true))
+        let options = self.options;
+        let protocol = match (options.strict_read, options.strict_write) {
+            (None, None) => None,
+            (strict_read, strict_write) => self.protocol.strictness(strict_read, strict_write),
+        }
+        .unwrap_or_else(|| self.protocol.clone());
+        let message_codec = MessageCodec::new(protocol.clone())
+            .with_decoder(self.decoder.clone())
+            .with_limits(self.limits);
+        if self.auto_detect {
+            return DefaultCodec::Detecting {
+                codec: message_codec,
+                max_frame_length: options.max_frame_length,
+            };
+        }
+        match (self.framing, options.compression) {
+            (Framing::Framed, Some(compression)) => {
+                let codec = if self.server {
+                    CompressedCodec::follow_peer(message_codec, compression.min_size)
+                } else {
+                    CompressedCodec::new(message_codec, compression)
+                };
+                DefaultCodec::Compressed(codec.with_max_frame_length(options.max_frame_length))
+            }
+            (Framing::Framed, None) => DefaultCodec::Framed(
+                FramedCodec::new(message_codec).with_max_frame_length(options.max_frame_length),
+            ),
+            (Framing::Header, _) => DefaultCodec::Header(
+                THeaderCodec::new(message_codec, protocol.header_protocol_id())
+                    .with_max_frame_length(options.max_frame_length),
+            ),
+            (Framing::Unframed, _) => DefaultCodec::Unframed(
+                UnframedCodec::new(message_codec).with_max_frame_length(options.max_frame_length),
+            ),
+            (Framing::Http, _) => {
+                let codec = if self.server {
+                    HttpCodec::server(message_codec, self.http_path.clone())
+                } else {
+                    HttpCodec::client(
+                        message_codec,
+                        self.http_path.clone(),
+                        self.http_host.clone(),
+                    )
+                };
+                DefaultCodec::Http(codec.with_max_frame_length(options.max_frame_length))
+            }
+        }
+    }
+
+    fn buffer_capacity(&self) -> (usize, usize) {
+        (
+            self.options.read_buffer_capacity,
+            self.options.write_buffer_capacity,
+        )
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
+
     use bytes::BufMut;
     use futures_util::{SinkExt, StreamExt};
     use tokio_util::codec::{Decoder, Encoder};
 
-    use crate::codec::MessageCodec;
+    use crate::binary::TBinaryProtocolFactory;
+    use crate::codec::{
+        CodecOptions, DefaultMakeCodec, MakeCodec, MessageCodec, SizedEncoder, UnframedCodec,
+    };
+    use crate::compact::TCompactProtocolFactory;
     use crate::context::MsgContext;
+    use crate::json::TJSONProtocolFactory;
     use crate::message::Message;
     use crate::protocol::{
-        TFieldIdentifier, TInputProtocol, TMessageIdentifier, TMessageType, TOutputProtocol,
-        TStructIdentifier, TType,
+        TFieldIdentifier, TInputProtocol, TLengthProtocol, TMessageIdentifier, TMessageType,
+        TOutputProtocol, TProtocolFactory, TStructIdentifier, TType,
     };
     use crate::{ApplicationError, ApplicationErrorKind};
 
@@ -252,6 +782,11 @@ mod tests {
             Ok(Some(src.split().freeze()))
         }
     }
+    impl SizedEncoder<bytes::Bytes> for IdentityCodec {
+        fn encoded_size(&self, item: &bytes::Bytes) -> usize {
+            item.len()
+        }
+    }
 
     /// test_framed_read read from mocked io with FramedCodec + IdentityCodec.
     #[tokio::test]
@@ -294,6 +829,36 @@ mod tests {
         assert!(s.is_ok(), "send must be success");
     }
 
+    /// test_framed_encode_error leaves nothing of a mis-sized frame behind.
+    #[test]
+    fn test_framed_encode_error() {
+        struct MisSizedCodec;
+        impl Encoder<bytes::Bytes> for MisSizedCodec {
+            type Error = crate::Error;
+
+            fn encode(
+                &mut self,
+                item: bytes::Bytes,
+                dst: &mut bytes::BytesMut,
+            ) -> Result<(), Self::Error> {
+                dst.extend(item);
+                Ok(())
+            }
+        }
+        impl SizedEncoder<bytes::Bytes> for MisSizedCodec {
+            fn encoded_size(&self, item: &bytes::Bytes) -> usize {
+                item.len() + 1
+            }
+        }
+
+        let mut dst = bytes::BytesMut::from(&b"queued"[..]);
+        let mut codec = super::FramedCodec::new(MisSizedCodec);
+        assert!(codec
+            .encode(bytes::Bytes::from_static(b"abc"), &mut dst)
+            .is_err());
+        assert_eq!(&dst[..], b"queued");
+    }
+
     #[derive(Debug, Default, Clone, Eq, PartialEq)]
     struct MockMessage {
         id: i32,
@@ -354,11 +919,31 @@ mod tests {
             protocol.read_struct_end()?;
             Ok(output)
         }
+
+        fn encoded_size<T: TLengthProtocol>(&self, cx: &MsgContext, protocol: &mut T) -> usize {
+            protocol.struct_begin_len(&TStructIdentifier {
+                name: "MockMessage".to_string(),
+            }) + protocol.field_begin_len(&TFieldIdentifier {
+                name: Some("id".to_string()),
+                field_type: TType::I32,
+                id: Some(1),
+            }) + self.id.encoded_size(cx, protocol)
+                + protocol.field_end_len()
+                + protocol.field_begin_len(&TFieldIdentifier {
+                    name: Some("is_male".to_string()),
+                    field_type: TType::Bool,
+                    id: Some(2),
+                })
+                + self.is_male.encoded_size(cx, protocol)
+                + protocol.field_end_len()
+                + protocol.field_stop_len()
+                + protocol.struct_end_len()
+        }
     }
 
     #[test]
     fn test_message_codec() {
-        let mut codec = MessageCodec::new(true);
+        let mut codec = MessageCodec::new(Arc::new(TBinaryProtocolFactory::new(true)));
         let mut buf = bytes::BytesMut::new();
 
         let cx = MsgContext {
@@ -381,7 +966,8 @@ mod tests {
 
     #[test]
     fn test_message_codec_error() {
-        let mut codec: MessageCodec<MockMessage, MockMessage> = MessageCodec::new(true);
+        let mut codec: MessageCodec<MockMessage, MockMessage> =
+            MessageCodec::new(Arc::new(TBinaryProtocolFactory::new(true)));
         let mut buf = bytes::BytesMut::new();
 
         let cx = MsgContext {
@@ -398,4 +984,219 @@ mod tests {
         let (_decoded_cx, decoded_message) = codec.decode(&mut buf).unwrap().unwrap();
         assert_eq!(error, decoded_message.unwrap_err());
     }
+
+    #[test]
+    fn test_encoded_size() {
+        let cx = MsgContext {
+            identifier: TMessageIdentifier {
+                name: "TestMethod".to_string(),
+                message_type: TMessageType::Call,
+                sequence_number: 300,
+            },
+            ..MsgContext::default()
+        };
+        let protocols: Vec<Arc<dyn TProtocolFactory>> = vec![
+            Arc::new(TBinaryProtocolFactory::new(true)),
+            Arc::new(TBinaryProtocolFactory::new(false)),
+            Arc::new(TCompactProtocolFactory::new()),
+            Arc::new(TJSONProtocolFactory::new()),
+        ];
+        for protocol in protocols {
+            let mut codec = MessageCodec::<MockMessage, MockMessage>::new(protocol);
+            let items = vec![
+                (
+                    cx.clone(),
+                    Ok(MockMessage {
+                        id: -70000,
+                        is_male: false,
+                    }),
+                ),
+                (
+                    cx.clone(),
+                    Err(ApplicationError::new(
+                        ApplicationErrorKind::InternalError,
+                        "the \"error\"",
+                    )),
+                ),
+            ];
+            for item in items {
+                let size = codec.encoded_size(&item);
+                let mut buf = bytes::BytesMut::new();
+                codec.encode(item, &mut buf).unwrap();
+                assert_eq!(size, buf.len());
+            }
+        }
+    }
+
+    #[test]
+    fn test_message_codec_protocols() {
+        let cx = MsgContext {
+            identifier: TMessageIdentifier {
+                name: "TestMethod".to_string(),
+                message_type: TMessageType::Call,
+                sequence_number: 1,
+            },
+            ..MsgContext::default()
+        };
+        let message = MockMessage {
+            id: 1,
+            is_male: true,
+        };
+
+        // non-strict binary: no version header, the name length comes first
+        let mut codec = MessageCodec::new(Arc::new(TBinaryProtocolFactory::new(false)));
+        let mut buf = bytes::BytesMut::new();
+        codec
+            .encode((cx.clone(), Ok(message.clone())), &mut buf)
+            .unwrap();
+        assert_eq!(&buf[..4], &[0x00, 0x00, 0x00, 0x0A]);
+        let (decoded_cx, decoded_message) = codec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(cx.identifier, decoded_cx.identifier);
+        assert_eq!(message, decoded_message.unwrap());
+
+        // a strict writer is still readable by a non-strict reader
+        let mut writer = MessageCodec::<MockMessage, MockMessage>::new(Arc::new(
+            TBinaryProtocolFactory::with_strictness(true, true),
+        ));
+        let mut reader = MessageCodec::<MockMessage, MockMessage>::new(Arc::new(
+            TBinaryProtocolFactory::with_strictness(false, false),
+        ));
+        writer
+            .encode((cx.clone(), Ok(message.clone())), &mut buf)
+            .unwrap();
+        assert_eq!(&buf[..2], &[0x80, 0x01]);
+        let (_decoded_cx, decoded_message) = reader.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(message, decoded_message.unwrap());
+
+        // compact
+        let mut codec = MessageCodec::new(Arc::new(TCompactProtocolFactory::new()));
+        let mut buf = bytes::BytesMut::new();
+        codec
+            .encode((cx.clone(), Ok(message.clone())), &mut buf)
+            .unwrap();
+        assert_eq!(&buf[..2], &[0x82, 0x21]);
+        let (decoded_cx, decoded_message) = codec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(cx.identifier, decoded_cx.identifier);
+        assert_eq!(message, decoded_message.unwrap());
+        assert!(buf.is_empty());
+
+        // json
+        let mut codec = MessageCodec::new(Arc::new(TJSONProtocolFactory::new()));
+        let mut buf = bytes::BytesMut::new();
+        codec
+            .encode((cx.clone(), Ok(message.clone())), &mut buf)
+            .unwrap();
+        assert_eq!(
+            &buf[..],
+            &br#"[1,"TestMethod",1,1,{"1":{"i32":1},"2":{"tf":1}}]"#[..]
+        );
+        let (decoded_cx, decoded_message) = codec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(cx.identifier, decoded_cx.identifier);
+        assert_eq!(message, decoded_message.unwrap());
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_codec_options() {
+        let cx = MsgContext {
+            identifier: TMessageIdentifier {
+                name: "TestMethod".to_string(),
+                message_type: TMessageType::Call,
+                sequence_number: 1,
+            },
+            ..MsgContext::default()
+        };
+        let message = MockMessage {
+            id: 1,
+            is_male: true,
+        };
+        let mut make_codec =
+            DefaultMakeCodec::<MockMessage, MockMessage>::new().options(CodecOptions {
+                max_frame_length: 32,
+                strict_read: Some(false),
+                strict_write: Some(false),
+                read_buffer_capacity: 64,
+                ..CodecOptions::default()
+            });
+        assert_eq!(make_codec.buffer_capacity(), (64, 8 * 1024));
+
+        // non-strict: the name length comes right after the frame length
+        let mut codec = make_codec.make_codec();
+        let mut buf = bytes::BytesMut::new();
+        codec
+            .encode((cx.clone(), Ok(message.clone())), &mut buf)
+            .unwrap();
+        assert_eq!(&buf[4..8], &[0x00, 0x00, 0x00, 0x0A]);
+        let (_, decoded_message) = codec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(message, decoded_message.unwrap());
+
+        let long = MsgContext {
+            identifier: TMessageIdentifier {
+                name: "A".repeat(32),
+                ..cx.identifier.clone()
+            },
+            ..MsgContext::default()
+        };
+        assert!(codec
+            .encode((long.clone(), Ok(message.clone())), &mut buf)
+            .is_err());
+        assert!(buf.is_empty());
+
+        // frames longer than the limit are refused on read as well
+        let mut codec = DefaultMakeCodec::<MockMessage, MockMessage>::new().make_codec();
+        codec.encode((long, Ok(message)), &mut buf).unwrap();
+        let mut codec = make_codec.make_codec();
+        assert!(codec.decode(&mut buf).is_err());
+    }
+
+    #[test]
+    fn test_unframed_partial() {
+        let cx = MsgContext {
+            identifier: TMessageIdentifier {
+                name: "TestMethod".to_string(),
+                message_type: TMessageType::Call,
+                sequence_number: 1,
+            },
+            ..MsgContext::default()
+        };
+        let message = MockMessage {
+            id: -70000,
+            is_male: true,
+        };
+        let protocols: Vec<Arc<dyn TProtocolFactory>> = vec![
+            Arc::new(TBinaryProtocolFactory::new(true)),
+            Arc::new(TBinaryProtocolFactory::new(false)),
+            Arc::new(TCompactProtocolFactory::new()),
+            Arc::new(TJSONProtocolFactory::new()),
+        ];
+        for protocol in protocols {
+            let mut codec =
+                UnframedCodec::new(MessageCodec::<MockMessage, MockMessage>::new(protocol));
+            let mut encoded = bytes::BytesMut::new();
+            codec
+                .encode((cx.clone(), Ok(message.clone())), &mut encoded)
+                .unwrap();
+            codec
+                .encode((cx.clone(), Ok(message.clone())), &mut encoded)
+                .unwrap();
+            let size = encoded.len() / 2;
+
+            // every strict prefix of a message is incomplete and left untouched
+            let mut buf = bytes::BytesMut::new();
+            for b in encoded[..size].iter() {
+                assert!(codec.decode(&mut buf).unwrap().is_none());
+                assert_eq!(&buf[..], &encoded[..buf.len()]);
+                buf.put_u8(*b);
+            }
+            buf.extend_from_slice(&encoded[size..size + 1]);
+            let (_, decoded_message) = codec.decode(&mut buf).unwrap().unwrap();
+            assert_eq!(message, decoded_message.unwrap());
+            assert_eq!(&buf[..], &encoded[size..size + 1]);
+
+            buf.extend_from_slice(&encoded[size + 1..]);
+            let (_, decoded_message) = codec.decode(&mut buf).unwrap().unwrap();
+            assert_eq!(message, decoded_message.unwrap());
+            assert!(buf.is_empty());
+        }
+    }
 }
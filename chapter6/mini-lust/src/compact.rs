@@ -0,0 +1,1214 @@
+use std::convert::TryFrom;
+
+use bytes::{Bytes, BytesMut};
+
+use crate::binary::protocol_len_check;
+use crate::limits::DecodeLimits;
+use crate::protocol::{
+    TFieldIdentifier, TInputProtocol, TLengthProtocol, TListIdentifier, TMapIdentifier,
+    TMessageIdentifier, TMessageType, TOutputProtocol, TProtocolFactory, TSetIdentifier,
+    TStructIdentifier, TType,
+};
+use crate::{new_protocol_error, ProtocolError, ProtocolErrorKind};
+
+const COMPACT_PROTOCOL_ID: u8 = 0x82;
+const COMPACT_VERSION: u8 = 0x01;
+const COMPACT_VERSION_MASK: u8 = 0x1F;
+
+// Mini-lust note: warp a BufMut to reuse buffer and do buffered packet parsing.
+#[derive(Debug)]
+pub struct TCompactOutputProtocol<T> {
+    buf: T,
+    // Identifier of the last field serialized in a struct.
+    last_write_field_id: i16,
+    // Stack of the last written field ids (new entry added each time a nested struct is written).
+    write_field_id_stack: Vec<i16>,
+    // Field identifier of the boolean field to be written.
+    // Saved because boolean fields and their value are encoded in a single byte.
+    pending_write_bool_field_identifier: Option<TFieldIdentifier>,
+}
+
+impl<T> TCompactOutputProtocol<T> {
+    pub fn new(buf: T) -> TCompactOutputProtocol<T> {
+        TCompactOutputProtocol {
+            buf,
+            last_write_field_id: 0,
+            write_field_id_stack: Vec::new(),
+            pending_write_bool_field_identifier: None,
+        }
+    }
+}
+
+impl<T> TCompactOutputProtocol<T>
+where
+    T: bytes::BufMut,
+{
+    #[inline]
+    fn write_field_header(&mut self, field_type: u8, field_id: i16) -> crate::Result<()> {
+        let field_delta = field_id.wrapping_sub(self.last_write_field_id);
+        if field_delta > 0 && field_delta <= 15 {
+            self.write_byte(((field_delta as u8) << 4) | field_type)?;
+        } else {
+            self.write_byte(field_type)?;
+            self.write_i16(field_id)?;
+        }
+        self.last_write_field_id = field_id;
+        Ok(())
+    }
+
+    #[inline]
+    fn write_list_set_begin(
+        &mut self,
+        element_type: TType,
+        element_count: i32,
+    ) -> crate::Result<()> {
+        let elem_identifier = collection_type_to_u8(element_type)?;
+        if element_count <= 14 {
+            let header = (element_count as u8) << 4 | elem_identifier;
+            self.write_byte(header)
+        } else {
+            let header = 0xF0 | elem_identifier;
+            self.write_byte(header)?;
+            self.write_varint(element_count as u32 as u64);
+            Ok(())
+        }
+    }
+
+    #[inline]
+    fn write_varint(&mut self, mut n: u64) {
+        while n >= 0x80 {
+            self.buf.put_u8((n as u8) | 0x80);
+            n >>= 7;
+        }
+        self.buf.put_u8(n as u8);
+    }
+
+    #[inline]
+    fn assert_no_pending_bool_write(&self) -> crate::Result<()> {
+        if let Some(ref f) = self.pending_write_bool_field_identifier {
+            return Err(new_protocol_error(
+                ProtocolErrorKind::InvalidData,
+                format!("pending bool field {:?} not written", f),
+            ));
+        }
+        Ok(())
+    }
+}
+
+// Mini-lust note: Mainly copied from official thrift code
+impl<T> TOutputProtocol for TCompactOutputProtocol<T>
+where
+    T: bytes::BufMut,
+{
+    #[inline]
+    fn write_message_begin(&mut self, identifier: &TMessageIdentifier) -> crate::Result<()> {
+        self.write_byte(COMPACT_PROTOCOL_ID)?;
+        let message_type: u8 = identifier.message_type.into();
+        self.write_byte((message_type << 5) | COMPACT_VERSION)?;
+        self.write_varint(identifier.sequence_number as u32 as u64);
+        self.write_string(&identifier.name)
+    }
+
+    #[inline]
+    fn write_message_end(&mut self) -> crate::Result<()> {
+        self.assert_no_pending_bool_write()
+    }
+
+    #[inline]
+    fn write_struct_begin(&mut self, _: &TStructIdentifier) -> crate::Result<()> {
+        self.write_field_id_stack.push(self.last_write_field_id);
+        self.last_write_field_id = 0;
+        Ok(())
+    }
+
+    #[inline]
+    fn write_struct_end(&mut self) -> crate::Result<()> {
+        self.assert_no_pending_bool_write()?;
+        self.last_write_field_id = self.write_field_id_stack.pop().ok_or_else(|| {
+            new_protocol_error(
+                ProtocolErrorKind::InvalidData,
+                "WriteStructEnd called without matching WriteStructBegin",
+            )
+        })?;
+        Ok(())
+    }
+
+    #[inline]
+    fn write_field_begin(&mut self, identifier: &TFieldIdentifier) -> crate::Result<()> {
+        let id = match identifier.id {
+            Some(id) => id,
+            None => {
+                return Err(crate::Error::Protocol(ProtocolError {
+                    kind: ProtocolErrorKind::Unknown,
+                    message: format!(
+                        "cannot write identifier {:?} without sequence number",
+                        &identifier
+                    ),
+                }));
+            }
+        };
+
+        match identifier.field_type {
+            TType::Bool => {
+                if self.pending_write_bool_field_identifier.is_some() {
+                    return Err(new_protocol_error(
+                        ProtocolErrorKind::InvalidData,
+                        format!(
+                            "should not have a pending bool while writing another bool with id: {:?}",
+                            id
+                        ),
+                    ));
+                }
+                self.pending_write_bool_field_identifier = Some(identifier.clone());
+                Ok(())
+            }
+            _ => {
+                let field_type = type_to_u8(identifier.field_type)?;
+                self.write_field_header(field_type, id)
+            }
+        }
+    }
+
+    #[inline]
+    fn write_field_end(&mut self) -> crate::Result<()> {
+        self.assert_no_pending_bool_write()
+    }
+
+    #[inline]
+    fn write_field_stop(&mut self) -> crate::Result<()> {
+        self.assert_no_pending_bool_write()?;
+        self.write_byte(type_to_u8(TType::Stop)?)
+    }
+
+    #[inline]
+    fn write_bool(&mut self, b: bool) -> crate::Result<()> {
+        match self.pending_write_bool_field_identifier.take() {
+            Some(pending) => {
+                let field_id = pending.id.expect("bool field should have a field id");
+                let field_type_as_u8 = if b { 0x01 } else { 0x02 };
+                self.write_field_header(field_type_as_u8, field_id)
+            }
+            None => {
+                if b {
+                    self.write_byte(0x01)
+                } else {
+                    self.write_byte(0x02)
+                }
+            }
+        }
+    }
+
+    #[inline]
+    fn write_bytes(&mut self, b: &[u8]) -> crate::Result<()> {
+        self.write_varint(b.len() as u64);
+        self.buf.put_slice(b);
+        Ok(())
+    }
+
+    #[inline]
+    fn write_i8(&mut self, i: i8) -> crate::Result<()> {
+        self.buf.put_i8(i);
+        Ok(())
+    }
+
+    #[inline]
+    fn write_i16(&mut self, i: i16) -> crate::Result<()> {
+        self.write_i32(i as i32)
+    }
+
+    #[inline]
+    fn write_i32(&mut self, i: i32) -> crate::Result<()> {
+        self.write_varint(((i << 1) ^ (i >> 31)) as u32 as u64);
+        Ok(())
+    }
+
+    #[inline]
+    fn write_i64(&mut self, i: i64) -> crate::Result<()> {
+        self.write_varint(((i << 1) ^ (i >> 63)) as u64);
+        Ok(())
+    }
+
+    #[inline]
+    fn write_double(&mut self, d: f64) -> crate::Result<()> {
+        self.buf.put_f64_le(d);
+        Ok(())
+    }
+
+    #[inline]
+    fn write_string(&mut self, s: &str) -> crate::Result<()> {
+        self.write_bytes(s.as_bytes())
+    }
+
+    #[inline]
+    fn write_uuid(&mut self, uuid: [u8; 16]) -> crate::Result<()> {
+        self.buf.put_slice(&uuid);
+        Ok(())
+    }
+
+    #[inline]
+    fn write_list_begin(&mut self, identifier: &TListIdentifier) -> crate::Result<()> {
+        self.write_list_set_begin(identifier.element_type, identifier.size)
+    }
+
+    #[inline]
+    fn write_list_end(&mut self) -> crate::Result<()> {
+        Ok(())
+    }
+
+    #[inline]
+    fn write_set_begin(&mut self, identifier: &TSetIdentifier) -> crate::Result<()> {
+        self.write_list_set_begin(identifier.element_type, identifier.size)
+    }
+
+    #[inline]
+    fn write_set_end(&mut self) -> crate::Result<()> {
+        Ok(())
+    }
+
+    #[inline]
+    fn write_map_begin(&mut self, identifier: &TMapIdentifier) -> crate::Result<()> {
+        if identifier.size == 0 {
+            self.write_byte(0)
+        } else {
+            self.write_varint(identifier.size as u32 as u64);
+
+            let key_type = identifier
+                .key_type
+                .expect("map identifier to write should contain key type");
+            let key_type_byte = collection_type_to_u8(key_type)? << 4;
+
+            let val_type = identifier
+                .value_type
+                .expect("map identifier to write should contain value type");
+            let val_type_byte = collection_type_to_u8(val_type)?;
+
+            self.write_byte(key_type_byte | val_type_byte)
+        }
+    }
+
+    #[inline]
+    fn write_map_end(&mut self) -> crate::Result<()> {
+        Ok(())
+    }
+
+    #[inline]
+    fn flush(&mut self) -> crate::Result<()> {
+        Ok(())
+    }
+
+    // utility
+    //
+
+    #[inline]
+    fn write_byte(&mut self, b: u8) -> crate::Result<()> {
+        self.buf.put_u8(b);
+        Ok(())
+    }
+}
+
+/// Computes the lengths written by `TCompactOutputProtocol`.
+///
+/// Like the output protocol it tracks the last field id of each struct, since
+/// field headers are encoded relative to it.
+#[derive(Debug, Default)]
+pub struct TCompactLengthProtocol {
+    last_write_field_id: i16,
+    write_field_id_stack: Vec<i16>,
+    pending_write_bool_field_id: Option<i16>,
+}
+
+impl TCompactLengthProtocol {
+    pub fn new() -> TCompactLengthProtocol {
+        TCompactLengthProtocol::default()
+    }
+
+    #[inline]
+    fn field_header_len(&mut self, field_id: i16) -> usize {
+        let field_delta = field_id.wrapping_sub(self.last_write_field_id);
+        self.last_write_field_id = field_id;
+        if field_delta > 0 && field_delta <= 15 {
+            1
+        } else {
+            1 + self.i16_len(field_id)
+        }
+    }
+
+    #[inline]
+    fn list_set_begin_len(&mut self, element_count: i32) -> usize {
+        if element_count <= 14 {
+            1
+        } else {
+            1 + varint_len(element_count as u32 as u64)
+        }
+    }
+}
+
+impl TLengthProtocol for TCompactLengthProtocol {
+    #[inline]
+    fn message_begin_len(&mut self, identifier: &TMessageIdentifier) -> usize {
+        2 + varint_len(identifier.sequence_number as u32 as u64) + self.string_len(&identifier.name)
+    }
+
+    #[inline]
+    fn message_end_len(&mut self) -> usize {
+        0
+    }
+
+    #[inline]
+    fn struct_begin_len(&mut self, _: &TStructIdentifier) -> usize {
+        self.write_field_id_stack.push(self.last_write_field_id);
+        self.last_write_field_id = 0;
+        0
+    }
+
+    #[inline]
+    fn struct_end_len(&mut self) -> usize {
+        self.last_write_field_id = self.write_field_id_stack.pop().unwrap_or_default();
+        0
+    }
+
+    #[inline]
+    fn field_begin_len(&mut self, identifier: &TFieldIdentifier) -> usize {
+        let id = identifier.id.unwrap_or_default();
+        match identifier.field_type {
+            // the header is written along with the value
+            TType::Bool => {
+                self.pending_write_bool_field_id = Some(id);
+                0
+            }
+            _ => self.field_header_len(id),
+        }
+    }
+
+    #[inline]
+    fn field_end_len(&mut self) -> usize {
+        0
+    }
+
+    #[inline]
+    fn field_stop_len(&mut self) -> usize {
+        1
+    }
+
+    #[inline]
+    fn bool_len(&mut self, _: bool) -> usize {
+        match self.pending_write_bool_field_id.take() {
+            Some(field_id) => self.field_header_len(field_id),
+            None => 1,
+        }
+    }
+
+    #[inline]
+    fn bytes_len(&mut self, b: &[u8]) -> usize {
+        varint_len(b.len() as u64) + b.len()
+    }
+
+    #[inline]
+    fn i8_len(&mut self, _: i8) -> usize {
+        1
+    }
+
+    #[inline]
+    fn i16_len(&mut self, i: i16) -> usize {
+        self.i32_len(i as i32)
+    }
+
+    #[inline]
+    fn i32_len(&mut self, i: i32) -> usize {
+        varint_len(((i << 1) ^ (i >> 31)) as u32 as u64)
+    }
+
+    #[inline]
+    fn i64_len(&mut self, i: i64) -> usize {
+        varint_len(((i << 1) ^ (i >> 63)) as u64)
+    }
+
+    #[inline]
+    fn double_len(&mut self, _: f64) -> usize {
+        8
+    }
+
+    #[inline]
+    fn string_len(&mut self, s: &str) -> usize {
+        self.bytes_len(s.as_bytes())
+    }
+
+    #[inline]
+    fn uuid_len(&mut self, _: [u8; 16]) -> usize {
+        16
+    }
+
+    #[inline]
+    fn list_begin_len(&mut self, identifier: &TListIdentifier) -> usize {
+        self.list_set_begin_len(identifier.size)
+    }
+
+    #[inline]
+    fn list_end_len(&mut self) -> usize {
+        0
+    }
+
+    #[inline]
+    fn set_begin_len(&mut self, identifier: &TSetIdentifier) -> usize {
+        self.list_set_begin_len(identifier.size)
+    }
+
+    #[inline]
+    fn set_end_len(&mut self) -> usize {
+        0
+    }
+
+    #[inline]
+    fn map_begin_len(&mut self, identifier: &TMapIdentifier) -> usize {
+        if identifier.size == 0 {
+            1
+        } else {
+            varint_len(identifier.size as u32 as u64) + 1
+        }
+    }
+
+    #[inline]
+    fn map_end_len(&mut self) -> usize {
+        0
+    }
+
+    #[inline]
+    fn byte_len(&mut self, _: u8) -> usize {
+        1
+    }
+}
+
+#[inline]
+fn varint_len(mut n: u64) -> usize {
+    let mut len = 1;
+    while n >= 0x80 {
+        n >>= 7;
+        len += 1;
+    }
+    len
+}
+
+// Mini-lust note: warp a Buf to reuse buffer and do buffered packet parsing.
+#[derive(Debug)]
+pub struct TCompactInputProtocol<T> {
+    buf: T,
+    // Identifier of the last field deserialized for a struct.
+    last_read_field_id: i16,
+    // Stack of the last read field ids (a new entry is added each time a nested struct is read).
+    read_field_id_stack: Vec<i16>,
+    // Boolean value for a field.
+    // Saved because boolean fields and their value are encoded in a single byte,
+    // and reading the field only occurs after the field id is read.
+    pending_read_bool_value: Option<bool>,
+    limits: DecodeLimits,
+}
+
+impl<T> TCompactInputProtocol<T> {
+    pub fn new(buf: T) -> TCompactInputProtocol<T> {
+        TCompactInputProtocol {
+            buf,
+            last_read_field_id: 0,
+            read_field_id_stack: Vec::new(),
+            pending_read_bool_value: None,
+            limits: DecodeLimits::default(),
+        }
+    }
+}
+
+impl<T> TCompactInputProtocol<T>
+where
+    T: bytes::Buf,
+{
+    #[inline]
+    fn read_list_set_begin(&mut self) -> crate::Result<(TType, i32)> {
+        let header = self.read_byte()?;
+        let element_type = collection_u8_to_type(header & 0x0F)?;
+
+        let possible_element_count = (header & 0xF0) >> 4;
+        let element_count = if possible_element_count != 15 {
+            // high bits set high if count and type encoded separately
+            possible_element_count as i32
+        } else {
+            self.read_varint()? as u32 as i32
+        };
+        self.limits
+            .check_container_bytes(element_count, self.buf.remaining())?;
+
+        Ok((element_type, element_count))
+    }
+
+    /// Read the length prefix of a string or binary, checking it against the
+    /// limits and the remaining data.
+    #[inline]
+    fn read_length(&mut self) -> crate::Result<usize> {
+        let len = self.read_varint()? as u32 as usize;
+        self.limits.check_string_length(len)?;
+        protocol_len_check(&self.buf, len)?;
+        Ok(len)
+    }
+
+    #[inline]
+    fn read_varint(&mut self) -> crate::Result<u64> {
+        let mut result = 0u64;
+        let mut shift = 0;
+        loop {
+            let b = self.read_byte()?;
+            result |= ((b & 0x7F) as u64) << shift;
+            if b & 0x80 == 0 {
+                return Ok(result);
+            }
+            shift += 7;
+            if shift >= 64 {
+                return Err(new_protocol_error(
+                    ProtocolErrorKind::InvalidData,
+                    "varint exceeds 64 bits",
+                ));
+            }
+        }
+    }
+}
+
+// Mini-lust note: Mainly copied from official thrift code
+impl<T> TInputProtocol for TCompactInputProtocol<T>
+where
+    T: bytes::Buf,
+{
+    #[inline]
+    fn read_message_begin(&mut self) -> crate::Result<TMessageIdentifier> {
+        let compact_id = self.read_byte()?;
+        if compact_id != COMPACT_PROTOCOL_ID {
+            return Err(crate::Error::Protocol(ProtocolError {
+                kind: ProtocolErrorKind::BadVersion,
+                message: format!("invalid compact protocol header {:?}", compact_id),
+            }));
+        }
+
+        let type_and_version = self.read_byte()?;
+        let received_version = type_and_version & COMPACT_VERSION_MASK;
+        if received_version != COMPACT_VERSION {
+            return Err(crate::Error::Protocol(ProtocolError {
+                kind: ProtocolErrorKind::BadVersion,
+                message: format!(
+                    "cannot process compact protocol version {:?}",
+                    received_version
+                ),
+            }));
+        }
+
+        // NOTE: unsigned right shift will pad with 0s
+        let message_type: TMessageType = TMessageType::try_from(type_and_version >> 5)?;
+        let sequence_number = self.read_varint()? as u32 as i32;
+        let service_call_name = self.read_string()?;
+
+        self.last_read_field_id = 0;
+
+        Ok(TMessageIdentifier::new(
+            service_call_name,
+            message_type,
+            sequence_number,
+        ))
+    }
+
+    #[inline]
+    fn read_message_end(&mut self) -> crate::Result<()> {
+        Ok(())
+    }
+
+    #[inline]
+    fn read_struct_begin(&mut self) -> crate::Result<Option<TStructIdentifier>> {
+        self.read_field_id_stack.push(self.last_read_field_id);
+        self.last_read_field_id = 0;
+        Ok(None)
+    }
+
+    #[inline]
+    fn read_struct_end(&mut self) -> crate::Result<()> {
+        self.last_read_field_id = self.read_field_id_stack.pop().ok_or_else(|| {
+            new_protocol_error(
+                ProtocolErrorKind::InvalidData,
+                "ReadStructEnd called without matching ReadStructBegin",
+            )
+        })?;
+        Ok(())
+    }
+
+    #[inline]
+    fn read_field_begin(&mut self) -> crate::Result<TFieldIdentifier> {
+        let field_type = self.read_byte()?;
+        let field_delta = (field_type & 0xF0) >> 4;
+        let field_type = match field_type & 0x0F {
+            0x01 => {
+                self.pending_read_bool_value = Some(true);
+                Ok(TType::Bool)
+            }
+            0x02 => {
+                self.pending_read_bool_value = Some(false);
+                Ok(TType::Bool)
+            }
+            ttu8 => u8_to_type(ttu8),
+        }?;
+
+        match field_type {
+            TType::Stop => Ok(
+                TFieldIdentifier::new::<Option<String>, String, Option<i16>>(
+                    None,
+                    TType::Stop,
+                    None,
+                ),
+            ),
+            _ => {
+                if field_delta != 0 {
+                    self.last_read_field_id = self
+                        .last_read_field_id
+                        .checked_add(field_delta as i16)
+                        .ok_or_else(|| {
+                            new_protocol_error(
+                                ProtocolErrorKind::InvalidData,
+                                "field id delta overflows the field id",
+                            )
+                        })?;
+                } else {
+                    self.last_read_field_id = self.read_i16()?;
+                };
+
+                Ok(TFieldIdentifier::new::<Option<String>, String, i16>(
+                    None,
+                    field_type,
+                    self.last_read_field_id,
+                ))
+            }
+        }
+    }
+
+    #[inline]
+    fn read_field_end(&mut self) -> crate::Result<()> {
+        Ok(())
+    }
+
+    #[inline]
+    fn read_bool(&mut self) -> crate::Result<bool> {
+        match self.pending_read_bool_value.take() {
+            Some(b) => Ok(b),
+            None => {
+                let b = self.read_byte()?;
+                match b {
+                    0x01 => Ok(true),
+                    0x02 => Ok(false),
+                    unkn => Err(crate::Error::Protocol(ProtocolError {
+                        kind: ProtocolErrorKind::InvalidData,
+                        message: format!("cannot convert {} into bool", unkn),
+                    })),
+                }
+            }
+        }
+    }
+
+    #[inline]
+    fn read_bytes(&mut self) -> crate::Result<Vec<u8>> {
+        let len = self.read_length()?;
+        let mut buf = vec![0u8; len];
+        self.buf.copy_to_slice(&mut buf);
+        Ok(buf)
+    }
+
+    #[inline]
+    fn read_binary(&mut self) -> crate::Result<Bytes> {
+        let len = self.read_length()?;
+        Ok(self.buf.copy_to_bytes(len))
+    }
+
+    #[inline]
+    fn read_i8(&mut self) -> crate::Result<i8> {
+        self.read_byte().map(|i| i as i8)
+    }
+
+    #[inline]
+    fn read_i16(&mut self) -> crate::Result<i16> {
+        self.read_i32().map(|i| i as i16)
+    }
+
+    #[inline]
+    fn read_i32(&mut self) -> crate::Result<i32> {
+        let n = self.read_varint()? as u32;
+        Ok(((n >> 1) as i32) ^ -((n & 1) as i32))
+    }
+
+    #[inline]
+    fn read_i64(&mut self) -> crate::Result<i64> {
+        let n = self.read_varint()?;
+        Ok(((n >> 1) as i64) ^ -((n & 1) as i64))
+    }
+
+    #[inline]
+    fn read_double(&mut self) -> crate::Result<f64> {
+        protocol_len_check(&self.buf, 8)?;
+        Ok(self.buf.get_f64_le())
+    }
+
+    #[inline]
+    fn read_string(&mut self) -> crate::Result<String> {
+        let bytes = self.read_bytes()?;
+        String::from_utf8(bytes).map_err(From::from)
+    }
+
+    #[inline]
+    fn read_uuid(&mut self) -> crate::Result<[u8; 16]> {
+        protocol_len_check(&self.buf, 16)?;
+        let mut uuid = [0; 16];
+        self.buf.copy_to_slice(&mut uuid);
+        Ok(uuid)
+    }
+
+    #[inline]
+    fn read_list_begin(&mut self) -> crate::Result<TListIdentifier> {
+        let (element_type, element_count) = self.read_list_set_begin()?;
+        Ok(TListIdentifier::new(element_type, element_count))
+    }
+
+    #[inline]
+    fn read_list_end(&mut self) -> crate::Result<()> {
+        Ok(())
+    }
+
+    #[inline]
+    fn read_set_begin(&mut self) -> crate::Result<TSetIdentifier> {
+        let (element_type, element_count) = self.read_list_set_begin()?;
+        Ok(TSetIdentifier::new(element_type, element_count))
+    }
+
+    #[inline]
+    fn read_set_end(&mut self) -> crate::Result<()> {
+        Ok(())
+    }
+
+    #[inline]
+    fn read_map_begin(&mut self) -> crate::Result<TMapIdentifier> {
+        let element_count = self.read_varint()? as u32 as i32;
+        self.limits
+            .check_container_bytes(element_count, self.buf.remaining())?;
+        if element_count == 0 {
+            Ok(TMapIdentifier::new(None, None, 0))
+        } else {
+            let type_header = self.read_byte()?;
+            let key_type = collection_u8_to_type((type_header & 0xF0) >> 4)?;
+            let val_type = collection_u8_to_type(type_header & 0x0F)?;
+            Ok(TMapIdentifier::new(key_type, val_type, element_count))
+        }
+    }
+
+    #[inline]
+    fn read_map_end(&mut self) -> crate::Result<()> {
+        Ok(())
+    }
+
+    // utility
+    //
+
+    #[inline]
+    fn read_byte(&mut self) -> crate::Result<u8> {
+        protocol_len_check(&self.buf, 1)?;
+        Ok(self.buf.get_u8())
+    }
+
+    fn set_decode_limits(&mut self, limits: DecodeLimits) {
+        self.limits = limits;
+    }
+}
+
+/// Factory for compact protocol instances.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TCompactProtocolFactory;
+
+impl TCompactProtocolFactory {
+    pub fn new() -> TCompactProtocolFactory {
+        TCompactProtocolFactory
+    }
+}
+
+impl TProtocolFactory for TCompactProtocolFactory {
+    fn input_protocol<'a>(&self, buf: &'a mut BytesMut) -> Box<dyn TInputProtocol + 'a> {
+        Box::new(TCompactInputProtocol::new(buf))
+    }
+
+    fn slice_input_protocol<'a>(&self, buf: &'a mut &[u8]) -> Box<dyn TInputProtocol + 'a> {
+        Box::new(TCompactInputProtocol::new(buf))
+    }
+
+    fn output_protocol<'a>(&self, buf: &'a mut BytesMut) -> Box<dyn TOutputProtocol + 'a> {
+        Box::new(TCompactOutputProtocol::new(buf))
+    }
+
+    fn length_protocol(&self) -> Box<dyn TLengthProtocol> {
+        Box::new(TCompactLengthProtocol::new())
+    }
+
+    fn header_protocol_id(&self) -> Option<i32> {
+        Some(crate::header::HEADER_PROTOCOL_COMPACT)
+    }
+}
+
+#[inline]
+fn collection_type_to_u8(field_type: TType) -> crate::Result<u8> {
+    match field_type {
+        TType::Bool => Ok(0x01),
+        f => type_to_u8(f),
+    }
+}
+
+#[inline]
+fn type_to_u8(field_type: TType) -> crate::Result<u8> {
+    match field_type {
+        TType::Stop => Ok(0x00),
+        TType::I08 => Ok(0x03), // equivalent to TType::Byte
+        TType::I16 => Ok(0x04),
+        TType::I32 => Ok(0x05),
+        TType::I64 => Ok(0x06),
+        TType::Double => Ok(0x07),
+        TType::String => Ok(0x08),
+        TType::List => Ok(0x09),
+        TType::Set => Ok(0x0A),
+        TType::Map => Ok(0x0B),
+        TType::Struct => Ok(0x0C),
+        TType::Uuid => Ok(0x0D), // compact uses 0x0D rather than the binary 16
+        unkn => Err(new_protocol_error(
+            ProtocolErrorKind::NotImplemented,
+            format!("unsupported field type {:?} in compact protocol", unkn),
+        )),
+    }
+}
+
+#[inline]
+fn collection_u8_to_type(b: u8) -> crate::Result<TType> {
+    match b {
+        // 0x02 is the type of the spec, 0x01 the one most writers send
+        0x01 | 0x02 => Ok(TType::Bool),
+        o => u8_to_type(o),
+    }
+}
+
+#[inline]
+fn u8_to_type(b: u8) -> crate::Result<TType> {
+    match b {
+        0x00 => Ok(TType::Stop),
+        0x03 => Ok(TType::I08), // equivalent to TType::Byte
+        0x04 => Ok(TType::I16),
+        0x05 => Ok(TType::I32),
+        0x06 => Ok(TType::I64),
+        0x07 => Ok(TType::Double),
+        0x08 => Ok(TType::String),
+        0x09 => Ok(TType::List),
+        0x0A => Ok(TType::Set),
+        0x0B => Ok(TType::Map),
+        0x0C => Ok(TType::Struct),
+        0x0D => Ok(TType::Uuid),
+        unkn => Err(new_protocol_error(
+            ProtocolErrorKind::InvalidData,
+            format!("cannot convert {} into TType", unkn),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::BytesMut;
+
+    use crate::compact::{TCompactInputProtocol, TCompactOutputProtocol};
+    use crate::protocol::{
+        TFieldIdentifier, TInputProtocol, TListIdentifier, TMapIdentifier, TMessageIdentifier,
+        TMessageType, TOutputProtocol, TStructIdentifier, TType,
+    };
+
+    #[test]
+    fn test_write_message_begin() {
+        let mut buf = BytesMut::new();
+        let mut protocol = TCompactOutputProtocol::new(&mut buf);
+        protocol
+            .write_message_begin(&TMessageIdentifier::new("foo", TMessageType::Call, 431))
+            .unwrap();
+
+        let expected: [u8; 8] = [0x82, 0x21, 0xAF, 0x03, 0x03, 0x66, 0x6F, 0x6F];
+        assert_eq!(&buf[..], &expected);
+
+        let mut protocol = TCompactInputProtocol::new(&mut buf);
+        let ident = protocol.read_message_begin().unwrap();
+        assert_eq!(
+            ident,
+            TMessageIdentifier::new("foo", TMessageType::Call, 431)
+        );
+    }
+
+    #[test]
+    fn test_zigzag_varint() {
+        let mut buf = BytesMut::new();
+        let mut protocol = TCompactOutputProtocol::new(&mut buf);
+        protocol.write_i32(0).unwrap();
+        protocol.write_i32(-1).unwrap();
+        protocol.write_i32(1).unwrap();
+        protocol.write_i32(300).unwrap();
+        protocol.write_i64(i64::MIN).unwrap();
+        protocol.write_i16(-64).unwrap();
+
+        let expected: [u8; 16] = [
+            0x00, 0x01, 0x02, 0xD8, 0x04, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+            0x01, 0x7F,
+        ];
+        assert_eq!(&buf[..], &expected);
+
+        let mut protocol = TCompactInputProtocol::new(&mut buf);
+        assert_eq!(protocol.read_i32().unwrap(), 0);
+        assert_eq!(protocol.read_i32().unwrap(), -1);
+        assert_eq!(protocol.read_i32().unwrap(), 1);
+        assert_eq!(protocol.read_i32().unwrap(), 300);
+        assert_eq!(protocol.read_i64().unwrap(), i64::MIN);
+        assert_eq!(protocol.read_i16().unwrap(), -64);
+    }
+
+    #[test]
+    fn test_field_delta_and_nested_struct() {
+        let mut buf = BytesMut::new();
+        let mut protocol = TCompactOutputProtocol::new(&mut buf);
+        protocol
+            .write_struct_begin(&TStructIdentifier::new("outer"))
+            .unwrap();
+        protocol
+            .write_field_begin(&TFieldIdentifier::new("inner", TType::Struct, 1))
+            .unwrap();
+        // nested struct resets the field id delta
+        protocol
+            .write_struct_begin(&TStructIdentifier::new("inner"))
+            .unwrap();
+        protocol
+            .write_field_begin(&TFieldIdentifier::new("a", TType::I32, 3))
+            .unwrap();
+        protocol.write_i32(1).unwrap();
+        protocol.write_field_end().unwrap();
+        protocol.write_field_stop().unwrap();
+        protocol.write_struct_end().unwrap();
+        protocol.write_field_end().unwrap();
+        // delta is computed against the outer struct again
+        protocol
+            .write_field_begin(&TFieldIdentifier::new("b", TType::I64, 2))
+            .unwrap();
+        protocol.write_i64(2).unwrap();
+        protocol.write_field_end().unwrap();
+        // delta larger than 15 uses the long form
+        protocol
+            .write_field_begin(&TFieldIdentifier::new("c", TType::I08, 20))
+            .unwrap();
+        protocol.write_i8(3).unwrap();
+        protocol.write_field_end().unwrap();
+        protocol.write_field_stop().unwrap();
+        protocol.write_struct_end().unwrap();
+
+        let expected: [u8; 10] = [0x1C, 0x35, 0x02, 0x00, 0x16, 0x04, 0x03, 0x28, 0x03, 0x00];
+        assert_eq!(&buf[..], &expected);
+
+        let mut protocol = TCompactInputProtocol::new(&mut buf);
+        protocol.read_struct_begin().unwrap();
+        let field = protocol.read_field_begin().unwrap();
+        assert_eq!((field.field_type, field.id), (TType::Struct, Some(1)));
+        protocol.read_struct_begin().unwrap();
+        let field = protocol.read_field_begin().unwrap();
+        assert_eq!((field.field_type, field.id), (TType::I32, Some(3)));
+        assert_eq!(protocol.read_i32().unwrap(), 1);
+        protocol.read_field_end().unwrap();
+        assert_eq!(protocol.read_field_begin().unwrap().field_type, TType::Stop);
+        protocol.read_struct_end().unwrap();
+        protocol.read_field_end().unwrap();
+        let field = protocol.read_field_begin().unwrap();
+        assert_eq!((field.field_type, field.id), (TType::I64, Some(2)));
+        assert_eq!(protocol.read_i64().unwrap(), 2);
+        let field = protocol.read_field_begin().unwrap();
+        assert_eq!((field.field_type, field.id), (TType::I08, Some(20)));
+        assert_eq!(protocol.read_i8().unwrap(), 3);
+        assert_eq!(protocol.read_field_begin().unwrap().field_type, TType::Stop);
+        protocol.read_struct_end().unwrap();
+    }
+
+    #[test]
+    fn test_field_delta_overflow() {
+        // long form field id 32767, then a short form delta of 1
+        let mut buf = BytesMut::from(&[0x03, 0xFE, 0xFF, 0x03, 0x00, 0x13][..]);
+        let mut protocol = TCompactInputProtocol::new(&mut buf);
+        protocol.read_struct_begin().unwrap();
+        let field = protocol.read_field_begin().unwrap();
+        assert_eq!((field.field_type, field.id), (TType::I08, Some(i16::MAX)));
+        assert_eq!(protocol.read_i8().unwrap(), 0);
+        assert!(protocol.read_field_begin().is_err());
+    }
+
+    #[test]
+    fn test_bool_fields_are_packed() {
+        let mut buf = BytesMut::new();
+        let mut protocol = TCompactOutputProtocol::new(&mut buf);
+        protocol
+            .write_struct_begin(&TStructIdentifier::new("s"))
+            .unwrap();
+        protocol
+            .write_field_begin(&TFieldIdentifier::new("t", TType::Bool, 1))
+            .unwrap();
+        protocol.write_bool(true).unwrap();
+        protocol.write_field_end().unwrap();
+        protocol
+            .write_field_begin(&TFieldIdentifier::new("f", TType::Bool, 2))
+            .unwrap();
+        protocol.write_bool(false).unwrap();
+        protocol.write_field_end().unwrap();
+        protocol.write_field_stop().unwrap();
+        protocol.write_struct_end().unwrap();
+        // bools inside a collection take a whole byte each
+        protocol
+            .write_list_begin(&TListIdentifier::new(TType::Bool, 2))
+            .unwrap();
+        protocol.write_bool(true).unwrap();
+        protocol.write_bool(false).unwrap();
+        protocol.write_list_end().unwrap();
+
+        let expected: [u8; 6] = [0x11, 0x12, 0x00, 0x21, 0x01, 0x02];
+        assert_eq!(&buf[..], &expected);
+
+        let mut protocol = TCompactInputProtocol::new(&mut buf);
+        protocol.read_struct_begin().unwrap();
+        let field = protocol.read_field_begin().unwrap();
+        assert_eq!((field.field_type, field.id), (TType::Bool, Some(1)));
+        assert!(protocol.read_bool().unwrap());
+        let field = protocol.read_field_begin().unwrap();
+        assert_eq!((field.field_type, field.id), (TType::Bool, Some(2)));
+        assert!(!protocol.read_bool().unwrap());
+        assert_eq!(protocol.read_field_begin().unwrap().field_type, TType::Stop);
+        protocol.read_struct_end().unwrap();
+        let list = protocol.read_list_begin().unwrap();
+        assert_eq!(list, TListIdentifier::new(TType::Bool, 2));
+        assert!(protocol.read_bool().unwrap());
+        assert!(!protocol.read_bool().unwrap());
+    }
+
+    #[test]
+    fn test_collection_headers() {
+        let mut buf = BytesMut::new();
+        let mut protocol = TCompactOutputProtocol::new(&mut buf);
+        protocol
+            .write_list_begin(&TListIdentifier::new(TType::I32, 3))
+            .unwrap();
+        protocol
+            .write_list_begin(&TListIdentifier::new(TType::String, 20))
+            .unwrap();
+        protocol
+            .write_map_begin(&TMapIdentifier::new(TType::String, TType::I32, 0))
+            .unwrap();
+        protocol
+            .write_map_begin(&TMapIdentifier::new(TType::String, TType::Struct, 2))
+            .unwrap();
+        protocol.write_string("hi").unwrap();
+        protocol.write_double(1.0).unwrap();
+
+        let expected: [u8; 17] = [
+            0x35, 0xF8, 0x14, 0x00, 0x02, 0x8C, 0x02, 0x68, 0x69, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0xF0, 0x3F,
+        ];
+        assert_eq!(&buf[..], &expected);
+
+        // element counts must fit in the remaining input
+        buf.extend_from_slice(&[0; 20]);
+        let mut protocol = TCompactInputProtocol::new(&mut buf);
+        assert_eq!(
+            protocol.read_list_begin().unwrap(),
+            TListIdentifier::new(TType::I32, 3)
+        );
+        assert_eq!(
+            protocol.read_list_begin().unwrap(),
+            TListIdentifier::new(TType::String, 20)
+        );
+        assert_eq!(
+            protocol.read_map_begin().unwrap(),
+            TMapIdentifier::new(None, None, 0)
+        );
+        assert_eq!(
+            protocol.read_map_begin().unwrap(),
+            TMapIdentifier::new(TType::String, TType::Struct, 2)
+        );
+        assert_eq!(protocol.read_string().unwrap(), "hi");
+        assert_eq!(protocol.read_double().unwrap(), 1.0);
+    }
+
+    #[test]
+    fn test_bool_element_types() {
+        for element_type in [0x01, 0x02] {
+            // one element lists of bool, the element true
+            let mut buf = BytesMut::from(&[0x10 | element_type, 0x01][..]);
+            let mut protocol = TCompactInputProtocol::new(&mut buf);
+            assert_eq!(
+                protocol.read_list_begin().unwrap(),
+                TListIdentifier::new(TType::Bool, 1)
+            );
+            assert!(protocol.read_bool().unwrap());
+            assert!(buf.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_read_binary_zero_copy() {
+        let mut buf = BytesMut::new();
+        let mut protocol = TCompactOutputProtocol::new(&mut buf);
+        protocol.write_bytes(b"blob").unwrap();
+        protocol.write_i32(7).unwrap();
+        let payload = buf[1..].as_ptr();
+
+        let mut protocol = TCompactInputProtocol::new(&mut buf);
+        let binary = protocol.read_binary().unwrap();
+        assert_eq!(&binary[..], b"blob");
+        // the payload is a slice of the input buffer
+        assert_eq!(binary.as_ptr(), payload);
+        assert_eq!(protocol.read_i32().unwrap(), 7);
+    }
+
+    #[test]
+    fn test_uuid_field() {
+        let uuid = [0xAB; 16];
+        let mut buf = BytesMut::new();
+        let mut protocol = TCompactOutputProtocol::new(&mut buf);
+        protocol
+            .write_field_begin(&TFieldIdentifier::new("id", TType::Uuid, 1))
+            .unwrap();
+        protocol.write_uuid(uuid).unwrap();
+        protocol.write_field_end().unwrap();
+        assert_eq!(buf[0], 0x1D);
+        assert_eq!(&buf[1..], &uuid);
+
+        let mut protocol = TCompactInputProtocol::new(&mut buf);
+        let ident = protocol.read_field_begin().unwrap();
+        assert_eq!(ident.field_type, TType::Uuid);
+        assert_eq!(protocol.read_uuid().unwrap(), uuid);
+    }
+
+    #[test]
+    fn test_skip_unknown_fields() {
+        let mut buf = BytesMut::new();
+        let mut protocol = TCompactOutputProtocol::new(&mut buf);
+        protocol
+            .write_struct_begin(&TStructIdentifier::new("s"))
+            .unwrap();
+        protocol
+            .write_field_begin(&TFieldIdentifier::new("flag", TType::Bool, 1))
+            .unwrap();
+        protocol.write_bool(true).unwrap();
+        protocol.write_field_end().unwrap();
+        protocol
+            .write_field_begin(&TFieldIdentifier::new("m", TType::Map, 2))
+            .unwrap();
+        protocol
+            .write_map_begin(&TMapIdentifier::new(TType::I32, TType::Bool, 1))
+            .unwrap();
+        protocol.write_i32(7).unwrap();
+        protocol.write_bool(false).unwrap();
+        protocol.write_map_end().unwrap();
+        protocol.write_field_end().unwrap();
+        protocol.write_field_stop().unwrap();
+        protocol.write_struct_end().unwrap();
+        protocol.write_i32(42).unwrap();
+
+        let mut protocol = TCompactInputProtocol::new(&mut buf);
+        protocol.skip(TType::Struct).unwrap();
+        assert_eq!(protocol.read_i32().unwrap(), 42);
+    }
+}
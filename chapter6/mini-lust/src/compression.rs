@@ -0,0 +1,340 @@
+//! Compression of framed messages.
+//!
+//! A compressed frame keeps the 4-byte length prefix of the framed transport,
+//! its body is:
+//!
+//! ```text
+//! 0xC5 | algorithm: u8 | uncompressed length: u32 | compressed message
+//! ```
+//!
+//! No Thrift protocol starts a message with `0xC5`, so compressed and plain
+//! frames can be told apart and mixed on the same connection.
+
+use std::io::{Read, Write};
+
+use bytes::{Buf, BufMut, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::codec::MAX_MESSAGE_SIZE;
+use crate::{new_protocol_error, ProtocolErrorKind};
+
+const COMPRESSED_MAGIC: u8 = 0xC5;
+// magic, algorithm, uncompressed length
+const COMPRESSED_HEADER_SIZE: usize = 1 + 1 + 4;
+
+/// Compression algorithm of a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Zlib,
+    Zstd,
+    Lz4,
+}
+
+impl Compression {
+    fn id(self) -> u8 {
+        match self {
+            Compression::Zlib => 1,
+            Compression::Zstd => 2,
+            Compression::Lz4 => 3,
+        }
+    }
+
+    fn from_id(id: u8) -> crate::Result<Self> {
+        match id {
+            1 => Ok(Compression::Zlib),
+            2 => Ok(Compression::Zstd),
+            3 => Ok(Compression::Lz4),
+            _ => Err(new_protocol_error(
+                ProtocolErrorKind::NotImplemented,
+                format!("unknown compression algorithm {}", id),
+            )),
+        }
+    }
+
+    fn compress(self, src: &[u8]) -> crate::Result<Vec<u8>> {
+        match self {
+            Compression::Zlib => {
+                let mut encoder =
+                    flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(src)?;
+                Ok(encoder.finish()?)
+            }
+            Compression::Zstd => Ok(zstd::bulk::compress(src, 0)?),
+            Compression::Lz4 => Ok(lz4_flex::block::compress(src)),
+        }
+    }
+
+    fn decompress(self, src: &[u8], size: usize) -> crate::Result<Vec<u8>> {
+        let invalid = |e: String| {
+            new_protocol_error(
+                ProtocolErrorKind::InvalidData,
+                format!("cannot decompress {:?} frame: {}", self, e),
+            )
+        };
+        let output = match self {
+            Compression::Zlib => {
+                let mut output = Vec::with_capacity(size);
+                // Read one byte more than announced to catch lying peers.
+                flate2::read::ZlibDecoder::new(src)
+                    .take(size as u64 + 1)
+                    .read_to_end(&mut output)
+                    .map_err(|e| invalid(e.to_string()))?;
+                output
+            }
+            Compression::Zstd => {
+                zstd::bulk::decompress(src, size).map_err(|e| invalid(e.to_string()))?
+            }
+            Compression::Lz4 => {
+                lz4_flex::block::decompress(src, size).map_err(|e| invalid(e.to_string()))?
+            }
+        };
+        if output.len() != size {
+            return Err(invalid(format!(
+                "{} bytes announced, {} bytes found",
+                size,
+                output.len()
+            )));
+        }
+        Ok(output)
+    }
+}
+
+/// When and how `CompressedCodec` compresses the frames it writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompressionOptions {
+    pub algorithm: Compression,
+    /// Messages shorter than this are sent uncompressed.
+    pub min_size: usize,
+}
+
+impl CompressionOptions {
+    pub fn new(algorithm: Compression, min_size: usize) -> Self {
+        Self {
+            algorithm,
+            min_size,
+        }
+    }
+}
+
+/// Framed transport whose messages are compressed when large enough.
+///
+/// Both compressed and plain frames are read. A codec made with
+/// `CompressedCodec::follow_peer` compresses only once its peer has sent a
+/// compressed frame, with the same algorithm, so that a server can enable
+/// compression before its clients do.
+pub struct CompressedCodec<C> {
+    inner: C,
+    compression: Option<Compression>,
+    min_size: usize,
+    follow_peer: bool,
+    max_frame_length: usize,
+}
+
+impl<C> CompressedCodec<C> {
+    pub fn new(inner: C, options: CompressionOptions) -> Self {
+        CompressedCodec {
+            inner,
+            compression: Some(options.algorithm),
+            min_size: options.min_size,
+            follow_peer: false,
+            max_frame_length: MAX_MESSAGE_SIZE,
+        }
+    }
+
+    /// Compress messages of at least `min_size` bytes, only after the peer
+    /// sent a compressed frame.
+    pub fn follow_peer(inner: C, min_size: usize) -> Self {
+        CompressedCodec {
+            inner,
+            compression: None,
+            min_size,
+            follow_peer: true,
+            max_frame_length: MAX_MESSAGE_SIZE,
+        }
+    }
+
+    /// Refuse to read or write frames, compressed or not, longer than
+    /// `max_frame_length`.
+    pub fn with_max_frame_length(mut self, max_frame_length: usize) -> Self {
+        self.max_frame_length = max_frame_length;
+        self
+    }
+
+    #[inline]
+    fn check_length(&self, length: usize) -> crate::Result<()> {
+        if length > self.max_frame_length {
+            return Err(new_protocol_error(
+                ProtocolErrorKind::SizeLimit,
+                format!("Frame of length {} is too large.", length),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl<C, T> Encoder<T> for CompressedCodec<C>
+where
+    C: Encoder<T>,
+    crate::Error: From<C::Error>,
+{
+    type Error = crate::Error;
+
+    fn encode(&mut self, item: T, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        // Mini-lust note: the compressed length is only known after
+        // compressing, so the message is encoded aside first.
+        let mut message = BytesMut::new();
+        self.inner.encode(item, &mut message)?;
+        self.check_length(message.len())?;
+
+        if let Some(compression) = self.compression {
+            if message.len() >= self.min_size {
+                let compressed = compression.compress(&message)?;
+                // Incompressible data is better sent as is.
+                if COMPRESSED_HEADER_SIZE + compressed.len() < message.len() {
+                    let length = COMPRESSED_HEADER_SIZE + compressed.len();
+                    dst.reserve(4 + length);
+                    dst.put_u32(length as u32);
+                    dst.put_u8(COMPRESSED_MAGIC);
+                    dst.put_u8(compression.id());
+                    dst.put_u32(message.len() as u32);
+                    dst.put_slice(&compressed);
+                    return Ok(());
+                }
+            }
+        }
+
+        dst.reserve(4 + message.len());
+        dst.put_u32(message.len() as u32);
+        dst.put_slice(&message);
+        Ok(())
+    }
+}
+
+impl<C> Decoder for CompressedCodec<C>
+where
+    C: Decoder,
+    crate::Error: From<C::Error>,
+{
+    type Item = C::Item;
+    type Error = crate::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if src.len() < 4 {
+            return Ok(None);
+        }
+        let mut length_bytes = [0u8; 4];
+        length_bytes.copy_from_slice(&src[..4]);
+        let length = u32::from_be_bytes(length_bytes) as usize;
+        self.check_length(length)?;
+        if src.len() < 4 + length {
+            src.reserve(4 + length - src.len());
+            return Ok(None);
+        }
+        src.advance(4);
+        let mut frame = src.split_to(length);
+
+        if frame.first() == Some(&COMPRESSED_MAGIC) {
+            if frame.len() < COMPRESSED_HEADER_SIZE {
+                return Err(new_protocol_error(
+                    ProtocolErrorKind::InvalidData,
+                    "truncated compressed frame header",
+                ));
+            }
+            frame.advance(1);
+            let compression = Compression::from_id(frame.get_u8())?;
+            let size = frame.get_u32() as usize;
+            self.check_length(size)?;
+            frame = BytesMut::from(&compression.decompress(&frame, size)?[..]);
+            if self.follow_peer {
+                self.compression = Some(compression);
+            }
+        }
+
+        match self.inner.decode(&mut frame)? {
+            None => Err(new_protocol_error(
+                ProtocolErrorKind::InvalidData,
+                "unable to decode message which the data size is enough for decoding",
+            )),
+            Some(item) => Ok(Some(item)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::BytesMut;
+    use tokio_util::codec::{Decoder, Encoder};
+
+    use crate::codec::FramedCodec;
+    use crate::compression::{CompressedCodec, Compression, CompressionOptions};
+    use crate::testing::{cx, message_codec};
+
+    #[test]
+    fn test_compressed_round_trip() {
+        let long = "thrift ".repeat(200);
+        for algorithm in [Compression::Zlib, Compression::Zstd, Compression::Lz4] {
+            let options = CompressionOptions::new(algorithm, 64);
+            let mut codec = CompressedCodec::new(message_codec(), options);
+            let mut buf = BytesMut::new();
+
+            codec.encode((cx(), Ok(long.clone())), &mut buf).unwrap();
+            assert_eq!(buf[4], 0xC5);
+            assert!(buf.len() < long.len());
+            // short messages are not compressed
+            let start = buf.len();
+            codec
+                .encode((cx(), Ok("short".to_string())), &mut buf)
+                .unwrap();
+            assert_eq!(buf[start + 4], 0x80);
+
+            let (_, decoded) = codec.decode(&mut buf).unwrap().unwrap();
+            assert_eq!(decoded.unwrap(), long);
+            let (_, decoded) = codec.decode(&mut buf).unwrap().unwrap();
+            assert_eq!(decoded.unwrap(), "short");
+            assert!(buf.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_follow_peer() {
+        let long = "thrift ".repeat(200);
+        let mut server = CompressedCodec::follow_peer(message_codec(), 64);
+
+        // a plain framed client gets plain replies
+        let mut client = FramedCodec::new(message_codec());
+        let mut buf = BytesMut::new();
+        client.encode((cx(), Ok(long.clone())), &mut buf).unwrap();
+        let (cx, req) = server.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(req.unwrap(), long);
+        server.encode((cx, Ok(long.clone())), &mut buf).unwrap();
+        let (_, resp) = client.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(resp.unwrap(), long);
+
+        // a compressing client gets compressed replies
+        let options = CompressionOptions::new(Compression::Lz4, 64);
+        let mut client = CompressedCodec::new(message_codec(), options);
+        client
+            .encode((self::cx(), Ok(long.clone())), &mut buf)
+            .unwrap();
+        let (cx, req) = server.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(req.unwrap(), long);
+        server.encode((cx, Ok(long.clone())), &mut buf).unwrap();
+        assert_eq!(&buf[4..6], &[0xC5, 3]);
+        let (_, resp) = client.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(resp.unwrap(), long);
+    }
+
+    #[test]
+    fn test_decompressed_size_limit() {
+        let long = "thrift ".repeat(200);
+        let options = CompressionOptions::new(Compression::Zstd, 64);
+        let mut client = CompressedCodec::new(message_codec(), options);
+        let mut buf = BytesMut::new();
+        client.encode((cx(), Ok(long)), &mut buf).unwrap();
+
+        let mut server =
+            CompressedCodec::follow_peer(message_codec(), 64).with_max_frame_length(512);
+        assert!(buf.len() < 512);
+        assert!(server.decode(&mut buf).is_err());
+    }
+}
@@ -2,13 +2,19 @@ use std::io;
 use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::Arc;
 use std::task::{Context, Poll};
+use std::time::Duration;
 
 use futures_util::FutureExt;
 use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 use tower::Service;
 
+use crate::memory;
+use crate::resolve::{CachingResolver, Resolve};
 use crate::utils::BoxFuture;
+use crate::{new_transport_error, TransportErrorKind};
 
 pub trait Io: AsyncWrite + AsyncRead + Send + 'static {}
 
@@ -50,14 +56,84 @@ impl AsyncWrite for BoxedIo {
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct DefaultMakeConnection;
+/// Opens TCP, unix socket and in-memory connections.
+///
+/// Host name targets are resolved when connecting, see `CachingResolver`.
+#[derive(Debug, Clone, Default)]
+pub struct DefaultMakeConnection {
+    resolver: CachingResolver,
+}
+
+impl DefaultMakeConnection {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Resolve host names with `resolver`, again once their addresses are
+    /// older than `re_resolve_interval`.
+    pub fn with_resolver(resolver: Arc<dyn Resolve>, re_resolve_interval: Duration) -> Self {
+        Self {
+            resolver: CachingResolver::new(resolver, re_resolve_interval),
+        }
+    }
+}
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
 pub enum SocketOrUnix {
     Socket(SocketAddr),
+    /// A host name and a port, resolved when connecting. Connections are
+    /// spread across all the addresses of the name.
+    Host(String, u16),
+    /// A unix socket, or on Linux an abstract one when the path starts with a
+    /// NUL byte, see `SocketOrUnix::abstract_unix`.
     #[cfg(unix)]
     Unix(PathBuf),
+    /// A server of the same process listening on `MemoryAddr::new(name)`.
+    Memory(String),
+}
+
+#[cfg(target_os = "linux")]
+impl SocketOrUnix {
+    /// The Linux abstract unix socket `name`, which has no file.
+    pub fn abstract_unix(name: &str) -> Self {
+        SocketOrUnix::Unix(abstract_unix_path(name))
+    }
+}
+
+/// Path of the Linux abstract unix socket `name`, for servers to listen on.
+#[cfg(target_os = "linux")]
+pub fn abstract_unix_path(name: &str) -> PathBuf {
+    PathBuf::from(format!("\0{}", name))
+}
+
+/// Parse an endpoint: `127.0.0.1:9090` or `[::1]:9090`, `host.name:9090`,
+/// `unix:/path/to/socket` or `memory:name`.
+impl FromStr for SocketOrUnix {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        #[cfg(unix)]
+        if let Some(path) = s.strip_prefix("unix:") {
+            return Ok(SocketOrUnix::Unix(PathBuf::from(path)));
+        }
+        if let Some(name) = s.strip_prefix("memory:") {
+            return Ok(SocketOrUnix::Memory(name.to_string()));
+        }
+        if let Ok(addr) = s.parse() {
+            return Ok(SocketOrUnix::Socket(addr));
+        }
+        if let Some((host, port)) = s.rsplit_once(':') {
+            // IPv6 addresses are bracketed, a bare one is not a host name.
+            let port = port.parse();
+            if let (false, Ok(port)) = (host.is_empty() || host.contains(':'), port) {
+                return Ok(SocketOrUnix::Host(host.to_string(), port));
+            }
+        }
+        Err(new_transport_error(
+            TransportErrorKind::Unknown,
+            format!("invalid endpoint {:?}", s),
+        ))
+    }
 }
 
 impl Service<SocketAddr> for DefaultMakeConnection {
@@ -111,6 +187,102 @@ impl Service<SocketOrUnix> for DefaultMakeConnection {
                     r.map(BoxedIo::new)
                 }))
             }
+            SocketOrUnix::Host(host, port) => {
+                let resolver = self.resolver.clone();
+                Box::pin(async move {
+                    let addr = resolver.next_addr(&host, port).await?;
+                    let conn = tokio::net::TcpStream::connect(addr).await?;
+                    Ok(BoxedIo::new(conn))
+                })
+            }
+            SocketOrUnix::Memory(name) => {
+                Box::pin(async move { memory::connect(&name).map(BoxedIo::new) })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use tower::Service;
+
+    use crate::connection::{DefaultMakeConnection, SocketOrUnix};
+    use crate::resolve::Resolve;
+    use crate::utils::BoxFuture;
+
+    struct StubResolver(Vec<SocketAddr>);
+
+    impl Resolve for StubResolver {
+        fn resolve(&self, _host: &str, _port: u16) -> BoxFuture<Vec<SocketAddr>, io::Error> {
+            let addrs = self.0.clone();
+            Box::pin(async move { Ok(addrs) })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_host_connections_spread() {
+        let first = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let second = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let resolver = StubResolver(vec![
+            first.local_addr().unwrap(),
+            second.local_addr().unwrap(),
+        ]);
+        let mut make_connection =
+            DefaultMakeConnection::with_resolver(Arc::new(resolver), Duration::from_secs(60));
+
+        let target = SocketOrUnix::Host("thrift.example".to_string(), 9090);
+        for listener in [&first, &second, &first] {
+            let conn = make_connection.call(target.clone());
+            let (conn, accepted) = tokio::join!(conn, listener.accept());
+            conn.unwrap();
+            accepted.unwrap();
         }
     }
+
+    #[test]
+    fn test_parse_endpoints() {
+        let parse = |s: &str| s.parse::<SocketOrUnix>().ok();
+        assert_eq!(
+            parse("127.0.0.1:9090"),
+            Some(SocketOrUnix::Socket("127.0.0.1:9090".parse().unwrap()))
+        );
+        assert_eq!(
+            parse("[::1]:9090"),
+            Some(SocketOrUnix::Socket("[::1]:9090".parse().unwrap()))
+        );
+        assert_eq!(
+            parse("thrift.example:9090"),
+            Some(SocketOrUnix::Host("thrift.example".to_string(), 9090))
+        );
+        #[cfg(unix)]
+        assert_eq!(
+            parse("unix:/tmp/thrift.sock"),
+            Some(SocketOrUnix::Unix("/tmp/thrift.sock".into()))
+        );
+        assert_eq!(
+            parse("memory:thrift"),
+            Some(SocketOrUnix::Memory("thrift".to_string()))
+        );
+        for invalid in ["thrift.example", ":9090", "thrift.example:port", "::1:9090"] {
+            assert_eq!(parse(invalid), None);
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_abstract_unix() {
+        let name = format!("mini-lust-test-{}", std::process::id());
+        let listener =
+            tokio::net::UnixListener::bind(crate::connection::abstract_unix_path(&name)).unwrap();
+        let mut make_connection = DefaultMakeConnection::new();
+        let conn = make_connection.call(SocketOrUnix::abstract_unix(&name));
+        let (conn, accepted) = tokio::join!(conn, listener.accept());
+        conn.unwrap();
+        accepted.unwrap();
+    }
 }
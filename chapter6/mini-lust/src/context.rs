@@ -1,5 +1,9 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
 use crate::protocol::TMessageIdentifier;
 use crate::connection::SocketOrUnix;
+use crate::tls::PeerIdentity;
 
 /// MsgContext can only be used across our framework and middleware.
 #[derive(Clone, Debug, Default, Eq, PartialEq)]
@@ -8,4 +12,12 @@ pub struct MsgContext {
     pub identifier: TMessageIdentifier,
     /// target
     pub target: Option<SocketOrUnix>,
+    /// Key/value metadata sent along with the message, carried by THeader
+    /// info headers.
+    pub headers: HashMap<String, String>,
+    /// Verified certificate of the client, set by servers accepting TLS
+    /// connections with client authentication.
+    pub peer_identity: Option<Arc<PeerIdentity>>,
+    /// Key picking the endpoint of the call with `Balance::ConsistentHash`.
+    pub hash_key: Option<u64>,
 }
@@ -0,0 +1,245 @@
+//! Server side detection of the framing and protocol spoken by a client.
+//!
+//! The first bytes of a connection tell them apart:
+//! - `0x80 0x01`: unframed strict binary
+//! - `0x82`: unframed compact
+//! - otherwise a 4-byte frame length comes first, followed by
+//!   - `0x0F 0xFF`: THeader, its protocol id is read from the header
+//!   - `0x80 0x01`: framed strict binary
+//!   - `0x82`: framed compact
+//!   - `0x00`: framed non-strict binary, the high byte of the name length
+//!   - anything else: unframed non-strict binary, the 4 bytes were the length
+//!     of the method name which follows, then a call or oneway message type
+//!
+//! Frame lengths starting with `0x80` or `0x82` would exceed the max message
+//! size, so they cannot be confused with the unframed protocols.
+
+use std::convert::TryFrom;
+use std::sync::Arc;
+
+use bytes::BytesMut;
+
+use crate::binary::TBinaryProtocolFactory;
+use crate::codec::{DefaultCodec, FramedCodec, MessageCodec, UnframedCodec};
+use crate::compact::TCompactProtocolFactory;
+use crate::header::{
+    THeaderCodec, HEADER_MAGIC, HEADER_PROTOCOL_BINARY, HEADER_PROTOCOL_COMPACT,
+    HEADER_PROTOCOL_JSON,
+};
+use crate::json::TJSONProtocolFactory;
+use crate::protocol::{TMessageType, TProtocolFactory};
+use crate::{new_protocol_error, ProtocolErrorKind};
+
+const BINARY_VERSION_1: [u8; 2] = [0x80, 0x01];
+const COMPACT_PROTOCOL_ID: u8 = 0x82;
+
+// length, magic, flags, sequence number, header size, protocol id
+const HEADER_PROTOCOL_ID_OFFSET: usize = 4 + 2 + 2 + 4 + 2;
+
+/// Pick the codec matching the first bytes of `src`, `None` if more bytes are
+/// needed to tell. The picked codec is configured like `configured` apart from
+/// its protocol, and reads frames up to `max_frame_length` long.
+pub(crate) fn detect_codec<E, D>(
+    src: &BytesMut,
+    configured: &MessageCodec<E, D>,
+    max_frame_length: usize,
+) -> crate::Result<Option<DefaultCodec<E, D>>> {
+    let codec = |protocol: Arc<dyn TProtocolFactory>| configured.clone_with_protocol(protocol);
+    // Old clients may not understand strict replies, so we answer the way
+    // they ask.
+    let strict_binary = || Arc::new(TBinaryProtocolFactory::with_strictness(false, true));
+    let non_strict_binary = || Arc::new(TBinaryProtocolFactory::with_strictness(false, false));
+    let compact = || Arc::new(TCompactProtocolFactory::new());
+
+    if src.is_empty() {
+        return Ok(None);
+    }
+    if src[0] == COMPACT_PROTOCOL_ID {
+        return Ok(Some(DefaultCodec::Unframed(
+            UnframedCodec::new(codec(compact())).with_max_frame_length(max_frame_length),
+        )));
+    }
+    if src[0] == BINARY_VERSION_1[0] {
+        if src.len() < 2 {
+            return Ok(None);
+        }
+        if src[1] != BINARY_VERSION_1[1] {
+            return Err(new_protocol_error(
+                ProtocolErrorKind::BadVersion,
+                format!("unknown binary protocol version {:#04x}", src[1]),
+            ));
+        }
+        return Ok(Some(DefaultCodec::Unframed(
+            UnframedCodec::new(codec(strict_binary())).with_max_frame_length(max_frame_length),
+        )));
+    }
+
+    if src.len() < 6 {
+        return Ok(None);
+    }
+    let framed = |protocol: Arc<dyn TProtocolFactory>| {
+        Some(DefaultCodec::Framed(
+            FramedCodec::new(codec(protocol)).with_max_frame_length(max_frame_length),
+        ))
+    };
+    if src[4..6] == HEADER_MAGIC.to_be_bytes() {
+        if src.len() <= HEADER_PROTOCOL_ID_OFFSET {
+            return Ok(None);
+        }
+        // Protocol ids fit in a single varint byte.
+        let protocol_id = src[HEADER_PROTOCOL_ID_OFFSET] as i32;
+        let protocol: Arc<dyn TProtocolFactory> = match protocol_id {
+            HEADER_PROTOCOL_BINARY => strict_binary(),
+            HEADER_PROTOCOL_JSON => Arc::new(TJSONProtocolFactory::new()),
+            HEADER_PROTOCOL_COMPACT => compact(),
+            _ => {
+                return Err(new_protocol_error(
+                    ProtocolErrorKind::NotImplemented,
+                    format!("unexpected THeader protocol id {}", protocol_id),
+                ))
+            }
+        };
+        let header_codec = THeaderCodec::new(codec(protocol), Some(protocol_id))
+            .with_max_frame_length(max_frame_length);
+        return Ok(Some(DefaultCodec::Header(header_codec)));
+    }
+    if src[4..6] == BINARY_VERSION_1 {
+        return Ok(framed(strict_binary()));
+    }
+    if src[4] == COMPACT_PROTOCOL_ID {
+        return Ok(framed(compact()));
+    }
+    if src[4] == 0 {
+        return Ok(framed(non_strict_binary()));
+    }
+
+    let name_length = u32::from_be_bytes([src[0], src[1], src[2], src[3]]) as usize;
+    if name_length > max_frame_length {
+        return Err(unknown_protocol());
+    }
+    if src.len() <= 4 + name_length {
+        return Ok(None);
+    }
+    let name_is_plausible = src[4..4 + name_length].iter().all(u8::is_ascii_graphic);
+    let is_request = matches!(
+        TMessageType::try_from(src[4 + name_length]),
+        Ok(TMessageType::Call) | Ok(TMessageType::OneWay)
+    );
+    if !name_is_plausible || !is_request {
+        return Err(unknown_protocol());
+    }
+    Ok(Some(DefaultCodec::Unframed(
+        UnframedCodec::new(codec(non_strict_binary())).with_max_frame_length(max_frame_length),
+    )))
+}
+
+fn unknown_protocol() -> crate::Error {
+    new_protocol_error(
+        ProtocolErrorKind::InvalidData,
+        "unable to detect the framing and protocol of the client",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use bytes::BytesMut;
+    use tokio_util::codec::{Decoder, Encoder};
+
+    use crate::binary::TBinaryProtocolFactory;
+    use crate::codec::{DefaultCodec, FramedCodec, MessageCodec, UnframedCodec, MAX_MESSAGE_SIZE};
+    use crate::compact::TCompactProtocolFactory;
+    use crate::context::MsgContext;
+    use crate::header::{THeaderCodec, HEADER_PROTOCOL_BINARY, HEADER_PROTOCOL_COMPACT};
+    use crate::protocol::{TMessageIdentifier, TMessageType, TProtocolFactory};
+
+    fn message_codec<P: TProtocolFactory + 'static>(protocol: P) -> MessageCodec<i32, i32> {
+        MessageCodec::new(Arc::new(protocol))
+    }
+
+    fn detecting() -> DefaultCodec<i32, i32> {
+        DefaultCodec::Detecting {
+            codec: message_codec(TBinaryProtocolFactory::default()),
+            max_frame_length: MAX_MESSAGE_SIZE,
+        }
+    }
+
+    #[test]
+    fn test_detect_clients() {
+        let strict = || TBinaryProtocolFactory::new(true);
+        let clients = vec![
+            DefaultCodec::Framed(FramedCodec::new(message_codec(strict()))),
+            DefaultCodec::Framed(FramedCodec::new(message_codec(
+                TBinaryProtocolFactory::new(false),
+            ))),
+            DefaultCodec::Framed(FramedCodec::new(message_codec(
+                TCompactProtocolFactory::new(),
+            ))),
+            DefaultCodec::Unframed(UnframedCodec::new(message_codec(strict()))),
+            DefaultCodec::Unframed(UnframedCodec::new(message_codec(
+                TBinaryProtocolFactory::new(false),
+            ))),
+            DefaultCodec::Unframed(UnframedCodec::new(message_codec(
+                TCompactProtocolFactory::new(),
+            ))),
+            DefaultCodec::Header(THeaderCodec::new(
+                message_codec(strict()),
+                Some(HEADER_PROTOCOL_BINARY),
+            )),
+            DefaultCodec::Header(THeaderCodec::new(
+                message_codec(TCompactProtocolFactory::new()),
+                Some(HEADER_PROTOCOL_COMPACT),
+            )),
+        ];
+
+        for (i, mut client) in clients.into_iter().enumerate() {
+            let cx = MsgContext {
+                identifier: TMessageIdentifier::new("ping", TMessageType::Call, i as i32),
+                ..MsgContext::default()
+            };
+            let mut request = BytesMut::new();
+            client.encode((cx, Ok(7)), &mut request).unwrap();
+
+            // the request arrives byte by byte
+            let mut server = detecting();
+            let mut input = BytesMut::new();
+            let mut decoded = None;
+            for b in request.iter() {
+                assert!(decoded.is_none(), "client {} decoded too early", i);
+                input.extend_from_slice(&[*b]);
+                decoded = server.decode(&mut input).unwrap();
+            }
+            let (cx, req) = decoded.expect("request should be decoded");
+            assert_eq!(req.unwrap(), 7, "client {}", i);
+            assert!(input.is_empty());
+
+            // the reply speaks the same framing and protocol
+            let mut reply = BytesMut::new();
+            server.encode((cx, Ok(8)), &mut reply).unwrap();
+            let (cx, resp) = client.decode(&mut reply).unwrap().unwrap();
+            assert_eq!(cx.identifier.sequence_number, i as i32);
+            assert_eq!(resp.unwrap(), 8, "client {}", i);
+            assert!(reply.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_detect_bad_version() {
+        let mut server = detecting();
+        let mut input = BytesMut::from(&[0x80, 0x02, 0x00, 0x01][..]);
+        assert!(server.decode(&mut input).is_err());
+    }
+
+    #[test]
+    fn test_detect_unknown() {
+        // a name length followed by no plausible name
+        let mut server = detecting();
+        let mut input = BytesMut::from(&[0x00, 0x00, 0x00, 0x02, 0x01, 0x7F, 0x01][..]);
+        assert!(server.decode(&mut input).is_err());
+        // or by no call message type
+        let mut server = detecting();
+        let mut input = BytesMut::from(&b"\x00\x00\x00\x02ab\x02"[..]);
+        assert!(server.decode(&mut input).is_err());
+    }
+}
@@ -0,0 +1,486 @@
+//! Endpoints of a client found by service discovery.
+//!
+//! A discovery source is a `tower::discover::Discover` of endpoints: a stream
+//! of `Change::Insert(key, endpoint)` and `Change::Remove(key)`. The client
+//! balances its calls over the endpoints inserted, opening connections to an
+//! endpoint on the first calls to it and closing them once it is removed.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::future::Future;
+use std::hash::Hash;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, Weak};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::future::poll_fn;
+use futures::{ready, Stream};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::{Instant, Sleep};
+use tower::discover::{Change, Discover};
+
+use crate::balance::{Balancer, Picked};
+use crate::connection::SocketOrUnix;
+use crate::pool::Close;
+use crate::{new_transport_error, TransportErrorKind};
+
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Discover the given endpoints once and for all.
+#[derive(Debug, Clone)]
+pub struct StaticList {
+    endpoints: VecDeque<SocketOrUnix>,
+}
+
+impl StaticList {
+    pub fn new(endpoints: impl IntoIterator<Item = SocketOrUnix>) -> Self {
+        Self {
+            endpoints: endpoints.into_iter().collect(),
+        }
+    }
+}
+
+impl Stream for StaticList {
+    type Item = Result<Change<SocketOrUnix, SocketOrUnix>, crate::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let endpoint = self.endpoints.pop_front();
+        Poll::Ready(endpoint.map(|endpoint| Ok(Change::Insert(endpoint.clone(), endpoint))))
+    }
+}
+
+/// Discover the endpoints listed in a local file, polled for changes.
+///
+/// A `.toml` file lists them as `endpoints = ["127.0.0.1:9090"]`, any other
+/// file is JSON: `{"endpoints": ["127.0.0.1:9090"]}` or a bare array. See
+/// `SocketOrUnix::from_str` for the endpoints. A file that cannot be read or
+/// parsed leaves the endpoints as they were.
+pub struct EndpointsFile {
+    path: PathBuf,
+    interval: Duration,
+    sleep: Option<Pin<Box<Sleep>>>,
+    contents: Option<String>,
+    endpoints: HashSet<SocketOrUnix>,
+    changes: VecDeque<Change<SocketOrUnix, SocketOrUnix>>,
+}
+
+impl EndpointsFile {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            interval: DEFAULT_POLL_INTERVAL,
+            sleep: None,
+            contents: None,
+            endpoints: HashSet::new(),
+            changes: VecDeque::new(),
+        }
+    }
+
+    /// Read the file again after `interval`, 1 second by default.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    fn reload(&mut self) {
+        // Mini-lust note: the file is small and local, it is read in place
+        // rather than on a blocking thread.
+        let contents = match std::fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(e) => {
+                log::warn!("unable to read endpoints {}: {}", self.path.display(), e);
+                return;
+            }
+        };
+        if self.contents.as_ref() == Some(&contents) {
+            return;
+        }
+        let endpoints = match parse_endpoints(&self.path, &contents) {
+            Ok(endpoints) => endpoints,
+            Err(e) => {
+                log::warn!("invalid endpoints {}: {}", self.path.display(), e);
+                return;
+            }
+        };
+        self.contents = Some(contents);
+        // Insert first, so that calls always have somewhere to go.
+        for endpoint in endpoints.difference(&self.endpoints) {
+            let change = Change::Insert(endpoint.clone(), endpoint.clone());
+            self.changes.push_back(change);
+        }
+        for endpoint in self.endpoints.difference(&endpoints) {
+            self.changes.push_back(Change::Remove(endpoint.clone()));
+        }
+        self.endpoints = endpoints;
+    }
+}
+
+impl Stream for EndpointsFile {
+    type Item = Result<Change<SocketOrUnix, SocketOrUnix>, crate::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            if let Some(change) = self.changes.pop_front() {
+                return Poll::Ready(Some(Ok(change)));
+            }
+            let deadline = Instant::now() + self.interval;
+            match &mut self.sleep {
+                Some(sleep) => {
+                    ready!(sleep.as_mut().poll(cx));
+                    sleep.as_mut().reset(deadline);
+                }
+                None => self.sleep = Some(Box::pin(tokio::time::sleep_until(deadline))),
+            }
+            self.reload();
+        }
+    }
+}
+
+impl fmt::Debug for EndpointsFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EndpointsFile")
+            .field("path", &self.path)
+            .field("interval", &self.interval)
+            .field("endpoints", &self.endpoints)
+            .finish()
+    }
+}
+
+fn parse_endpoints(path: &Path, contents: &str) -> crate::Result<HashSet<SocketOrUnix>> {
+    let invalid =
+        |e: &dyn fmt::Display| new_transport_error(TransportErrorKind::Unknown, format!("{}", e));
+    let endpoints: Vec<String> = if path.extension().and_then(|ext| ext.to_str()) == Some("toml") {
+        let value: toml::Value = toml::from_str(contents).map_err(|e| invalid(&e))?;
+        match value
+            .get("endpoints")
+            .and_then(|endpoints| endpoints.as_array())
+        {
+            Some(endpoints) => endpoints
+                .iter()
+                .map(|endpoint| endpoint.as_str().map(str::to_string))
+                .collect::<Option<_>>(),
+            None => None,
+        }
+    } else {
+        let value: serde_json::Value = serde_json::from_str(contents).map_err(|e| invalid(&e))?;
+        match value.get("endpoints").unwrap_or(&value).as_array() {
+            Some(endpoints) => endpoints
+                .iter()
+                .map(|endpoint| endpoint.as_str().map(str::to_string))
+                .collect::<Option<_>>(),
+            None => None,
+        }
+    }
+    .ok_or_else(|| invalid(&"expected a list of endpoints"))?;
+    endpoints.iter().map(|endpoint| endpoint.parse()).collect()
+}
+
+type Driver = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+type StartDriver =
+    Box<dyn FnOnce(Weak<Endpoints>, Arc<dyn Close>, watch::Sender<bool>) -> Driver + Send>;
+
+/// A discovery source, started once the client is built.
+pub(crate) struct Discovery(StartDriver);
+
+impl Discovery {
+    pub(crate) fn new<D>(discover: D) -> Self
+    where
+        D: Discover<Service = SocketOrUnix> + Send + 'static,
+        D::Key: Hash + Send,
+        D::Error: fmt::Display,
+    {
+        Self(Box::new(|endpoints, connections, discovered| {
+            Box::pin(drive(discover, endpoints, connections, discovered))
+        }))
+    }
+}
+
+/// Apply the changes of `discover` to `endpoints` while they are used.
+async fn drive<D>(
+    discover: D,
+    endpoints: Weak<Endpoints>,
+    connections: Arc<dyn Close>,
+    discovered: watch::Sender<bool>,
+) where
+    D: Discover<Service = SocketOrUnix>,
+    D::Key: Hash,
+    D::Error: fmt::Display,
+{
+    futures::pin_mut!(discover);
+    let mut keys = HashMap::new();
+    loop {
+        let change = poll_fn(|cx| {
+            let change = discover.as_mut().poll_discover(cx);
+            // Calls wait for the changes ready at once, those of a static list
+            // or of the first read of a file.
+            if change.is_pending() && !*discovered.borrow() {
+                let _ = discovered.send(true);
+            }
+            change
+        })
+        .await;
+        let endpoints = match endpoints.upgrade() {
+            Some(endpoints) => endpoints,
+            None => return,
+        };
+        match change {
+            Some(Ok(Change::Insert(key, endpoint))) => {
+                log::info!("endpoint {:?} discovered", endpoint);
+                endpoints.insert(endpoint.clone());
+                if let Some(replaced) = keys.insert(key, endpoint) {
+                    endpoints.remove(&replaced, &*connections);
+                }
+            }
+            Some(Ok(Change::Remove(key))) => {
+                if let Some(removed) = keys.remove(&key) {
+                    log::info!("endpoint {:?} removed", removed);
+                    endpoints.remove(&removed, &*connections);
+                }
+            }
+            Some(Err(e)) => log::warn!("service discovery failed: {}", e),
+            None => {
+                let _ = discovered.send(true);
+                return;
+            }
+        }
+    }
+}
+
+/// The endpoints discovered so far.
+pub(crate) struct Endpoints {
+    balancer: Mutex<Balancer>,
+    discovered: watch::Receiver<bool>,
+    driver: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Endpoints {
+    /// Start discovering endpoints with `discovery` for `balancer`, closing
+    /// the connections of those removed with `connections`.
+    pub(crate) fn start(
+        discovery: Discovery,
+        balancer: Balancer,
+        connections: Arc<dyn Close>,
+    ) -> Arc<Self> {
+        let (discovered_tx, discovered) = watch::channel(false);
+        let endpoints = Arc::new(Self {
+            balancer: Mutex::new(balancer),
+            discovered,
+            driver: Mutex::new(None),
+        });
+        let driver = (discovery.0)(Arc::downgrade(&endpoints), connections, discovered_tx);
+        *endpoints.driver.lock().unwrap() = Some(tokio::spawn(driver));
+        endpoints
+    }
+
+    fn insert(&self, endpoint: SocketOrUnix) {
+        self.balancer.lock().unwrap().insert(endpoint);
+    }
+
+    /// Remove `endpoint` once, closing its connections when it is gone.
+    fn remove(&self, endpoint: &SocketOrUnix, connections: &dyn Close) {
+        let gone = self.balancer.lock().unwrap().remove(endpoint);
+        if gone {
+            connections.close_target(endpoint);
+        }
+    }
+
+    /// The endpoint of a call keyed `hash_key`, once the first endpoints are
+    /// discovered.
+    pub(crate) async fn pick(&self, hash_key: Option<u64>) -> crate::Result<Picked> {
+        let mut discovered = self.discovered.clone();
+        while !*discovered.borrow() {
+            if discovered.changed().await.is_err() {
+                break;
+            }
+        }
+        self.balancer.lock().unwrap().pick(hash_key)
+    }
+
+    /// Stop discovering endpoints.
+    pub(crate) fn stop(&self) {
+        if let Some(driver) = self.driver.lock().unwrap().take() {
+            driver.abort();
+        }
+    }
+}
+
+impl Drop for Endpoints {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::convert::Infallible;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use futures::{SinkExt, StreamExt, TryStreamExt};
+    use tokio_util::codec::Framed;
+    use tower::discover::Change;
+
+    use crate::codec::MakeCodec;
+    use crate::connection::SocketOrUnix;
+    use crate::discover::{EndpointsFile, StaticList};
+    use crate::memory::MemoryAddr;
+    use crate::protocol::TMessageType;
+    use crate::server::Listenable;
+    use crate::{Client, ClientBuilder, DefaultMakeCodec, TransportErrorKind};
+
+    fn memory(name: &str) -> SocketOrUnix {
+        SocketOrUnix::Memory(name.to_string())
+    }
+
+    async fn changes(file: &mut EndpointsFile) -> Vec<Change<SocketOrUnix, SocketOrUnix>> {
+        let mut changes = Vec::new();
+        while let Ok(Some(change)) =
+            tokio::time::timeout(Duration::from_millis(50), file.next()).await
+        {
+            changes.push(change.unwrap());
+        }
+        changes
+    }
+
+    fn inserted(changes: &[Change<SocketOrUnix, SocketOrUnix>]) -> HashSet<SocketOrUnix> {
+        changes
+            .iter()
+            .filter_map(|change| match change {
+                Change::Insert(_, endpoint) => Some(endpoint.clone()),
+                Change::Remove(_) => None,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_endpoints_file() {
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("mini-lust-endpoints-{}.json", std::process::id()));
+        std::fs::write(&path, r#"["memory:a", "memory:b"]"#).unwrap();
+        let mut file = EndpointsFile::new(&path).interval(Duration::from_millis(5));
+        let first = changes(&mut file).await;
+        assert_eq!(first.len(), 2);
+        assert_eq!(inserted(&first), [memory("a"), memory("b")].into());
+
+        std::fs::write(&path, r#"{"endpoints": ["memory:b", "memory:c"]}"#).unwrap();
+        let second = changes(&mut file).await;
+        assert_eq!(second.len(), 2);
+        assert!(matches!(&second[0], Change::Insert(_, c) if *c == memory("c")));
+        assert!(matches!(&second[1], Change::Remove(a) if *a == memory("a")));
+
+        // invalid contents are ignored
+        for contents in ["[\"memory:b\"", "[\"b\"]", "{}"] {
+            std::fs::write(&path, contents).unwrap();
+            assert!(changes(&mut file).await.is_empty());
+        }
+        std::fs::remove_file(&path).unwrap();
+        assert!(changes(&mut file).await.is_empty());
+
+        let path = dir.join(format!("mini-lust-endpoints-{}.toml", std::process::id()));
+        std::fs::write(&path, "endpoints = [\"memory:a\", \"127.0.0.1:9090\"]").unwrap();
+        let mut file = EndpointsFile::new(&path);
+        let endpoints = [memory("a"), "127.0.0.1:9090".parse().unwrap()];
+        assert_eq!(inserted(&changes(&mut file).await), endpoints.into());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    /// Replies to every call with `name`, counting the open connections.
+    async fn serve(name: &'static str) -> Arc<AtomicUsize> {
+        let mut listener = MemoryAddr::new(name).bind().await.unwrap();
+        let open = Arc::new(AtomicUsize::new(0));
+        let counter = open.clone();
+        tokio::spawn(async move {
+            while let Some(conn) = listener.next().await {
+                let codec = DefaultMakeCodec::<String, String>::new().make_codec();
+                let mut conn = Framed::new(conn.unwrap(), codec);
+                let open = counter.clone();
+                open.fetch_add(1, Ordering::SeqCst);
+                tokio::spawn(async move {
+                    while let Ok(Some((mut cx, _))) = conn.try_next().await {
+                        cx.identifier.message_type = TMessageType::Reply;
+                        if conn.send((cx, Ok(name.to_string()))).await.is_err() {
+                            break;
+                        }
+                    }
+                    open.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+        });
+        open
+    }
+
+    async fn call(client: &mut Client<String, String>) -> crate::Result<String> {
+        client.call("name", String::new()).await
+    }
+
+    async fn wait_until(condition: impl Fn() -> bool) {
+        let wait = async {
+            while !condition() {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(1), wait)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_client_follows_endpoints() {
+        let open_a = serve("discover-a").await;
+        let open_b = serve("discover-b").await;
+
+        let endpoints = StaticList::new([memory("discover-a"), memory("discover-b")]);
+        let mut client: Client<String, String> = ClientBuilder::discover(endpoints).build();
+        let mut names = HashSet::new();
+        for _ in 0..2 {
+            names.insert(call(&mut client).await.unwrap());
+        }
+        assert_eq!(
+            names,
+            ["discover-a".to_string(), "discover-b".to_string()].into()
+        );
+        client.close();
+        wait_until(|| open_a.load(Ordering::SeqCst) + open_b.load(Ordering::SeqCst) == 0).await;
+
+        let (changes, discover) = futures::channel::mpsc::unbounded();
+        let insert = |name: &'static str| Ok::<_, Infallible>(Change::Insert(name, memory(name)));
+        let remove = |name: &'static str| Ok::<_, Infallible>(Change::Remove(name));
+        let mut client: Client<String, String> = ClientBuilder::discover(discover).build();
+        changes.unbounded_send(insert("discover-a")).unwrap();
+        assert_eq!(call(&mut client).await.unwrap(), "discover-a");
+
+        changes.unbounded_send(insert("discover-b")).unwrap();
+        while call(&mut client).await.unwrap() != "discover-b" {}
+        assert_eq!(call(&mut client).await.unwrap(), "discover-a");
+
+        // the connections to removed endpoints are closed
+        changes.unbounded_send(remove("discover-a")).unwrap();
+        wait_until(|| open_a.load(Ordering::SeqCst) == 0).await;
+        for _ in 0..3 {
+            assert_eq!(call(&mut client).await.unwrap(), "discover-b");
+        }
+        changes.unbounded_send(remove("discover-b")).unwrap();
+        wait_until(|| open_b.load(Ordering::SeqCst) == 0).await;
+        let err = call(&mut client).await.unwrap_err();
+        assert!(matches!(
+            err,
+            crate::Error::Transport(e) if e.kind == TransportErrorKind::NotOpen
+        ));
+
+        // and opened again once they are back
+        changes.unbounded_send(insert("discover-a")).unwrap();
+        let mut resp = call(&mut client).await;
+        while resp.is_err() {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+            resp = call(&mut client).await;
+        }
+        assert_eq!(resp.unwrap(), "discover-a");
+        assert_eq!(open_a.load(Ordering::SeqCst), 1);
+    }
+}
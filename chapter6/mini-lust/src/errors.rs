@@ -217,7 +217,11 @@ impl From<ApplicationError> for Error {
 
 impl From<tower::BoxError> for Error {
     fn from(e: BoxError) -> Self {
-        new_application_error(ApplicationErrorKind::Unknown, e.to_string())
+        // Errors of services behind a `Buffer` come back boxed.
+        match e.downcast::<Error>() {
+            Ok(e) => *e,
+            Err(e) => new_application_error(ApplicationErrorKind::Unknown, e.to_string()),
+        }
     }
 }
 
@@ -394,7 +398,7 @@ pub enum ProtocolErrorKind {
     /// Unsupported Thrift protocol, server or field type.
     NotImplemented = 5,
     /// Reached the maximum nested depth to which an encoded Thrift field could
-    /// be skipped.
+    /// be skipped or decoded.
     DepthLimit = 6,
 }
 
@@ -407,7 +411,7 @@ impl Display for ProtocolError {
             ProtocolErrorKind::SizeLimit => "message too long",
             ProtocolErrorKind::BadVersion => "invalid thrift version",
             ProtocolErrorKind::NotImplemented => "not implemented",
-            ProtocolErrorKind::DepthLimit => "maximum skip depth reached",
+            ProtocolErrorKind::DepthLimit => "maximum depth reached",
         };
 
         write!(f, "{}", error_text)
@@ -0,0 +1,362 @@
+//! THeader transport, as used by fbthrift and Kitex.
+//!
+//! Every frame looks like:
+//! ```text
+//! +----------------+----------------+----------------+
+//! | LENGTH (u32)   | MAGIC 0x0FFF   | FLAGS (u16)    |
+//! +----------------+----------------+----------------+
+//! | SEQUENCE NUMBER (u32)           | HEADER SIZE/4  |
+//! +---------------------------------+----------------+
+//! | PROTOCOL ID (varint) | NUM TRANSFORMS (varint)    |
+//! | TRANSFORM IDS (varint)...  | INFO HEADERS...      |
+//! | PADDING to 4 bytes | PAYLOAD...                   |
+//! +---------------------------------------------------+
+//! ```
+//! Info headers are `type (varint) | count (varint) | (key, value)...`, where
+//! keys and values are varint length prefixed strings.
+
+use std::collections::HashMap;
+
+use bytes::{Buf, BufMut, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::codec::{encode_sized, SizedEncoder, MAX_MESSAGE_SIZE};
+use crate::context::MsgContext;
+use crate::{new_protocol_error, ProtocolErrorKind};
+
+pub(crate) const HEADER_MAGIC: u16 = 0x0FFF;
+
+pub(crate) const HEADER_PROTOCOL_BINARY: i32 = 0;
+pub(crate) const HEADER_PROTOCOL_JSON: i32 = 1;
+pub(crate) const HEADER_PROTOCOL_COMPACT: i32 = 2;
+
+const INFO_KEYVALUE: u32 = 1;
+const INFO_PKEYVALUE: u32 = 2;
+
+// The header size is written as a u16 count of 4-byte words.
+const MAX_HEADER_SIZE: usize = u16::MAX as usize * 4;
+
+/// Codec speaking the THeader transport, it can replace `FramedCodec`.
+///
+/// Info key/value headers are exposed as `MsgContext::headers` in both
+/// directions.
+pub struct THeaderCodec<C> {
+    inner: C,
+    protocol_id: Option<i32>,
+    max_frame_length: usize,
+}
+
+impl<C> THeaderCodec<C> {
+    /// Wrap the inner codec, `protocol_id` is the THeader id of the protocol
+    /// the inner codec speaks.
+    pub fn new(inner: C, protocol_id: Option<i32>) -> Self {
+        THeaderCodec {
+            inner,
+            protocol_id,
+            max_frame_length: MAX_MESSAGE_SIZE,
+        }
+    }
+
+    /// Refuse to read or write frames longer than `max_frame_length`.
+    pub fn with_max_frame_length(mut self, max_frame_length: usize) -> Self {
+        self.max_frame_length = max_frame_length;
+        self
+    }
+
+    #[inline]
+    fn protocol_id(&self) -> crate::Result<i32> {
+        self.protocol_id.ok_or_else(|| {
+            new_protocol_error(
+                ProtocolErrorKind::NotImplemented,
+                "protocol cannot be carried by THeader",
+            )
+        })
+    }
+}
+
+impl<C, T> Encoder<(MsgContext, T)> for THeaderCodec<C>
+where
+    C: SizedEncoder<(MsgContext, T)>,
+    crate::Error: From<C::Error>,
+{
+    type Error = crate::Error;
+
+    fn encode(&mut self, item: (MsgContext, T), dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let mut header = Vec::new();
+        write_varint(&mut header, self.protocol_id()? as u32);
+        // No transforms.
+        write_varint(&mut header, 0);
+        let headers = &item.0.headers;
+        if !headers.is_empty() {
+            write_varint(&mut header, INFO_KEYVALUE);
+            write_varint(&mut header, headers.len() as u32);
+            for (k, v) in headers.iter() {
+                write_varint(&mut header, k.len() as u32);
+                header.put_slice(k.as_bytes());
+                write_varint(&mut header, v.len() as u32);
+                header.put_slice(v.as_bytes());
+            }
+        }
+        while header.len() % 4 != 0 {
+            header.put_u8(0);
+        }
+        if header.len() > MAX_HEADER_SIZE {
+            return Err(new_protocol_error(
+                ProtocolErrorKind::SizeLimit,
+                format!("Header of length {} is too large.", header.len()),
+            ));
+        }
+
+        let size = self.inner.encoded_size(&item);
+        let length = 10 + header.len() + size;
+        if length > self.max_frame_length {
+            return Err(new_protocol_error(
+                ProtocolErrorKind::SizeLimit,
+                format!("Frame of length {} is too large.", length),
+            ));
+        }
+        dst.reserve(4 + length);
+        let start = dst.len();
+        dst.put_u32(length as u32);
+        dst.put_u16(HEADER_MAGIC);
+        dst.put_u16(0);
+        dst.put_u32(item.0.identifier.sequence_number as u32);
+        dst.put_u16((header.len() / 4) as u16);
+        dst.put_slice(&header);
+
+        encode_sized(&mut self.inner, item, size, dst, start)
+    }
+}
+
+impl<C, T> Decoder for THeaderCodec<C>
+where
+    C: Decoder<Item = (MsgContext, T)>,
+    crate::Error: From<C::Error>,
+{
+    type Item = (MsgContext, T);
+    type Error = crate::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if src.len() < 4 {
+            // Not enough data to read length marker.
+            return Ok(None);
+        }
+
+        let mut length_bytes = [0u8; 4];
+        length_bytes.copy_from_slice(&src[..4]);
+        let length = u32::from_be_bytes(length_bytes) as usize;
+        if length > self.max_frame_length {
+            return Err(new_protocol_error(
+                ProtocolErrorKind::SizeLimit,
+                format!("Frame of length {} is too large.", length),
+            ));
+        }
+        if src.len() < 4 + length {
+            src.reserve(4 + length - src.len());
+            return Ok(None);
+        }
+
+        let mut frame = src.split_to(4 + length);
+        frame.advance(4);
+        if frame.len() < 10 {
+            return Err(new_protocol_error(
+                ProtocolErrorKind::InvalidData,
+                "THeader frame is too short",
+            ));
+        }
+        let magic = frame.get_u16();
+        if magic != HEADER_MAGIC {
+            return Err(new_protocol_error(
+                ProtocolErrorKind::InvalidData,
+                format!("bad THeader magic {:#06x}", magic),
+            ));
+        }
+        let _flags = frame.get_u16();
+        let _sequence_number = frame.get_u32();
+        let header_size = frame.get_u16() as usize * 4;
+        if frame.len() < header_size {
+            return Err(new_protocol_error(
+                ProtocolErrorKind::InvalidData,
+                format!("THeader size {} exceeds the frame", header_size),
+            ));
+        }
+        let mut header = frame.split_to(header_size);
+
+        let protocol_id = read_varint(&mut header)? as i32;
+        if protocol_id != self.protocol_id()? {
+            return Err(new_protocol_error(
+                ProtocolErrorKind::NotImplemented,
+                format!("unexpected THeader protocol id {}", protocol_id),
+            ));
+        }
+        let num_transforms = read_varint(&mut header)?;
+        if num_transforms != 0 {
+            return Err(new_protocol_error(
+                ProtocolErrorKind::NotImplemented,
+                "THeader transforms are not supported",
+            ));
+        }
+        let headers = read_info_headers(&mut header)?;
+
+        let decoded = self.inner.decode(&mut frame)?;
+        match decoded {
+            None => Err(new_protocol_error(
+                ProtocolErrorKind::InvalidData,
+                "unable to decode message which the data size is enough for decoding",
+            )),
+            Some((mut cx, inner)) => {
+                cx.headers = headers;
+                Ok(Some((cx, inner)))
+            }
+        }
+    }
+}
+
+fn read_info_headers(header: &mut BytesMut) -> crate::Result<HashMap<String, String>> {
+    let mut headers = HashMap::new();
+    while header.has_remaining() {
+        match read_varint(header)? {
+            INFO_KEYVALUE | INFO_PKEYVALUE => {
+                let count = read_varint(header)?;
+                for _ in 0..count {
+                    let key = read_string(header)?;
+                    let value = read_string(header)?;
+                    headers.insert(key, value);
+                }
+            }
+            // Padding, or an info type we do not know, ends the headers.
+            _ => break,
+        }
+    }
+    Ok(headers)
+}
+
+#[inline]
+fn read_string(header: &mut BytesMut) -> crate::Result<String> {
+    let len = read_varint(header)? as usize;
+    if header.remaining() < len {
+        return Err(new_protocol_error(
+            ProtocolErrorKind::InvalidData,
+            "unexpected data length",
+        ));
+    }
+    let bytes = header.split_to(len);
+    String::from_utf8(bytes.to_vec()).map_err(From::from)
+}
+
+#[inline]
+fn read_varint(header: &mut BytesMut) -> crate::Result<u32> {
+    let mut result = 0u32;
+    let mut shift = 0;
+    loop {
+        if !header.has_remaining() || shift > 28 {
+            return Err(new_protocol_error(
+                ProtocolErrorKind::InvalidData,
+                "invalid varint in THeader",
+            ));
+        }
+        let b = header.get_u8();
+        result |= ((b & 0x7F) as u32) << shift;
+        if b & 0x80 == 0 {
+            return Ok(result);
+        }
+        shift += 7;
+    }
+}
+
+#[inline]
+fn write_varint(buf: &mut Vec<u8>, mut n: u32) {
+    while n >= 0x80 {
+        buf.put_u8((n as u8) | 0x80);
+        n >>= 7;
+    }
+    buf.put_u8(n as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use bytes::BytesMut;
+    use tokio_util::codec::{Decoder, Encoder};
+
+    use crate::binary::TBinaryProtocolFactory;
+    use crate::codec::MessageCodec;
+    use crate::compact::TCompactProtocolFactory;
+    use crate::context::MsgContext;
+    use crate::header::{THeaderCodec, HEADER_PROTOCOL_BINARY, HEADER_PROTOCOL_COMPACT};
+    use crate::protocol::{TMessageIdentifier, TMessageType};
+
+    fn context() -> MsgContext {
+        MsgContext {
+            identifier: TMessageIdentifier::new("ping", TMessageType::Call, 5),
+            ..MsgContext::default()
+        }
+    }
+
+    #[test]
+    fn test_frame_layout() {
+        let mut codec = THeaderCodec::new(
+            MessageCodec::<bool, bool>::new(Arc::new(TBinaryProtocolFactory::default())),
+            Some(HEADER_PROTOCOL_BINARY),
+        );
+        let mut cx = context();
+        cx.headers.insert("k".to_string(), "v".to_string());
+        let mut buf = BytesMut::new();
+        codec.encode((cx, Ok(true)), &mut buf).unwrap();
+
+        #[rustfmt::skip]
+        let header = [
+            0x0F, 0xFF, 0x00, 0x00, // magic and flags
+            0x00, 0x00, 0x00, 0x05, // sequence number
+            0x00, 0x02, // header size in words
+            0x00, 0x00, // protocol id, transforms
+            0x01, 0x01, 0x01, b'k', 0x01, b'v', // info key/value headers
+        ];
+        assert_eq!(&buf[4..22], &header[..]);
+        assert_eq!(
+            buf.len() - 4,
+            u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]) as usize
+        );
+        // the binary payload follows directly
+        assert_eq!(&buf[22..24], &[0x80, 0x01]);
+    }
+
+    #[test]
+    fn test_headers_round_trip() {
+        let mut codec = THeaderCodec::new(
+            MessageCodec::<bool, bool>::new(Arc::new(TCompactProtocolFactory::new())),
+            Some(HEADER_PROTOCOL_COMPACT),
+        );
+        let mut cx = context();
+        cx.headers.insert("trace-id".to_string(), "abc".to_string());
+        cx.headers.insert("caller".to_string(), "test".to_string());
+        let mut buf = BytesMut::new();
+        codec.encode((cx.clone(), Ok(false)), &mut buf).unwrap();
+        // a partial frame is not decoded
+        let mut partial = BytesMut::from(&buf[..buf.len() - 1]);
+        assert!(codec.decode(&mut partial).unwrap().is_none());
+
+        // a trailing frame is left untouched
+        buf.extend_from_slice(&[0, 0, 0, 1]);
+        let (decoded_cx, decoded) = codec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(decoded_cx, cx);
+        assert!(!decoded.unwrap());
+        assert_eq!(&buf[..], &[0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn test_protocol_mismatch() {
+        let mut writer = THeaderCodec::new(
+            MessageCodec::<bool, bool>::new(Arc::new(TCompactProtocolFactory::new())),
+            Some(HEADER_PROTOCOL_COMPACT),
+        );
+        let mut reader = THeaderCodec::new(
+            MessageCodec::<bool, bool>::new(Arc::new(TBinaryProtocolFactory::default())),
+            Some(HEADER_PROTOCOL_BINARY),
+        );
+        let mut buf = BytesMut::new();
+        writer.encode((context(), Ok(true)), &mut buf).unwrap();
+        assert!(reader.decode(&mut buf).is_err());
+    }
+}
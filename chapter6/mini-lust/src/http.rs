@@ -0,0 +1,371 @@
+//! Thrift over HTTP/1.1, as spoken by `THttpClient`.
+//!
+//! Each call is a `POST` whose body is one message, its reply is the body of
+//! the response. Bodies are delimited with `Content-Length`, chunked bodies
+//! are not supported. Oneway calls are answered with an empty `200`, requests
+//! with another method or path with a `405` or a `404`.
+
+use std::fmt::Write;
+
+use bytes::{Buf, BufMut, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::codec::{encode_sized, SizedEncoder, MAX_MESSAGE_SIZE};
+use crate::{new_protocol_error, new_transport_error, ProtocolErrorKind, TransportErrorKind};
+
+const CONTENT_TYPE: &str = "application/x-thrift";
+// Bounds the request line and headers, the body is bounded by the max frame
+// length.
+const MAX_HEADERS_SIZE: usize = 64 * 1024;
+const MAX_HEADERS: usize = 64;
+
+// Written as is by servers, there is no message to encode in them.
+const ONEWAY_RESPONSE: &[u8] = b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n";
+const NOT_FOUND: &[u8] =
+    b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+const METHOD_NOT_ALLOWED: &[u8] = b"HTTP/1.1 405 Method Not Allowed\r\nAllow: POST\r\n\
+    Content-Length: 0\r\nConnection: close\r\n\r\n";
+
+enum Role {
+    /// Sends requests to `host`, reads responses.
+    Client { host: String },
+    /// Reads requests, sends responses.
+    Server,
+}
+
+/// Codec carrying messages in HTTP/1.1 `POST` requests and their responses.
+pub struct HttpCodec<C> {
+    inner: C,
+    role: Role,
+    path: String,
+    max_frame_length: usize,
+    rejection: Option<&'static [u8]>,
+}
+
+impl<C> HttpCodec<C> {
+    /// Codec of a client posting its calls to `path` on `host`.
+    pub fn client(inner: C, path: impl Into<String>, host: impl Into<String>) -> Self {
+        HttpCodec {
+            inner,
+            role: Role::Client { host: host.into() },
+            path: path.into(),
+            max_frame_length: MAX_MESSAGE_SIZE,
+            rejection: None,
+        }
+    }
+
+    /// Codec of a server accepting calls posted to `path`.
+    pub fn server(inner: C, path: impl Into<String>) -> Self {
+        HttpCodec {
+            inner,
+            role: Role::Server,
+            path: path.into(),
+            max_frame_length: MAX_MESSAGE_SIZE,
+            rejection: None,
+        }
+    }
+
+    /// Refuse to read or write bodies longer than `max_frame_length`.
+    pub fn with_max_frame_length(mut self, max_frame_length: usize) -> Self {
+        self.max_frame_length = max_frame_length;
+        self
+    }
+
+    /// The response a server writes back once it has handled a oneway call.
+    /// Clients skip these empty responses.
+    pub fn oneway_response(&self) -> Option<&'static [u8]> {
+        match self.role {
+            Role::Client { .. } => None,
+            Role::Server => Some(ONEWAY_RESPONSE),
+        }
+    }
+
+    /// The response rejecting the request whose method or path failed the
+    /// last decode, to be written back before closing the connection.
+    pub fn take_rejection(&mut self) -> Option<&'static [u8]> {
+        self.rejection.take()
+    }
+
+    #[inline]
+    fn check_length(&self, length: usize) -> crate::Result<()> {
+        if length > self.max_frame_length {
+            return Err(new_protocol_error(
+                ProtocolErrorKind::SizeLimit,
+                format!("HTTP body of length {} is too large.", length),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Check the request line or status line of the head in `src`, returns
+    /// the lengths of the head and of the body once the head is complete.
+    fn check_head(&mut self, src: &[u8]) -> crate::Result<Option<(usize, usize)>> {
+        let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+        let (status, headers) = match self.role {
+            Role::Client { .. } => {
+                let mut response = httparse::Response::new(&mut headers);
+                let status = response.parse(src).map_err(invalid_http)?;
+                if status.is_complete() {
+                    let code = response.code.unwrap_or_default();
+                    if code != 200 {
+                        return Err(new_transport_error(
+                            TransportErrorKind::Unknown,
+                            format!(
+                                "HTTP status {} {}",
+                                code,
+                                response.reason.unwrap_or_default()
+                            ),
+                        ));
+                    }
+                }
+                (status, response.headers)
+            }
+            Role::Server => {
+                let mut request = httparse::Request::new(&mut headers);
+                let status = request.parse(src).map_err(invalid_http)?;
+                if status.is_complete() {
+                    if request.method != Some("POST") {
+                        self.rejection = Some(METHOD_NOT_ALLOWED);
+                        return Err(invalid_http(format!(
+                            "unexpected method {}",
+                            request.method.unwrap_or_default()
+                        )));
+                    }
+                    let path = request.path.unwrap_or_default();
+                    let path = path.split('?').next().unwrap_or_default();
+                    if path != self.path {
+                        self.rejection = Some(NOT_FOUND);
+                        return Err(invalid_http(format!("unexpected path {}", path)));
+                    }
+                }
+                (status, request.headers)
+            }
+        };
+
+        let head_length = match status {
+            httparse::Status::Complete(head_length) => head_length,
+            httparse::Status::Partial if src.len() > MAX_HEADERS_SIZE => {
+                return Err(invalid_http("HTTP headers are too large"))
+            }
+            httparse::Status::Partial => return Ok(None),
+        };
+        let mut content_length = None;
+        for header in headers.iter() {
+            if header.name.eq_ignore_ascii_case("transfer-encoding")
+                && !header.value.eq_ignore_ascii_case(b"identity")
+            {
+                return Err(invalid_http("chunked bodies are not supported"));
+            }
+            if header.name.eq_ignore_ascii_case("content-length") {
+                let length = std::str::from_utf8(header.value)
+                    .ok()
+                    .and_then(|length| length.trim().parse::<usize>().ok())
+                    .ok_or_else(|| invalid_http("invalid Content-Length"))?;
+                content_length = Some(length);
+            }
+        }
+        let content_length =
+            content_length.ok_or_else(|| invalid_http("missing Content-Length"))?;
+        Ok(Some((head_length, content_length)))
+    }
+}
+
+fn invalid_http(e: impl std::fmt::Display) -> crate::Error {
+    new_protocol_error(
+        ProtocolErrorKind::InvalidData,
+        format!("invalid HTTP message: {}", e),
+    )
+}
+
+impl<C, T> Encoder<T> for HttpCodec<C>
+where
+    C: SizedEncoder<T>,
+    crate::Error: From<C::Error>,
+{
+    type Error = crate::Error;
+
+    fn encode(&mut self, item: T, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let size = self.inner.encoded_size(&item);
+        self.check_length(size)?;
+
+        let mut head = String::new();
+        match &self.role {
+            Role::Client { host } => {
+                let _ = write!(
+                    head,
+                    "POST {} HTTP/1.1\r\nHost: {}\r\nAccept: {}\r\n",
+                    self.path, host, CONTENT_TYPE
+                );
+            }
+            Role::Server => head.push_str("HTTP/1.1 200 OK\r\n"),
+        }
+        let _ = write!(
+            head,
+            "Content-Type: {}\r\nContent-Length: {}\r\n\r\n",
+            CONTENT_TYPE, size
+        );
+
+        dst.reserve(head.len() + size);
+        let start = dst.len();
+        dst.put_slice(head.as_bytes());
+        encode_sized(&mut self.inner, item, size, dst, start)
+    }
+}
+
+impl<C> Decoder for HttpCodec<C>
+where
+    C: Decoder,
+    crate::Error: From<C::Error>,
+{
+    type Item = C::Item;
+    type Error = crate::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        // Mini-lust note: the head is parsed again until the whole body has
+        // arrived, it is short compared to the body.
+        let (head_length, content_length) = loop {
+            let (head_length, content_length) = match self.check_head(src)? {
+                Some(lengths) => lengths,
+                None => return Ok(None),
+            };
+            // An empty body carries no message, it answers a oneway call.
+            match self.role {
+                Role::Client { .. } if content_length == 0 => src.advance(head_length),
+                _ => break (head_length, content_length),
+            }
+        };
+        self.check_length(content_length)?;
+        if src.len() < head_length + content_length {
+            src.reserve(head_length + content_length - src.len());
+            return Ok(None);
+        }
+        src.advance(head_length);
+        let mut body = src.split_to(content_length);
+        match self.inner.decode(&mut body)? {
+            None => Err(new_protocol_error(
+                ProtocolErrorKind::InvalidData,
+                "unable to decode message which the data size is enough for decoding",
+            )),
+            Some(item) => Ok(Some(item)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::BytesMut;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_util::codec::{Decoder, Encoder};
+
+    use crate::context::MsgContext;
+    use crate::http::HttpCodec;
+    use crate::memory::MemoryAddr;
+    use crate::protocol::TMessageType;
+    use crate::testing::{cx, message_codec, serve_in_memory};
+    use crate::{ApplicationResult, ClientBuilder, Server, SocketOrUnix};
+
+    #[test]
+    fn test_http_round_trip() {
+        let mut client = HttpCodec::client(message_codec(), "/thrift", "example.com");
+        let mut server = HttpCodec::server(message_codec(), "/thrift");
+
+        let mut buf = BytesMut::new();
+        client
+            .encode((cx(), Ok("ping".to_string())), &mut buf)
+            .unwrap();
+        let head_length = buf.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        let head = std::str::from_utf8(&buf[..head_length]).unwrap();
+        assert!(head.starts_with("POST /thrift HTTP/1.1\r\nHost: example.com\r\n"));
+        assert!(head.contains("Content-Type: application/x-thrift\r\n"));
+
+        // the body may arrive in pieces
+        let last = buf.split_off(buf.len() - 1);
+        assert!(server.decode(&mut buf).unwrap().is_none());
+        buf.unsplit(last);
+        let (cx, req) = server.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(req.unwrap(), "ping");
+        assert!(buf.is_empty());
+
+        server
+            .encode((cx, Ok("pong".to_string())), &mut buf)
+            .unwrap();
+        assert!(buf.starts_with(b"HTTP/1.1 200 OK\r\n"));
+        let (_, resp) = client.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(resp.unwrap(), "pong");
+    }
+
+    #[test]
+    fn test_http_oneway() {
+        let mut client = HttpCodec::client(message_codec(), "/thrift", "example.com");
+        let mut server = HttpCodec::server(message_codec(), "/thrift");
+        assert!(client.oneway_response().is_none());
+
+        // the empty response to a oneway call is skipped, even in pieces
+        let mut buf = BytesMut::from(&server.oneway_response().unwrap()[..5]);
+        assert!(client.decode(&mut buf).unwrap().is_none());
+        buf.extend_from_slice(&server.oneway_response().unwrap()[5..]);
+        assert!(client.decode(&mut buf).unwrap().is_none());
+        assert!(buf.is_empty());
+
+        buf.extend_from_slice(server.oneway_response().unwrap());
+        server
+            .encode((cx(), Ok("pong".to_string())), &mut buf)
+            .unwrap();
+        let (_, resp) = client.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(resp.unwrap(), "pong");
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_http_rejected() {
+        let mut server = HttpCodec::server(message_codec(), "/thrift");
+        assert!(server.take_rejection().is_none());
+        let mut buf = BytesMut::from(&b"GET /thrift HTTP/1.1\r\nHost: a\r\n\r\n"[..]);
+        assert!(server.decode(&mut buf).is_err());
+        let rejection = server.take_rejection().unwrap();
+        assert!(rejection.starts_with(b"HTTP/1.1 405 Method Not Allowed\r\n"));
+        assert!(server.take_rejection().is_none());
+        let mut buf =
+            BytesMut::from(&b"POST /other HTTP/1.1\r\nHost: a\r\nContent-Length: 0\r\n\r\n"[..]);
+        assert!(server.decode(&mut buf).is_err());
+        let rejection = server.take_rejection().unwrap();
+        assert!(rejection.starts_with(b"HTTP/1.1 404 Not Found\r\n"));
+
+        let mut client = HttpCodec::client(message_codec(), "/thrift", "a");
+        let mut buf =
+            BytesMut::from(&b"HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\n\r\n"[..]);
+        assert!(client.decode(&mut buf).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_http_server() {
+        let service = tower::service_fn(
+            |(cx, req): (MsgContext, ApplicationResult<String>)| async move {
+                if cx.identifier.message_type == TMessageType::OneWay {
+                    return Ok::<_, crate::Error>(None);
+                }
+                Ok(Some((cx, req.map(|req| req.to_uppercase()))))
+            },
+        );
+        let server = Server::<_, MemoryAddr, String, String>::new(service).http("/rpc");
+        serve_in_memory(server, "http-server").await;
+
+        let mut client = ClientBuilder::new(SocketOrUnix::Memory("http-server".to_string()))
+            .http("/rpc")
+            .build();
+        let resp: String = client.call("shout", "hello".to_string()).await.unwrap();
+        assert_eq!(resp, "HELLO");
+        // the next call reads its reply past the response to the oneway call
+        client.oneway("notify", "hi".to_string()).await.unwrap();
+        let resp: String = client.call("shout", "again".to_string()).await.unwrap();
+        assert_eq!(resp, "AGAIN");
+
+        let mut conn = crate::memory::connect("http-server").unwrap();
+        conn.write_all(b"GET /rpc HTTP/1.1\r\nHost: a\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = Vec::new();
+        conn.read_to_end(&mut response).await.unwrap();
+        assert!(response.starts_with(b"HTTP/1.1 405 Method Not Allowed\r\n"));
+    }
+}
@@ -0,0 +1,1376 @@
+use std::convert::TryFrom;
+
+use bytes::BytesMut;
+
+use crate::binary::protocol_len_check;
+use crate::limits::DecodeLimits;
+use crate::protocol::{
+    TFieldIdentifier, TInputProtocol, TLengthProtocol, TListIdentifier, TMapIdentifier,
+    TMessageIdentifier, TMessageType, TOutputProtocol, TProtocolFactory, TSetIdentifier,
+    TStructIdentifier, TType,
+};
+use crate::{new_protocol_error, ProtocolErrorKind};
+
+const JSON_PROTOCOL_VERSION: i64 = 1;
+
+const JSON_OBJECT_START: u8 = b'{';
+const JSON_OBJECT_END: u8 = b'}';
+const JSON_ARRAY_START: u8 = b'[';
+const JSON_ARRAY_END: u8 = b']';
+const JSON_PAIR_SEPARATOR: u8 = b':';
+const JSON_ELEM_SEPARATOR: u8 = b',';
+const JSON_BACKSLASH: u8 = b'\\';
+const JSON_STRING_DELIMITER: u8 = b'"';
+
+const THRIFT_NAN: &str = "NaN";
+const THRIFT_INFINITY: &str = "Infinity";
+const THRIFT_NEGATIVE_INFINITY: &str = "-Infinity";
+
+/// Where the protocol currently is inside the JSON document.
+///
+/// Mini-lust note: the official implementations keep a stack of context
+/// objects; we keep the same state machine as a plain enum.
+#[derive(Debug, Clone, Copy)]
+enum JsonContext {
+    /// Top level, values are written back to back.
+    Base,
+    /// Inside an array, values are separated by `,`.
+    List { first: bool },
+    /// Inside an object, values alternate between keys and values.
+    Pair { first: bool, colon: bool },
+}
+
+impl JsonContext {
+    /// Advance to the next element and return the separator that goes before it.
+    #[inline]
+    fn next(&mut self) -> Option<u8> {
+        match self {
+            JsonContext::Base => None,
+            JsonContext::List { first } => {
+                if *first {
+                    *first = false;
+                    None
+                } else {
+                    Some(JSON_ELEM_SEPARATOR)
+                }
+            }
+            JsonContext::Pair { first, colon } => {
+                if *first {
+                    *first = false;
+                    *colon = true;
+                    None
+                } else {
+                    let sep = if *colon {
+                        JSON_PAIR_SEPARATOR
+                    } else {
+                        JSON_ELEM_SEPARATOR
+                    };
+                    *colon = !*colon;
+                    Some(sep)
+                }
+            }
+        }
+    }
+
+    /// Object keys must be strings, so numbers written as keys are quoted.
+    #[inline]
+    fn escape_num(&self) -> bool {
+        matches!(self, JsonContext::Pair { colon: true, .. })
+    }
+}
+
+// Mini-lust note: warp a BufMut to reuse buffer and do buffered packet parsing.
+#[derive(Debug)]
+pub struct TJSONOutputProtocol<T> {
+    buf: T,
+    context: JsonContext,
+    context_stack: Vec<JsonContext>,
+}
+
+impl<T> TJSONOutputProtocol<T> {
+    pub fn new(buf: T) -> TJSONOutputProtocol<T> {
+        TJSONOutputProtocol {
+            buf,
+            context: JsonContext::Base,
+            context_stack: Vec::new(),
+        }
+    }
+
+    #[inline]
+    fn push_context(&mut self, context: JsonContext) {
+        let parent = std::mem::replace(&mut self.context, context);
+        self.context_stack.push(parent);
+    }
+
+    #[inline]
+    fn pop_context(&mut self) -> crate::Result<()> {
+        self.context = self.context_stack.pop().ok_or_else(|| {
+            new_protocol_error(ProtocolErrorKind::InvalidData, "unbalanced json context")
+        })?;
+        Ok(())
+    }
+}
+
+impl<T> TJSONOutputProtocol<T>
+where
+    T: bytes::BufMut,
+{
+    #[inline]
+    fn write_context_separator(&mut self) {
+        if let Some(sep) = self.context.next() {
+            self.buf.put_u8(sep);
+        }
+    }
+
+    #[inline]
+    fn write_json_escaped(&mut self, b: &[u8]) {
+        write_json_escaped(&mut self.buf, b);
+    }
+
+    #[inline]
+    fn write_json_string(&mut self, b: &[u8]) {
+        self.write_context_separator();
+        self.write_json_escaped(b);
+    }
+
+    #[inline]
+    fn write_json_integer(&mut self, i: i64) {
+        self.write_context_separator();
+        let escape = self.context.escape_num();
+        if escape {
+            self.buf.put_u8(JSON_STRING_DELIMITER);
+        }
+        self.buf.put_slice(i.to_string().as_bytes());
+        if escape {
+            self.buf.put_u8(JSON_STRING_DELIMITER);
+        }
+    }
+
+    #[inline]
+    fn write_json_double(&mut self, d: f64) {
+        self.write_context_separator();
+        let special = if d.is_nan() {
+            Some(THRIFT_NAN)
+        } else if d == f64::INFINITY {
+            Some(THRIFT_INFINITY)
+        } else if d == f64::NEG_INFINITY {
+            Some(THRIFT_NEGATIVE_INFINITY)
+        } else {
+            None
+        };
+        match special {
+            // Special values are not valid JSON numbers, so they are always quoted.
+            Some(s) => self.write_json_escaped(s.as_bytes()),
+            None => {
+                let escape = self.context.escape_num();
+                if escape {
+                    self.buf.put_u8(JSON_STRING_DELIMITER);
+                }
+                self.buf.put_slice(format!("{:?}", d).as_bytes());
+                if escape {
+                    self.buf.put_u8(JSON_STRING_DELIMITER);
+                }
+            }
+        }
+    }
+
+    #[inline]
+    fn write_json_base64(&mut self, b: &[u8]) {
+        self.write_context_separator();
+        // Mini-lust note: the official C++ and Java implementations write
+        // base64 without padding, and accept it either way.
+        let encoded = base64::encode_config(b, base64::STANDARD_NO_PAD);
+        self.write_json_escaped(encoded.as_bytes());
+    }
+
+    #[inline]
+    fn write_json_object_start(&mut self) {
+        self.write_context_separator();
+        self.buf.put_u8(JSON_OBJECT_START);
+        self.push_context(JsonContext::Pair {
+            first: true,
+            colon: true,
+        });
+    }
+
+    #[inline]
+    fn write_json_object_end(&mut self) -> crate::Result<()> {
+        self.pop_context()?;
+        self.buf.put_u8(JSON_OBJECT_END);
+        Ok(())
+    }
+
+    #[inline]
+    fn write_json_array_start(&mut self) {
+        self.write_context_separator();
+        self.buf.put_u8(JSON_ARRAY_START);
+        self.push_context(JsonContext::List { first: true });
+    }
+
+    #[inline]
+    fn write_json_array_end(&mut self) -> crate::Result<()> {
+        self.pop_context()?;
+        self.buf.put_u8(JSON_ARRAY_END);
+        Ok(())
+    }
+}
+
+impl<T> TOutputProtocol for TJSONOutputProtocol<T>
+where
+    T: bytes::BufMut,
+{
+    #[inline]
+    fn write_message_begin(&mut self, identifier: &TMessageIdentifier) -> crate::Result<()> {
+        self.write_json_array_start();
+        self.write_json_integer(JSON_PROTOCOL_VERSION);
+        self.write_json_string(identifier.name.as_bytes());
+        let message_type: u8 = identifier.message_type.into();
+        self.write_json_integer(message_type as i64);
+        self.write_json_integer(identifier.sequence_number as i64);
+        Ok(())
+    }
+
+    #[inline]
+    fn write_message_end(&mut self) -> crate::Result<()> {
+        self.write_json_array_end()
+    }
+
+    #[inline]
+    fn write_struct_begin(&mut self, _: &TStructIdentifier) -> crate::Result<()> {
+        self.write_json_object_start();
+        Ok(())
+    }
+
+    #[inline]
+    fn write_struct_end(&mut self) -> crate::Result<()> {
+        self.write_json_object_end()
+    }
+
+    #[inline]
+    fn write_field_begin(&mut self, identifier: &TFieldIdentifier) -> crate::Result<()> {
+        let id = identifier.id.ok_or_else(|| {
+            new_protocol_error(
+                ProtocolErrorKind::Unknown,
+                format!(
+                    "cannot write identifier {:?} without sequence number",
+                    &identifier
+                ),
+            )
+        })?;
+        let type_name = type_to_name(identifier.field_type)?;
+        self.write_json_integer(id as i64);
+        self.write_json_object_start();
+        self.write_json_string(type_name.as_bytes());
+        Ok(())
+    }
+
+    #[inline]
+    fn write_field_end(&mut self) -> crate::Result<()> {
+        self.write_json_object_end()
+    }
+
+    #[inline]
+    fn write_field_stop(&mut self) -> crate::Result<()> {
+        Ok(())
+    }
+
+    #[inline]
+    fn write_bool(&mut self, b: bool) -> crate::Result<()> {
+        self.write_json_integer(b as i64);
+        Ok(())
+    }
+
+    #[inline]
+    fn write_bytes(&mut self, b: &[u8]) -> crate::Result<()> {
+        self.write_json_base64(b);
+        Ok(())
+    }
+
+    #[inline]
+    fn write_i8(&mut self, i: i8) -> crate::Result<()> {
+        self.write_json_integer(i as i64);
+        Ok(())
+    }
+
+    #[inline]
+    fn write_i16(&mut self, i: i16) -> crate::Result<()> {
+        self.write_json_integer(i as i64);
+        Ok(())
+    }
+
+    #[inline]
+    fn write_i32(&mut self, i: i32) -> crate::Result<()> {
+        self.write_json_integer(i as i64);
+        Ok(())
+    }
+
+    #[inline]
+    fn write_i64(&mut self, i: i64) -> crate::Result<()> {
+        self.write_json_integer(i);
+        Ok(())
+    }
+
+    #[inline]
+    fn write_double(&mut self, d: f64) -> crate::Result<()> {
+        self.write_json_double(d);
+        Ok(())
+    }
+
+    #[inline]
+    fn write_string(&mut self, s: &str) -> crate::Result<()> {
+        self.write_json_string(s.as_bytes());
+        Ok(())
+    }
+
+    #[inline]
+    fn write_uuid(&mut self, uuid: [u8; 16]) -> crate::Result<()> {
+        self.write_json_string(format_uuid(&uuid).as_bytes());
+        Ok(())
+    }
+
+    #[inline]
+    fn write_list_begin(&mut self, identifier: &TListIdentifier) -> crate::Result<()> {
+        let type_name = type_to_name(identifier.element_type)?;
+        self.write_json_array_start();
+        self.write_json_string(type_name.as_bytes());
+        self.write_json_integer(identifier.size as i64);
+        Ok(())
+    }
+
+    #[inline]
+    fn write_list_end(&mut self) -> crate::Result<()> {
+        self.write_json_array_end()
+    }
+
+    #[inline]
+    fn write_set_begin(&mut self, identifier: &TSetIdentifier) -> crate::Result<()> {
+        let type_name = type_to_name(identifier.element_type)?;
+        self.write_json_array_start();
+        self.write_json_string(type_name.as_bytes());
+        self.write_json_integer(identifier.size as i64);
+        Ok(())
+    }
+
+    #[inline]
+    fn write_set_end(&mut self) -> crate::Result<()> {
+        self.write_json_array_end()
+    }
+
+    #[inline]
+    fn write_map_begin(&mut self, identifier: &TMapIdentifier) -> crate::Result<()> {
+        let key_type = identifier
+            .key_type
+            .expect("map identifier to write should contain key type");
+        let val_type = identifier
+            .value_type
+            .expect("map identifier to write should contain value type");
+        let (key_name, val_name) = (type_to_name(key_type)?, type_to_name(val_type)?);
+        self.write_json_array_start();
+        self.write_json_string(key_name.as_bytes());
+        self.write_json_string(val_name.as_bytes());
+        self.write_json_integer(identifier.size as i64);
+        self.write_json_object_start();
+        Ok(())
+    }
+
+    #[inline]
+    fn write_map_end(&mut self) -> crate::Result<()> {
+        self.write_json_object_end()?;
+        self.write_json_array_end()
+    }
+
+    #[inline]
+    fn flush(&mut self) -> crate::Result<()> {
+        Ok(())
+    }
+
+    // utility
+    //
+
+    #[inline]
+    fn write_byte(&mut self, b: u8) -> crate::Result<()> {
+        self.write_json_integer(b as i64);
+        Ok(())
+    }
+}
+
+/// Computes the lengths written by `TJSONOutputProtocol`.
+///
+/// Mini-lust note: JSON lengths depend on escaping, base64 and number
+/// formatting, so each value is written to a scratch buffer that is cleared
+/// right after being measured.
+#[derive(Debug)]
+pub struct TJSONLengthProtocol {
+    inner: TJSONOutputProtocol<BytesMut>,
+}
+
+impl TJSONLengthProtocol {
+    pub fn new() -> TJSONLengthProtocol {
+        TJSONLengthProtocol {
+            inner: TJSONOutputProtocol::new(BytesMut::new()),
+        }
+    }
+
+    #[inline]
+    fn measure<F>(&mut self, write: F) -> usize
+    where
+        F: FnOnce(&mut TJSONOutputProtocol<BytesMut>) -> crate::Result<()>,
+    {
+        // Errors are reported when the message is actually written.
+        let _ = write(&mut self.inner);
+        let len = self.inner.buf.len();
+        self.inner.buf.clear();
+        len
+    }
+}
+
+impl Default for TJSONLengthProtocol {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TLengthProtocol for TJSONLengthProtocol {
+    #[inline]
+    fn message_begin_len(&mut self, identifier: &TMessageIdentifier) -> usize {
+        self.measure(|p| p.write_message_begin(identifier))
+    }
+
+    #[inline]
+    fn message_end_len(&mut self) -> usize {
+        self.measure(|p| p.write_message_end())
+    }
+
+    #[inline]
+    fn struct_begin_len(&mut self, identifier: &TStructIdentifier) -> usize {
+        self.measure(|p| p.write_struct_begin(identifier))
+    }
+
+    #[inline]
+    fn struct_end_len(&mut self) -> usize {
+        self.measure(|p| p.write_struct_end())
+    }
+
+    #[inline]
+    fn field_begin_len(&mut self, identifier: &TFieldIdentifier) -> usize {
+        self.measure(|p| p.write_field_begin(identifier))
+    }
+
+    #[inline]
+    fn field_end_len(&mut self) -> usize {
+        self.measure(|p| p.write_field_end())
+    }
+
+    #[inline]
+    fn field_stop_len(&mut self) -> usize {
+        self.measure(|p| p.write_field_stop())
+    }
+
+    #[inline]
+    fn bool_len(&mut self, b: bool) -> usize {
+        self.measure(|p| p.write_bool(b))
+    }
+
+    #[inline]
+    fn bytes_len(&mut self, b: &[u8]) -> usize {
+        self.measure(|p| p.write_bytes(b))
+    }
+
+    #[inline]
+    fn i8_len(&mut self, i: i8) -> usize {
+        self.measure(|p| p.write_i8(i))
+    }
+
+    #[inline]
+    fn i16_len(&mut self, i: i16) -> usize {
+        self.measure(|p| p.write_i16(i))
+    }
+
+    #[inline]
+    fn i32_len(&mut self, i: i32) -> usize {
+        self.measure(|p| p.write_i32(i))
+    }
+
+    #[inline]
+    fn i64_len(&mut self, i: i64) -> usize {
+        self.measure(|p| p.write_i64(i))
+    }
+
+    #[inline]
+    fn double_len(&mut self, d: f64) -> usize {
+        self.measure(|p| p.write_double(d))
+    }
+
+    #[inline]
+    fn string_len(&mut self, s: &str) -> usize {
+        self.measure(|p| p.write_string(s))
+    }
+
+    #[inline]
+    fn uuid_len(&mut self, uuid: [u8; 16]) -> usize {
+        self.measure(|p| p.write_uuid(uuid))
+    }
+
+    #[inline]
+    fn list_begin_len(&mut self, identifier: &TListIdentifier) -> usize {
+        self.measure(|p| p.write_list_begin(identifier))
+    }
+
+    #[inline]
+    fn list_end_len(&mut self) -> usize {
+        self.measure(|p| p.write_list_end())
+    }
+
+    #[inline]
+    fn set_begin_len(&mut self, identifier: &TSetIdentifier) -> usize {
+        self.measure(|p| p.write_set_begin(identifier))
+    }
+
+    #[inline]
+    fn set_end_len(&mut self) -> usize {
+        self.measure(|p| p.write_set_end())
+    }
+
+    #[inline]
+    fn map_begin_len(&mut self, identifier: &TMapIdentifier) -> usize {
+        self.measure(|p| p.write_map_begin(identifier))
+    }
+
+    #[inline]
+    fn map_end_len(&mut self) -> usize {
+        self.measure(|p| p.write_map_end())
+    }
+
+    #[inline]
+    fn byte_len(&mut self, b: u8) -> usize {
+        self.measure(|p| p.write_byte(b))
+    }
+}
+
+// Mini-lust note: warp a Buf to reuse buffer and do buffered packet parsing.
+#[derive(Debug)]
+pub struct TJSONInputProtocol<T> {
+    buf: T,
+    context: JsonContext,
+    context_stack: Vec<JsonContext>,
+    limits: DecodeLimits,
+}
+
+impl<T> TJSONInputProtocol<T> {
+    pub fn new(buf: T) -> TJSONInputProtocol<T> {
+        TJSONInputProtocol {
+            buf,
+            context: JsonContext::Base,
+            context_stack: Vec::new(),
+            limits: DecodeLimits::default(),
+        }
+    }
+
+    #[inline]
+    fn push_context(&mut self, context: JsonContext) {
+        let parent = std::mem::replace(&mut self.context, context);
+        self.context_stack.push(parent);
+    }
+
+    #[inline]
+    fn pop_context(&mut self) -> crate::Result<()> {
+        self.context = self.context_stack.pop().ok_or_else(|| {
+            new_protocol_error(ProtocolErrorKind::InvalidData, "unbalanced json context")
+        })?;
+        Ok(())
+    }
+}
+
+impl<T> TJSONInputProtocol<T>
+where
+    T: bytes::Buf,
+{
+    #[inline]
+    fn peek(&self) -> crate::Result<u8> {
+        protocol_len_check(&self.buf, 1)?;
+        Ok(self.buf.chunk()[0])
+    }
+
+    #[inline]
+    fn read_json_syntax_char(&mut self, expected: u8) -> crate::Result<()> {
+        let ch = self.read_byte()?;
+        if ch != expected {
+            return Err(new_protocol_error(
+                ProtocolErrorKind::InvalidData,
+                format!("expected '{}' but found '{}'", expected as char, ch as char),
+            ));
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn read_context_separator(&mut self) -> crate::Result<()> {
+        match self.context.next() {
+            Some(sep) => self.read_json_syntax_char(sep),
+            None => Ok(()),
+        }
+    }
+
+    /// Read a quoted string and unescape it, without touching the context.
+    fn read_json_escaped(&mut self) -> crate::Result<Vec<u8>> {
+        self.read_json_syntax_char(JSON_STRING_DELIMITER)?;
+        let mut output = Vec::new();
+        // \uXXXX escapes are UTF-16 code units, surrogate pairs arrive as two escapes.
+        let mut code_units: Vec<u16> = Vec::new();
+        loop {
+            let ch = self.read_byte()?;
+            if ch == JSON_STRING_DELIMITER {
+                break;
+            }
+            // Strings have no length prefix, so the limit is checked as they
+            // grow.
+            self.limits.check_string_length(output.len() + 1)?;
+            if ch != JSON_BACKSLASH {
+                flush_code_units(&mut code_units, &mut output)?;
+                output.push(ch);
+                continue;
+            }
+            let escaped = self.read_byte()?;
+            if escaped == b'u' {
+                let mut hex = [0u8; 4];
+                for h in hex.iter_mut() {
+                    *h = self.read_byte()?;
+                }
+                let hex = std::str::from_utf8(&hex).map_err(|_| invalid_escape())?;
+                code_units.push(u16::from_str_radix(hex, 16).map_err(|_| invalid_escape())?);
+                continue;
+            }
+            flush_code_units(&mut code_units, &mut output)?;
+            output.push(match escaped {
+                b'"' => b'"',
+                b'\\' => b'\\',
+                b'/' => b'/',
+                b'b' => 0x08,
+                b'f' => 0x0C,
+                b'n' => 0x0A,
+                b'r' => 0x0D,
+                b't' => 0x09,
+                _ => return Err(invalid_escape()),
+            });
+        }
+        flush_code_units(&mut code_units, &mut output)?;
+        Ok(output)
+    }
+
+    #[inline]
+    fn read_json_string(&mut self) -> crate::Result<Vec<u8>> {
+        self.read_context_separator()?;
+        self.read_json_escaped()
+    }
+
+    #[inline]
+    fn read_json_numeric_chars(&mut self) -> crate::Result<String> {
+        let mut output = String::new();
+        loop {
+            // Inside a container a number is always followed by a delimiter,
+            // running out of data there means the message is incomplete.
+            if !self.buf.has_remaining() && matches!(self.context, JsonContext::Base) {
+                break;
+            }
+            let ch = self.peek()?;
+            match ch {
+                b'+' | b'-' | b'.' | b'0'..=b'9' | b'E' | b'e' => {
+                    self.buf.advance(1);
+                    output.push(ch as char);
+                }
+                _ => break,
+            }
+        }
+        Ok(output)
+    }
+
+    #[inline]
+    fn read_json_integer(&mut self) -> crate::Result<i64> {
+        self.read_context_separator()?;
+        let escape = self.context.escape_num();
+        if escape {
+            self.read_json_syntax_char(JSON_STRING_DELIMITER)?;
+        }
+        let chars = self.read_json_numeric_chars()?;
+        if escape {
+            self.read_json_syntax_char(JSON_STRING_DELIMITER)?;
+        }
+        chars.parse::<i64>().map_err(|_| {
+            new_protocol_error(
+                ProtocolErrorKind::InvalidData,
+                format!("cannot parse {:?} as integer", chars),
+            )
+        })
+    }
+
+    #[inline]
+    fn read_json_double(&mut self) -> crate::Result<f64> {
+        self.read_context_separator()?;
+        if self.peek()? == JSON_STRING_DELIMITER {
+            let raw = self.read_json_escaped()?;
+            let s = String::from_utf8(raw)?;
+            return match s.as_str() {
+                THRIFT_NAN => Ok(f64::NAN),
+                THRIFT_INFINITY => Ok(f64::INFINITY),
+                THRIFT_NEGATIVE_INFINITY => Ok(f64::NEG_INFINITY),
+                // Only keys may quote ordinary numbers.
+                _ if self.context.escape_num() => s.parse::<f64>().map_err(|_| {
+                    new_protocol_error(
+                        ProtocolErrorKind::InvalidData,
+                        format!("cannot parse {:?} as double", s),
+                    )
+                }),
+                _ => Err(new_protocol_error(
+                    ProtocolErrorKind::InvalidData,
+                    format!("numeric data unexpectedly quoted: {:?}", s),
+                )),
+            };
+        }
+        if self.context.escape_num() {
+            // A quote is required here, trigger the error.
+            self.read_json_syntax_char(JSON_STRING_DELIMITER)?;
+        }
+        let chars = self.read_json_numeric_chars()?;
+        chars.parse::<f64>().map_err(|_| {
+            new_protocol_error(
+                ProtocolErrorKind::InvalidData,
+                format!("cannot parse {:?} as double", chars),
+            )
+        })
+    }
+
+    #[inline]
+    fn read_json_base64(&mut self) -> crate::Result<Vec<u8>> {
+        let raw = self.read_json_string()?;
+        // Accept both padded and unpadded input.
+        let trimmed = match raw.iter().rposition(|&c| c != b'=') {
+            Some(end) => &raw[..=end],
+            None => &raw[..0],
+        };
+        base64::decode_config(trimmed, base64::STANDARD_NO_PAD).map_err(|e| {
+            new_protocol_error(
+                ProtocolErrorKind::InvalidData,
+                format!("invalid base64 data: {}", e),
+            )
+        })
+    }
+
+    #[inline]
+    fn read_json_object_start(&mut self) -> crate::Result<()> {
+        self.read_context_separator()?;
+        self.read_json_syntax_char(JSON_OBJECT_START)?;
+        self.push_context(JsonContext::Pair {
+            first: true,
+            colon: true,
+        });
+        Ok(())
+    }
+
+    #[inline]
+    fn read_json_object_end(&mut self) -> crate::Result<()> {
+        self.read_json_syntax_char(JSON_OBJECT_END)?;
+        self.pop_context()
+    }
+
+    #[inline]
+    fn read_json_array_start(&mut self) -> crate::Result<()> {
+        self.read_context_separator()?;
+        self.read_json_syntax_char(JSON_ARRAY_START)?;
+        self.push_context(JsonContext::List { first: true });
+        Ok(())
+    }
+
+    #[inline]
+    fn read_json_array_end(&mut self) -> crate::Result<()> {
+        self.read_json_syntax_char(JSON_ARRAY_END)?;
+        self.pop_context()
+    }
+
+    #[inline]
+    fn read_json_type(&mut self) -> crate::Result<TType> {
+        let name = self.read_json_string()?;
+        name_to_type(&name)
+    }
+
+    #[inline]
+    fn read_json_size(&mut self) -> crate::Result<i32> {
+        let size = self.read_json_integer()?;
+        let size = i32::try_from(size).map_err(|_| {
+            new_protocol_error(
+                ProtocolErrorKind::SizeLimit,
+                format!("container size {} out of range", size),
+            )
+        })?;
+        self.limits
+            .check_container_bytes(size, self.buf.remaining())?;
+        Ok(size)
+    }
+
+    #[inline]
+    fn read_json_narrow_integer<N: TryFrom<i64>>(&mut self) -> crate::Result<N> {
+        let value = self.read_json_integer()?;
+        N::try_from(value).map_err(|_| {
+            new_protocol_error(
+                ProtocolErrorKind::InvalidData,
+                format!("integer {} out of range", value),
+            )
+        })
+    }
+}
+
+impl<T> TInputProtocol for TJSONInputProtocol<T>
+where
+    T: bytes::Buf,
+{
+    #[inline]
+    fn read_message_begin(&mut self) -> crate::Result<TMessageIdentifier> {
+        self.read_json_array_start()?;
+        let version = self.read_json_integer()?;
+        if version != JSON_PROTOCOL_VERSION {
+            return Err(new_protocol_error(
+                ProtocolErrorKind::BadVersion,
+                format!("received bad version: {}", version),
+            ));
+        }
+        let name = String::from_utf8(self.read_json_string()?)?;
+        let message_type = self.read_json_integer()?;
+        let message_type = u8::try_from(message_type)
+            .map_err(|_| {
+                new_protocol_error(
+                    ProtocolErrorKind::InvalidData,
+                    format!("cannot convert {} to TMessageType", message_type),
+                )
+            })
+            .and_then(TMessageType::try_from)?;
+        let sequence_number = self.read_json_narrow_integer()?;
+        Ok(TMessageIdentifier::new(name, message_type, sequence_number))
+    }
+
+    #[inline]
+    fn read_message_end(&mut self) -> crate::Result<()> {
+        self.read_json_array_end()
+    }
+
+    #[inline]
+    fn read_struct_begin(&mut self) -> crate::Result<Option<TStructIdentifier>> {
+        self.read_json_object_start()?;
+        Ok(None)
+    }
+
+    #[inline]
+    fn read_struct_end(&mut self) -> crate::Result<()> {
+        self.read_json_object_end()
+    }
+
+    #[inline]
+    fn read_field_begin(&mut self) -> crate::Result<TFieldIdentifier> {
+        // There is no stop marker in JSON, the end of the object stands for it.
+        if self.peek()? == JSON_OBJECT_END {
+            return Ok(
+                TFieldIdentifier::new::<Option<String>, String, Option<i16>>(
+                    None,
+                    TType::Stop,
+                    None,
+                ),
+            );
+        }
+        let id = self.read_json_integer()?;
+        let id = i16::try_from(id).map_err(|_| {
+            new_protocol_error(
+                ProtocolErrorKind::InvalidData,
+                format!("field id {} out of range", id),
+            )
+        })?;
+        self.read_json_object_start()?;
+        let field_type = self.read_json_type()?;
+        Ok(TFieldIdentifier::new::<Option<String>, String, i16>(
+            None, field_type, id,
+        ))
+    }
+
+    #[inline]
+    fn read_field_end(&mut self) -> crate::Result<()> {
+        self.read_json_object_end()
+    }
+
+    #[inline]
+    fn read_bool(&mut self) -> crate::Result<bool> {
+        Ok(self.read_json_integer()? != 0)
+    }
+
+    #[inline]
+    fn read_bytes(&mut self) -> crate::Result<Vec<u8>> {
+        self.read_json_base64()
+    }
+
+    #[inline]
+    fn read_i8(&mut self) -> crate::Result<i8> {
+        self.read_json_narrow_integer()
+    }
+
+    #[inline]
+    fn read_i16(&mut self) -> crate::Result<i16> {
+        self.read_json_narrow_integer()
+    }
+
+    #[inline]
+    fn read_i32(&mut self) -> crate::Result<i32> {
+        self.read_json_narrow_integer()
+    }
+
+    #[inline]
+    fn read_i64(&mut self) -> crate::Result<i64> {
+        self.read_json_integer()
+    }
+
+    #[inline]
+    fn read_double(&mut self) -> crate::Result<f64> {
+        self.read_json_double()
+    }
+
+    #[inline]
+    fn read_string(&mut self) -> crate::Result<String> {
+        let bytes = self.read_json_string()?;
+        String::from_utf8(bytes).map_err(From::from)
+    }
+
+    #[inline]
+    fn read_uuid(&mut self) -> crate::Result<[u8; 16]> {
+        let raw = self.read_json_string()?;
+        parse_uuid(&raw)
+    }
+
+    #[inline]
+    fn read_list_begin(&mut self) -> crate::Result<TListIdentifier> {
+        self.read_json_array_start()?;
+        let element_type = self.read_json_type()?;
+        let size = self.read_json_size()?;
+        Ok(TListIdentifier::new(element_type, size))
+    }
+
+    #[inline]
+    fn read_list_end(&mut self) -> crate::Result<()> {
+        self.read_json_array_end()
+    }
+
+    #[inline]
+    fn read_set_begin(&mut self) -> crate::Result<TSetIdentifier> {
+        self.read_json_array_start()?;
+        let element_type = self.read_json_type()?;
+        let size = self.read_json_size()?;
+        Ok(TSetIdentifier::new(element_type, size))
+    }
+
+    #[inline]
+    fn read_set_end(&mut self) -> crate::Result<()> {
+        self.read_json_array_end()
+    }
+
+    #[inline]
+    fn read_map_begin(&mut self) -> crate::Result<TMapIdentifier> {
+        self.read_json_array_start()?;
+        let key_type = self.read_json_type()?;
+        let value_type = self.read_json_type()?;
+        let size = self.read_json_size()?;
+        self.read_json_object_start()?;
+        Ok(TMapIdentifier::new(key_type, value_type, size))
+    }
+
+    #[inline]
+    fn read_map_end(&mut self) -> crate::Result<()> {
+        self.read_json_object_end()?;
+        self.read_json_array_end()
+    }
+
+    // utility
+    //
+
+    #[inline]
+    fn read_byte(&mut self) -> crate::Result<u8> {
+        protocol_len_check(&self.buf, 1)?;
+        Ok(self.buf.get_u8())
+    }
+
+    fn set_decode_limits(&mut self, limits: DecodeLimits) {
+        self.limits = limits;
+    }
+}
+
+/// Factory for JSON protocol instances.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TJSONProtocolFactory;
+
+impl TJSONProtocolFactory {
+    pub fn new() -> TJSONProtocolFactory {
+        TJSONProtocolFactory
+    }
+}
+
+impl TProtocolFactory for TJSONProtocolFactory {
+    fn input_protocol<'a>(&self, buf: &'a mut BytesMut) -> Box<dyn TInputProtocol + 'a> {
+        Box::new(TJSONInputProtocol::new(buf))
+    }
+
+    fn slice_input_protocol<'a>(&self, buf: &'a mut &[u8]) -> Box<dyn TInputProtocol + 'a> {
+        Box::new(TJSONInputProtocol::new(buf))
+    }
+
+    fn output_protocol<'a>(&self, buf: &'a mut BytesMut) -> Box<dyn TOutputProtocol + 'a> {
+        Box::new(TJSONOutputProtocol::new(buf))
+    }
+
+    fn length_protocol(&self) -> Box<dyn TLengthProtocol> {
+        Box::new(TJSONLengthProtocol::new())
+    }
+
+    fn header_protocol_id(&self) -> Option<i32> {
+        Some(crate::header::HEADER_PROTOCOL_JSON)
+    }
+}
+
+/// Write `b` as a quoted JSON string, escaping control characters.
+#[inline]
+pub(crate) fn write_json_escaped<T: bytes::BufMut>(buf: &mut T, b: &[u8]) {
+    buf.put_u8(JSON_STRING_DELIMITER);
+    for &c in b {
+        match c {
+            JSON_STRING_DELIMITER | JSON_BACKSLASH => {
+                buf.put_u8(JSON_BACKSLASH);
+                buf.put_u8(c);
+            }
+            0x08 => buf.put_slice(b"\\b"),
+            0x09 => buf.put_slice(b"\\t"),
+            0x0A => buf.put_slice(b"\\n"),
+            0x0C => buf.put_slice(b"\\f"),
+            0x0D => buf.put_slice(b"\\r"),
+            c if c < 0x20 => {
+                buf.put_slice(format!("\\u{:04x}", c).as_bytes());
+            }
+            c => buf.put_u8(c),
+        }
+    }
+    buf.put_u8(JSON_STRING_DELIMITER);
+}
+
+#[inline]
+fn flush_code_units(code_units: &mut Vec<u16>, output: &mut Vec<u8>) -> crate::Result<()> {
+    if code_units.is_empty() {
+        return Ok(());
+    }
+    let s = String::from_utf16(code_units).map_err(|_| invalid_escape())?;
+    output.extend_from_slice(s.as_bytes());
+    code_units.clear();
+    Ok(())
+}
+
+#[inline]
+fn invalid_escape() -> crate::Error {
+    new_protocol_error(ProtocolErrorKind::InvalidData, "invalid json escape")
+}
+
+/// Format a UUID in its canonical hyphenated form, like
+/// `00112233-4455-6677-8899-aabbccddeeff`.
+pub(crate) fn format_uuid(uuid: &[u8; 16]) -> String {
+    let mut s = String::with_capacity(36);
+    for (i, b) in uuid.iter().enumerate() {
+        if i == 4 || i == 6 || i == 8 || i == 10 {
+            s.push('-');
+        }
+        s.push_str(&format!("{:02x}", b));
+    }
+    s
+}
+
+fn parse_uuid(raw: &[u8]) -> crate::Result<[u8; 16]> {
+    let invalid = || {
+        new_protocol_error(
+            ProtocolErrorKind::InvalidData,
+            format!("invalid uuid {:?}", String::from_utf8_lossy(raw)),
+        )
+    };
+    if raw.len() != 36 || [8, 13, 18, 23].iter().any(|&i| raw[i] != b'-') {
+        return Err(invalid());
+    }
+    let mut digits = raw
+        .iter()
+        .filter(|&&c| c != b'-')
+        .map(|&c| (c as char).to_digit(16));
+    let mut uuid = [0; 16];
+    for b in uuid.iter_mut() {
+        match (digits.next().flatten(), digits.next().flatten()) {
+            (Some(hi), Some(lo)) => *b = (hi << 4 | lo) as u8,
+            _ => return Err(invalid()),
+        }
+    }
+    Ok(uuid)
+}
+
+#[inline]
+fn type_to_name(field_type: TType) -> crate::Result<&'static str> {
+    match field_type {
+        TType::Bool => Ok("tf"),
+        TType::I08 => Ok("i8"),
+        TType::I16 => Ok("i16"),
+        TType::I32 => Ok("i32"),
+        TType::I64 => Ok("i64"),
+        TType::Double => Ok("dbl"),
+        TType::String => Ok("str"),
+        TType::Struct => Ok("rec"),
+        TType::Map => Ok("map"),
+        TType::Set => Ok("set"),
+        TType::List => Ok("lst"),
+        TType::Uuid => Ok("uid"),
+        unkn => Err(new_protocol_error(
+            ProtocolErrorKind::NotImplemented,
+            format!("unsupported field type {:?} in json protocol", unkn),
+        )),
+    }
+}
+
+#[inline]
+fn name_to_type(name: &[u8]) -> crate::Result<TType> {
+    match name {
+        b"tf" => Ok(TType::Bool),
+        b"i8" => Ok(TType::I08),
+        b"i16" => Ok(TType::I16),
+        b"i32" => Ok(TType::I32),
+        b"i64" => Ok(TType::I64),
+        b"dbl" => Ok(TType::Double),
+        b"str" => Ok(TType::String),
+        b"rec" => Ok(TType::Struct),
+        b"map" => Ok(TType::Map),
+        b"set" => Ok(TType::Set),
+        b"lst" => Ok(TType::List),
+        b"uid" => Ok(TType::Uuid),
+        unkn => Err(new_protocol_error(
+            ProtocolErrorKind::InvalidData,
+            format!(
+                "cannot convert {:?} into TType",
+                String::from_utf8_lossy(unkn)
+            ),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::BytesMut;
+
+    use crate::json::{TJSONInputProtocol, TJSONOutputProtocol};
+    use crate::protocol::{
+        TFieldIdentifier, TInputProtocol, TListIdentifier, TMapIdentifier, TMessageIdentifier,
+        TMessageType, TOutputProtocol, TStructIdentifier, TType,
+    };
+
+    #[test]
+    fn test_message_envelope() {
+        let mut buf = BytesMut::new();
+        let mut protocol = TJSONOutputProtocol::new(&mut buf);
+        protocol
+            .write_message_begin(&TMessageIdentifier::new("foo", TMessageType::Reply, 7))
+            .unwrap();
+        protocol
+            .write_struct_begin(&TStructIdentifier::new("s"))
+            .unwrap();
+        protocol
+            .write_field_begin(&TFieldIdentifier::new("id", TType::I32, 1))
+            .unwrap();
+        protocol.write_i32(-3).unwrap();
+        protocol.write_field_end().unwrap();
+        protocol
+            .write_field_begin(&TFieldIdentifier::new("ok", TType::Bool, 2))
+            .unwrap();
+        protocol.write_bool(true).unwrap();
+        protocol.write_field_end().unwrap();
+        protocol.write_field_stop().unwrap();
+        protocol.write_struct_end().unwrap();
+        protocol.write_message_end().unwrap();
+
+        let expected = r#"[1,"foo",2,7,{"1":{"i32":-3},"2":{"tf":1}}]"#;
+        assert_eq!(std::str::from_utf8(&buf).unwrap(), expected);
+
+        let mut protocol = TJSONInputProtocol::new(&mut buf);
+        assert_eq!(
+            protocol.read_message_begin().unwrap(),
+            TMessageIdentifier::new("foo", TMessageType::Reply, 7)
+        );
+        protocol.read_struct_begin().unwrap();
+        let field = protocol.read_field_begin().unwrap();
+        assert_eq!((field.field_type, field.id), (TType::I32, Some(1)));
+        assert_eq!(protocol.read_i32().unwrap(), -3);
+        protocol.read_field_end().unwrap();
+        let field = protocol.read_field_begin().unwrap();
+        assert_eq!((field.field_type, field.id), (TType::Bool, Some(2)));
+        assert!(protocol.read_bool().unwrap());
+        protocol.read_field_end().unwrap();
+        assert_eq!(protocol.read_field_begin().unwrap().field_type, TType::Stop);
+        protocol.read_struct_end().unwrap();
+        protocol.read_message_end().unwrap();
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_containers() {
+        let mut buf = BytesMut::new();
+        let mut protocol = TJSONOutputProtocol::new(&mut buf);
+        protocol
+            .write_list_begin(&TListIdentifier::new(TType::String, 2))
+            .unwrap();
+        protocol.write_string("a").unwrap();
+        protocol.write_string("b").unwrap();
+        protocol.write_list_end().unwrap();
+        // numeric map keys are quoted, values are not
+        protocol
+            .write_map_begin(&TMapIdentifier::new(TType::I32, TType::Double, 2))
+            .unwrap();
+        protocol.write_i32(1).unwrap();
+        protocol.write_double(0.5).unwrap();
+        protocol.write_i32(2).unwrap();
+        protocol.write_double(f64::NAN).unwrap();
+        protocol.write_map_end().unwrap();
+        protocol
+            .write_map_begin(&TMapIdentifier::new(TType::String, TType::I64, 0))
+            .unwrap();
+        protocol.write_map_end().unwrap();
+
+        let expected = r#"["str",2,"a","b"]["i32","dbl",2,{"1":0.5,"2":"NaN"}]["str","i64",0,{}]"#;
+        assert_eq!(std::str::from_utf8(&buf).unwrap(), expected);
+
+        let mut protocol = TJSONInputProtocol::new(&mut buf);
+        assert_eq!(
+            protocol.read_list_begin().unwrap(),
+            TListIdentifier::new(TType::String, 2)
+        );
+        assert_eq!(protocol.read_string().unwrap(), "a");
+        assert_eq!(protocol.read_string().unwrap(), "b");
+        protocol.read_list_end().unwrap();
+        assert_eq!(
+            protocol.read_map_begin().unwrap(),
+            TMapIdentifier::new(TType::I32, TType::Double, 2)
+        );
+        assert_eq!(protocol.read_i32().unwrap(), 1);
+        assert_eq!(protocol.read_double().unwrap(), 0.5);
+        assert_eq!(protocol.read_i32().unwrap(), 2);
+        assert!(protocol.read_double().unwrap().is_nan());
+        protocol.read_map_end().unwrap();
+        assert_eq!(
+            protocol.read_map_begin().unwrap(),
+            TMapIdentifier::new(TType::String, TType::I64, 0)
+        );
+        protocol.read_map_end().unwrap();
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_special_doubles() {
+        let mut buf = BytesMut::new();
+        let mut protocol = TJSONOutputProtocol::new(&mut buf);
+        protocol
+            .write_list_begin(&TListIdentifier::new(TType::Double, 3))
+            .unwrap();
+        protocol.write_double(f64::INFINITY).unwrap();
+        protocol.write_double(f64::NEG_INFINITY).unwrap();
+        protocol.write_double(-1.25e-3).unwrap();
+        protocol.write_list_end().unwrap();
+
+        let expected = r#"["dbl",3,"Infinity","-Infinity",-0.00125]"#;
+        assert_eq!(std::str::from_utf8(&buf).unwrap(), expected);
+
+        let mut protocol = TJSONInputProtocol::new(&mut buf);
+        protocol.read_list_begin().unwrap();
+        assert_eq!(protocol.read_double().unwrap(), f64::INFINITY);
+        assert_eq!(protocol.read_double().unwrap(), f64::NEG_INFINITY);
+        assert_eq!(protocol.read_double().unwrap(), -1.25e-3);
+        protocol.read_list_end().unwrap();
+    }
+
+    #[test]
+    fn test_string_escape_and_base64() {
+        let mut buf = BytesMut::new();
+        let mut protocol = TJSONOutputProtocol::new(&mut buf);
+        protocol
+            .write_list_begin(&TListIdentifier::new(TType::String, 2))
+            .unwrap();
+        protocol.write_string("q\"\\\n\u{1}é").unwrap();
+        protocol.write_bytes(&[0xDE, 0xAD, 0xBE, 0xEF]).unwrap();
+        protocol.write_list_end().unwrap();
+
+        let expected = "[\"str\",2,\"q\\\"\\\\\\n\\u0001é\",\"3q2+7w\"]";
+        assert_eq!(std::str::from_utf8(&buf).unwrap(), expected);
+
+        let mut protocol = TJSONInputProtocol::new(&mut buf);
+        protocol.read_list_begin().unwrap();
+        assert_eq!(protocol.read_string().unwrap(), "q\"\\\n\u{1}é");
+        assert_eq!(protocol.read_bytes().unwrap(), vec![0xDE, 0xAD, 0xBE, 0xEF]);
+        protocol.read_list_end().unwrap();
+
+        // padded base64 and \u escapes with surrogate pairs are accepted as well
+        let mut buf = BytesMut::from(&b"[\"str\",2,\"3q2+7w==\",\"\\ud83d\\ude00\\u00e9\"]"[..]);
+        let mut protocol = TJSONInputProtocol::new(&mut buf);
+        protocol.read_list_begin().unwrap();
+        assert_eq!(protocol.read_bytes().unwrap(), vec![0xDE, 0xAD, 0xBE, 0xEF]);
+        assert_eq!(protocol.read_string().unwrap(), "\u{1F600}é");
+        protocol.read_list_end().unwrap();
+    }
+
+    #[test]
+    fn test_uuid() {
+        let uuid = [
+            0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xAA, 0xBB, 0xCC, 0xDD,
+            0xEE, 0xFF,
+        ];
+        let mut buf = BytesMut::new();
+        let mut protocol = TJSONOutputProtocol::new(&mut buf);
+        protocol
+            .write_list_begin(&TListIdentifier::new(TType::Uuid, 1))
+            .unwrap();
+        protocol.write_uuid(uuid).unwrap();
+        protocol.write_list_end().unwrap();
+
+        let expected = "[\"uid\",1,\"00112233-4455-6677-8899-aabbccddeeff\"]";
+        assert_eq!(std::str::from_utf8(&buf).unwrap(), expected);
+
+        let mut protocol = TJSONInputProtocol::new(&mut buf);
+        assert_eq!(
+            protocol.read_list_begin().unwrap().element_type,
+            TType::Uuid
+        );
+        assert_eq!(protocol.read_uuid().unwrap(), uuid);
+        protocol.read_list_end().unwrap();
+
+        let mut buf = BytesMut::from(&b"\"00112233-4455-6677-8899aabbccddeeff0\""[..]);
+        let mut protocol = TJSONInputProtocol::new(&mut buf);
+        assert!(protocol.read_uuid().is_err());
+    }
+
+    #[test]
+    fn test_skip_unknown_fields() {
+        let mut buf = BytesMut::from(
+            &br#"{"1":{"map":["str","lst",1,{"k":["tf",2,1,0]}]},"2":{"rec":{}}}7"#[..],
+        );
+        let mut protocol = TJSONInputProtocol::new(&mut buf);
+        protocol.skip(TType::Struct).unwrap();
+        assert_eq!(protocol.read_i32().unwrap(), 7);
+    }
+
+    #[test]
+    fn test_integer_out_of_range() {
+        let mut buf = BytesMut::from(&br#"{"1":{"i8":300}}"#[..]);
+        let mut protocol = TJSONInputProtocol::new(&mut buf);
+        protocol.read_struct_begin().unwrap();
+        let field = protocol.read_field_begin().unwrap();
+        assert_eq!(field.field_type, TType::I08);
+        assert!(protocol.read_i8().is_err());
+
+        let mut buf = BytesMut::from(&b"-32769"[..]);
+        let mut protocol = TJSONInputProtocol::new(&mut buf);
+        assert!(protocol.read_i16().is_err());
+        let mut buf = BytesMut::from(&b"2147483648"[..]);
+        let mut protocol = TJSONInputProtocol::new(&mut buf);
+        assert!(protocol.read_i32().is_err());
+
+        let mut buf = BytesMut::from(&br#"[1,"ping",1,2147483648]"#[..]);
+        let mut protocol = TJSONInputProtocol::new(&mut buf);
+        assert!(protocol.read_message_begin().is_err());
+    }
+}
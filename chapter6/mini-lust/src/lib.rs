@@ -1,32 +1,81 @@
 #![cfg_attr(feature = "unstable", feature(core_intrinsics))]
 
-pub use client::{Client, ClientBuilder};
-pub use codec::DefaultMakeCodec;
+pub use binary::{
+    TBinaryInputProtocol, TBinaryLengthProtocol, TBinaryOutputProtocol, TBinaryProtocolFactory,
+};
+pub use balance::Balance;
+// Export Bytes for `binary` fields decoded without copying
+pub use bytes::Bytes;
+pub use client::{CallOptions, Client, ClientBuilder};
+pub use codec::{CodecOptions, DefaultMakeCodec, Framing};
+pub use compression::{CompressedCodec, Compression, CompressionOptions};
+pub use compact::{
+    TCompactInputProtocol, TCompactLengthProtocol, TCompactOutputProtocol, TCompactProtocolFactory,
+};
+#[cfg(target_os = "linux")]
+pub use connection::abstract_unix_path;
 pub use connection::{DefaultMakeConnection, SocketOrUnix};
 pub use context::MsgContext;
+pub use discover::{EndpointsFile, StaticList};
 // Export the error
 pub use errors::*;
+pub use header::THeaderCodec;
+pub use http::HttpCodec;
+pub use json::{
+    TJSONInputProtocol, TJSONLengthProtocol, TJSONOutputProtocol, TJSONProtocolFactory,
+};
+pub use limits::{DecodeLimits, TLimitedInputProtocol};
+pub use memory::{MemoryAddr, MemoryListenerStream};
 pub use message::Message;
+pub use multiplex::{MultiplexedRequest, MultiplexedResponse, MultiplexedServer};
+pub use pool::PoolConfig;
 pub use protocol::{
-    TFieldIdentifier, TInputProtocol, TListIdentifier, TMapIdentifier, TMessageType,
-    TOutputProtocol, TStructIdentifier, TType,
+    TFieldIdentifier, TInputProtocol, TLengthProtocol, TListIdentifier, TMapIdentifier,
+    TMessageType, TOutputProtocol, TProtocolFactory, TStructIdentifier, TType,
+};
+pub use resolve::{DnsResolver, Resolve};
+pub use retry::{is_transient, RetryPolicy};
+pub use server::{Listenable, Server, ServerError};
+pub use simple_json::{to_simple_json, TSimpleJSONOutputProtocol};
+pub use tls::{
+    ClientTlsConfig, PeerIdentity, ServerTlsConfig, TlsIncoming, TlsListener, TlsMakeConnection,
 };
-pub use server::{Server, ServerError};
 pub use transport::FramedMakeTransport;
+// Export Change for discovery sources
+pub use tower::discover::Change;
 pub use types::OrigType;
 pub use utils::{ttype_comparing, BoxFuture};
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+mod balance;
 mod binary;
 mod client;
 mod codec;
+mod compact;
+mod compression;
 mod connection;
 mod context;
+mod detect;
+mod discover;
 mod errors;
+mod header;
+mod http;
+mod json;
+mod limits;
+mod memory;
 mod message;
+mod multiplex;
+mod pipeline;
+mod pool;
 mod protocol;
+mod resolve;
+mod retry;
 mod server;
+mod simple_json;
+#[cfg(test)]
+mod testing;
+mod tls;
 mod transport;
 mod types;
 mod utils;
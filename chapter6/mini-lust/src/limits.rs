@@ -0,0 +1,441 @@
+//! Bounds on what a peer may make us decode.
+//!
+//! The frame size only bounds the bytes on the wire. A list header claiming
+//! two billion elements or deeply nested structs inside a small frame would
+//! still make the generated decoders allocate or recurse without limit, so
+//! codecs read messages through a `TLimitedInputProtocol`. It hands the limits
+//! to the protocol it wraps, which checks each length prefix before reading
+//! what follows.
+
+use bytes::Bytes;
+
+use crate::codec::MAX_MESSAGE_SIZE;
+use crate::protocol::{
+    TFieldIdentifier, TInputProtocol, TListIdentifier, TMapIdentifier, TMessageIdentifier,
+    TSetIdentifier, TStructIdentifier,
+};
+use crate::{new_protocol_error, new_transport_error, ProtocolErrorKind, TransportErrorKind};
+
+/// Limits enforced while decoding a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeLimits {
+    /// Max length in bytes of a string or binary.
+    pub max_string_length: usize,
+    /// Max number of elements of a list, set or map.
+    pub max_container_size: usize,
+    /// Max nesting depth of structs and containers.
+    pub max_depth: usize,
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        Self {
+            max_string_length: MAX_MESSAGE_SIZE,
+            max_container_size: MAX_MESSAGE_SIZE,
+            max_depth: 64,
+        }
+    }
+}
+
+impl DecodeLimits {
+    /// Fails with `SizeLimit` if a string or binary of `length` bytes is too
+    /// long.
+    pub(crate) fn check_string_length(&self, length: usize) -> crate::Result<()> {
+        if length > self.max_string_length {
+            return Err(new_protocol_error(
+                ProtocolErrorKind::SizeLimit,
+                format!(
+                    "string length {} exceeds {}",
+                    length, self.max_string_length
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Fails with `NegativeSize` or `SizeLimit` if a container of `size`
+    /// elements is invalid or too large.
+    pub(crate) fn check_container_size(&self, size: i32) -> crate::Result<()> {
+        if size < 0 {
+            return Err(new_protocol_error(
+                ProtocolErrorKind::NegativeSize,
+                format!("negative container size {}", size),
+            ));
+        }
+        if size as usize > self.max_container_size {
+            return Err(new_protocol_error(
+                ProtocolErrorKind::SizeLimit,
+                format!(
+                    "container size {} exceeds {}",
+                    size, self.max_container_size
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Like `check_container_size`, also failing with `EndOfFile` if the
+    /// `remaining` bytes cannot hold that many elements.
+    pub(crate) fn check_container_bytes(&self, size: i32, remaining: usize) -> crate::Result<()> {
+        self.check_container_size(size)?;
+        // Every element takes at least one byte, so the input bounds what
+        // decoding the container may preallocate.
+        if size as usize > remaining {
+            return Err(new_transport_error(
+                TransportErrorKind::EndOfFile,
+                "unexpected data length",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Wraps a `TInputProtocol` and fails with `SizeLimit` or `DepthLimit` when a
+/// read goes beyond the `DecodeLimits`.
+pub struct TLimitedInputProtocol<P> {
+    inner: P,
+    limits: DecodeLimits,
+    depth: usize,
+}
+
+impl<P> TLimitedInputProtocol<P> {
+    pub fn new(mut inner: P, limits: DecodeLimits) -> Self
+    where
+        P: TInputProtocol,
+    {
+        inner.set_decode_limits(limits);
+        Self {
+            inner,
+            limits,
+            depth: 0,
+        }
+    }
+
+    pub fn into_inner(self) -> P {
+        self.inner
+    }
+
+    fn enter(&mut self) -> crate::Result<()> {
+        if self.depth >= self.limits.max_depth {
+            return Err(new_protocol_error(
+                ProtocolErrorKind::DepthLimit,
+                format!("nesting depth exceeds {}", self.limits.max_depth),
+            ));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn exit(&mut self) {
+        self.depth = self.depth.saturating_sub(1);
+    }
+}
+
+impl<P> TInputProtocol for TLimitedInputProtocol<P>
+where
+    P: TInputProtocol,
+{
+    #[inline]
+    fn read_message_begin(&mut self) -> crate::Result<TMessageIdentifier> {
+        let identifier = self.inner.read_message_begin()?;
+        self.limits.check_string_length(identifier.name.len())?;
+        Ok(identifier)
+    }
+
+    #[inline]
+    fn read_message_end(&mut self) -> crate::Result<()> {
+        self.inner.read_message_end()
+    }
+
+    #[inline]
+    fn read_struct_begin(&mut self) -> crate::Result<Option<TStructIdentifier>> {
+        self.enter()?;
+        self.inner.read_struct_begin()
+    }
+
+    #[inline]
+    fn read_struct_end(&mut self) -> crate::Result<()> {
+        self.exit();
+        self.inner.read_struct_end()
+    }
+
+    #[inline]
+    fn read_field_begin(&mut self) -> crate::Result<TFieldIdentifier> {
+        self.inner.read_field_begin()
+    }
+
+    #[inline]
+    fn read_field_end(&mut self) -> crate::Result<()> {
+        self.inner.read_field_end()
+    }
+
+    #[inline]
+    fn read_bool(&mut self) -> crate::Result<bool> {
+        self.inner.read_bool()
+    }
+
+    #[inline]
+    fn read_bytes(&mut self) -> crate::Result<Vec<u8>> {
+        let bytes = self.inner.read_bytes()?;
+        self.limits.check_string_length(bytes.len())?;
+        Ok(bytes)
+    }
+
+    #[inline]
+    fn read_binary(&mut self) -> crate::Result<Bytes> {
+        let bytes = self.inner.read_binary()?;
+        self.limits.check_string_length(bytes.len())?;
+        Ok(bytes)
+    }
+
+    #[inline]
+    fn read_i8(&mut self) -> crate::Result<i8> {
+        self.inner.read_i8()
+    }
+
+    #[inline]
+    fn read_i16(&mut self) -> crate::Result<i16> {
+        self.inner.read_i16()
+    }
+
+    #[inline]
+    fn read_i32(&mut self) -> crate::Result<i32> {
+        self.inner.read_i32()
+    }
+
+    #[inline]
+    fn read_i64(&mut self) -> crate::Result<i64> {
+        self.inner.read_i64()
+    }
+
+    #[inline]
+    fn read_double(&mut self) -> crate::Result<f64> {
+        self.inner.read_double()
+    }
+
+    #[inline]
+    fn read_string(&mut self) -> crate::Result<String> {
+        let s = self.inner.read_string()?;
+        self.limits.check_string_length(s.len())?;
+        Ok(s)
+    }
+
+    #[inline]
+    fn read_uuid(&mut self) -> crate::Result<[u8; 16]> {
+        self.inner.read_uuid()
+    }
+
+    #[inline]
+    fn read_list_begin(&mut self) -> crate::Result<TListIdentifier> {
+        let identifier = self.inner.read_list_begin()?;
+        self.limits.check_container_size(identifier.size)?;
+        self.enter()?;
+        Ok(identifier)
+    }
+
+    #[inline]
+    fn read_list_end(&mut self) -> crate::Result<()> {
+        self.exit();
+        self.inner.read_list_end()
+    }
+
+    #[inline]
+    fn read_set_begin(&mut self) -> crate::Result<TSetIdentifier> {
+        let identifier = self.inner.read_set_begin()?;
+        self.limits.check_container_size(identifier.size)?;
+        self.enter()?;
+        Ok(identifier)
+    }
+
+    #[inline]
+    fn read_set_end(&mut self) -> crate::Result<()> {
+        self.exit();
+        self.inner.read_set_end()
+    }
+
+    #[inline]
+    fn read_map_begin(&mut self) -> crate::Result<TMapIdentifier> {
+        let identifier = self.inner.read_map_begin()?;
+        self.limits.check_container_size(identifier.size)?;
+        self.enter()?;
+        Ok(identifier)
+    }
+
+    #[inline]
+    fn read_map_end(&mut self) -> crate::Result<()> {
+        self.exit();
+        self.inner.read_map_end()
+    }
+
+    #[inline]
+    fn read_byte(&mut self) -> crate::Result<u8> {
+        self.inner.read_byte()
+    }
+
+    fn set_decode_limits(&mut self, limits: DecodeLimits) {
+        self.limits = limits;
+        self.inner.set_decode_limits(limits);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::BytesMut;
+
+    use crate::binary::{TBinaryInputProtocol, TBinaryOutputProtocol, TBinaryProtocolFactory};
+    use crate::compact::TCompactProtocolFactory;
+    use crate::context::MsgContext;
+    use crate::errors::{Error, ProtocolErrorKind, TransportErrorKind};
+    use crate::json::TJSONProtocolFactory;
+    use crate::limits::{DecodeLimits, TLimitedInputProtocol};
+    use crate::message::Message;
+    use crate::protocol::{
+        TInputProtocol, TListIdentifier, TOutputProtocol, TProtocolFactory, TStructIdentifier,
+        TType,
+    };
+
+    fn protocol_error_kind<T: std::fmt::Debug>(r: crate::Result<T>) -> ProtocolErrorKind {
+        match r {
+            Err(Error::Protocol(e)) => e.kind,
+            r => panic!("expected a protocol error, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_size_limits() {
+        let limits = DecodeLimits {
+            max_string_length: 4,
+            max_container_size: 2,
+            ..DecodeLimits::default()
+        };
+        let mut buf = BytesMut::new();
+        let mut protocol = TBinaryOutputProtocol::new(&mut buf, true);
+        protocol.write_string("abcd").unwrap();
+        protocol
+            .write_list_begin(&TListIdentifier::new(TType::I32, 2))
+            .unwrap();
+        protocol
+            .write_list_begin(&TListIdentifier::new(TType::I32, i32::MAX))
+            .unwrap();
+        protocol
+            .write_list_begin(&TListIdentifier::new(TType::I32, -1))
+            .unwrap();
+        protocol.write_string("abcde").unwrap();
+
+        let mut protocol =
+            TLimitedInputProtocol::new(TBinaryInputProtocol::new(&mut buf, true), limits);
+        assert_eq!(protocol.read_string().unwrap(), "abcd");
+        assert_eq!(protocol.read_list_begin().unwrap().size, 2);
+        assert_eq!(
+            protocol_error_kind(protocol.read_list_begin()),
+            ProtocolErrorKind::SizeLimit
+        );
+        assert_eq!(
+            protocol_error_kind(protocol.read_list_begin()),
+            ProtocolErrorKind::NegativeSize
+        );
+        assert_eq!(
+            protocol_error_kind(protocol.read_string()),
+            ProtocolErrorKind::SizeLimit
+        );
+    }
+
+    #[test]
+    fn test_length_prefix_checked_before_reading() {
+        let limits = DecodeLimits {
+            max_string_length: 4,
+            ..DecodeLimits::default()
+        };
+        let factories: Vec<Box<dyn TProtocolFactory>> = vec![
+            Box::new(TBinaryProtocolFactory::default()),
+            Box::new(TCompactProtocolFactory::new()),
+            Box::new(TJSONProtocolFactory::new()),
+        ];
+        for factory in factories {
+            // the string is incomplete, its length alone must be refused
+            let mut buf = BytesMut::new();
+            factory
+                .output_protocol(&mut buf)
+                .write_string("abcde")
+                .unwrap();
+            buf.truncate(buf.len() - 1);
+            let mut protocol = TLimitedInputProtocol::new(factory.input_protocol(&mut buf), limits);
+            assert_eq!(
+                protocol_error_kind(protocol.read_string()),
+                ProtocolErrorKind::SizeLimit
+            );
+        }
+    }
+
+    #[test]
+    fn test_container_size_bounded_by_input() {
+        let factories: Vec<Box<dyn TProtocolFactory>> = vec![
+            Box::new(TBinaryProtocolFactory::default()),
+            Box::new(TCompactProtocolFactory::new()),
+        ];
+        for factory in factories {
+            // a few bytes cannot hold a million elements
+            let mut buf = BytesMut::new();
+            factory
+                .output_protocol(&mut buf)
+                .write_list_begin(&TListIdentifier::new(TType::I32, 1_000_000))
+                .unwrap();
+            let mut protocol = TLimitedInputProtocol::new(
+                factory.input_protocol(&mut buf),
+                DecodeLimits::default(),
+            );
+            match protocol.read_list_begin() {
+                Err(Error::Transport(e)) => assert_eq!(e.kind, TransportErrorKind::EndOfFile),
+                r => panic!("expected end of file, got {:?}", r),
+            }
+        }
+    }
+
+    #[test]
+    fn test_depth_limit() {
+        let limits = DecodeLimits {
+            max_depth: 2,
+            ..DecodeLimits::default()
+        };
+        let mut buf = BytesMut::new();
+        let mut protocol = TBinaryOutputProtocol::new(&mut buf, true);
+        // a struct holding a list of lists
+        protocol
+            .write_struct_begin(&TStructIdentifier::new("s"))
+            .unwrap();
+        protocol
+            .write_list_begin(&TListIdentifier::new(TType::List, 1))
+            .unwrap();
+        protocol
+            .write_list_begin(&TListIdentifier::new(TType::I32, 0))
+            .unwrap();
+
+        let mut protocol =
+            TLimitedInputProtocol::new(TBinaryInputProtocol::new(&mut buf, true), limits);
+        protocol.read_struct_begin().unwrap();
+        protocol.read_list_begin().unwrap();
+        assert_eq!(
+            protocol_error_kind(protocol.read_list_begin()),
+            ProtocolErrorKind::DepthLimit
+        );
+    }
+
+    #[test]
+    fn test_depth_resets_on_end() {
+        let limits = DecodeLimits {
+            max_depth: 1,
+            ..DecodeLimits::default()
+        };
+        let mut buf = BytesMut::new();
+        let mut protocol = TBinaryOutputProtocol::new(&mut buf, true);
+        for _ in 0..3 {
+            ().encode(&MsgContext::default(), &mut protocol).unwrap();
+        }
+
+        let mut protocol =
+            TLimitedInputProtocol::new(TBinaryInputProtocol::new(&mut buf, true), limits);
+        for _ in 0..3 {
+            <()>::decode(&mut MsgContext::default(), &mut protocol).unwrap();
+        }
+    }
+}
@@ -0,0 +1,152 @@
+//! In-memory connections between clients and servers of the same process.
+//!
+//! A server listening on `MemoryAddr::new(name)` registers `name`, clients
+//! dialing `SocketOrUnix::Memory(name)` are handed one end of a duplex pipe
+//! whose other end is accepted by the server.
+
+use std::collections::HashMap;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Mutex, OnceLock};
+use std::task::{Context, Poll};
+
+use futures_core::Stream;
+use tokio::io::DuplexStream;
+use tokio::sync::mpsc;
+
+use crate::server::Listenable;
+
+// Bytes buffered in each direction of a pipe.
+const PIPE_CAPACITY: usize = 64 * 1024;
+
+type Registry = Mutex<HashMap<String, mpsc::UnboundedSender<DuplexStream>>>;
+
+fn registry() -> &'static Registry {
+    static REGISTRY: OnceLock<Registry> = OnceLock::new();
+    REGISTRY.get_or_init(Default::default)
+}
+
+/// Open a pipe to the server listening on `name`.
+pub(crate) fn connect(name: &str) -> io::Result<DuplexStream> {
+    let refused = || {
+        io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            format!("no server listening in memory on {}", name),
+        )
+    };
+    let registry = registry().lock().unwrap();
+    let sender = registry.get(name).ok_or_else(refused)?;
+    let (client, server) = tokio::io::duplex(PIPE_CAPACITY);
+    sender.send(server).map_err(|_| refused())?;
+    Ok(client)
+}
+
+/// Name of an in-memory listener.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct MemoryAddr(String);
+
+impl MemoryAddr {
+    pub fn new(name: impl Into<String>) -> Self {
+        MemoryAddr(name.into())
+    }
+}
+
+#[async_trait::async_trait]
+impl Listenable for MemoryAddr {
+    type Conn = DuplexStream;
+    type Stream = MemoryListenerStream;
+
+    async fn bind(&self) -> io::Result<Self::Stream> {
+        let mut registry = registry().lock().unwrap();
+        if let Some(sender) = registry.get(&self.0) {
+            if !sender.is_closed() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("a server already listens in memory on {}", self.0),
+                ));
+            }
+        }
+        let (sender, receiver) = mpsc::unbounded_channel();
+        registry.insert(self.0.clone(), sender);
+        Ok(MemoryListenerStream {
+            name: self.0.clone(),
+            receiver,
+        })
+    }
+}
+
+/// Connections accepted by a `MemoryAddr` listener. The name is released
+/// when dropped.
+pub struct MemoryListenerStream {
+    name: String,
+    receiver: mpsc::UnboundedReceiver<DuplexStream>,
+}
+
+impl Stream for MemoryListenerStream {
+    type Item = io::Result<DuplexStream>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx).map(|conn| conn.map(Ok))
+    }
+}
+
+impl Drop for MemoryListenerStream {
+    fn drop(&mut self) {
+        self.receiver.close();
+        let mut registry = registry().lock().unwrap();
+        // The name may have been taken over by a new listener already.
+        if registry.get(&self.name).is_some_and(|s| s.is_closed()) {
+            registry.remove(&self.name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use tower::service_fn;
+
+    use crate::context::MsgContext;
+    use crate::memory::MemoryAddr;
+    use crate::server::Listenable;
+    use crate::testing::serve_in_memory;
+    use crate::{ApplicationResult, ClientBuilder, Framing, Server, SocketOrUnix};
+
+    #[tokio::test]
+    async fn test_memory_client_server() {
+        for framing in [Framing::Framed, Framing::Header, Framing::Unframed] {
+            let name = format!("echo-{:?}", framing);
+            let service = service_fn(
+                |(cx, req): (MsgContext, ApplicationResult<String>)| async move {
+                    Ok::<_, crate::Error>(Some((cx, req)))
+                },
+            );
+            let server = Server::<_, MemoryAddr, String, String>::new(service).framing(framing);
+            serve_in_memory(server, &name).await;
+
+            let mut client = ClientBuilder::new(SocketOrUnix::Memory(name))
+                .framing(framing)
+                .build();
+            let resp: String = client.call("echo", "hello".to_string()).await.unwrap();
+            assert_eq!(resp, "hello");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_memory_names() {
+        let err = super::connect("nobody").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+
+        let addr = MemoryAddr::new("taken");
+        let listener = addr.bind().await.unwrap();
+        let err = addr.bind().await.err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+        assert!(super::connect("taken").is_ok());
+
+        // the name is released with the listener
+        drop(listener);
+        assert!(super::connect("taken").is_err());
+        assert!(addr.bind().await.is_ok());
+    }
+}
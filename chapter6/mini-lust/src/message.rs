@@ -1,14 +1,22 @@
 use std::convert::TryInto;
 
+use bytes::Bytes;
+
 use crate::context::MsgContext;
 use crate::protocol::{
-    TFieldIdentifier, TInputProtocol, TOutputProtocol, TStructIdentifier, TType,
+    TFieldIdentifier, TInputProtocol, TLengthProtocol, TOutputProtocol, TStructIdentifier, TType,
 };
 use crate::ApplicationError;
 
 pub trait Message: Sized {
     fn encode<T: TOutputProtocol>(&self, cx: &MsgContext, protocol: &mut T) -> crate::Result<()>;
     fn decode<T: TInputProtocol>(cx: &mut MsgContext, protocol: &mut T) -> crate::Result<Self>;
+    /// Number of bytes `encode` writes with the `TOutputProtocol` matching `protocol`.
+    ///
+    /// Framing codecs write this length ahead of the message, so hand-written
+    /// impls must return exactly what `encode` writes, using the same calls on
+    /// `protocol`. A mismatch makes the encode fail with `InvalidData`.
+    fn encoded_size<T: TLengthProtocol>(&self, cx: &MsgContext, protocol: &mut T) -> usize;
 }
 
 /// ApplicationError defined as:
@@ -24,7 +32,7 @@ impl Message for ApplicationError {
         })?;
         protocol.write_field_begin(&TFieldIdentifier {
             name: Some("message".to_string()),
-            field_type: TType::Struct,
+            field_type: TType::String,
             id: Some(1),
         })?;
         protocol.write_string(self.message.as_str())?;
@@ -42,6 +50,26 @@ impl Message for ApplicationError {
         Ok(())
     }
 
+    fn encoded_size<T: TLengthProtocol>(&self, _cx: &MsgContext, protocol: &mut T) -> usize {
+        protocol.struct_begin_len(&TStructIdentifier {
+            name: "ApplicationError".to_string(),
+        }) + protocol.field_begin_len(&TFieldIdentifier {
+            name: Some("message".to_string()),
+            field_type: TType::String,
+            id: Some(1),
+        }) + protocol.string_len(self.message.as_str())
+            + protocol.field_end_len()
+            + protocol.field_begin_len(&TFieldIdentifier {
+                name: Some("type".to_string()),
+                field_type: TType::I32,
+                id: Some(2),
+            })
+            + protocol.i32_len(self.kind.into())
+            + protocol.field_end_len()
+            + protocol.field_stop_len()
+            + protocol.struct_end_len()
+    }
+
     fn decode<T: TInputProtocol>(cx: &mut MsgContext, protocol: &mut T) -> crate::Result<Self> {
         protocol.read_struct_begin()?;
         let mut output = Self::default();
@@ -85,10 +113,16 @@ impl Message for () {
         protocol.read_struct_end()?;
         Ok(())
     }
+
+    fn encoded_size<T: TLengthProtocol>(&self, _cx: &MsgContext, protocol: &mut T) -> usize {
+        protocol.struct_begin_len(&TStructIdentifier {
+            name: "void".into(),
+        }) + protocol.struct_end_len()
+    }
 }
 
 macro_rules! impl_message {
-    ($e: ty, $r: ident, $w: ident) => {
+    ($e: ty, $r: ident, $w: ident, $l: ident) => {
         impl Message for $e {
             fn encode<T: TOutputProtocol>(
                 &self,
@@ -105,12 +139,16 @@ macro_rules! impl_message {
             ) -> crate::Result<Self> {
                 protocol.$r()
             }
+
+            fn encoded_size<T: TLengthProtocol>(&self, _cx: &MsgContext, protocol: &mut T) -> usize {
+                protocol.$l(self)
+            }
         }
     };
 }
 
 macro_rules! impl_message_deref {
-    ($e: ty, $r: ident, $w: ident) => {
+    ($e: ty, $r: ident, $w: ident, $l: ident) => {
         impl Message for $e {
             fn encode<T: TOutputProtocol>(
                 &self,
@@ -127,13 +165,19 @@ macro_rules! impl_message_deref {
             ) -> crate::Result<Self> {
                 protocol.$r()
             }
+
+            fn encoded_size<T: TLengthProtocol>(&self, _cx: &MsgContext, protocol: &mut T) -> usize {
+                protocol.$l(*self)
+            }
         }
     };
 }
 
-impl_message_deref!(bool, read_bool, write_bool);
-impl_message_deref!(i8, read_i8, write_i8);
-impl_message_deref!(i16, read_i16, write_i16);
-impl_message_deref!(i32, read_i32, write_i32);
-impl_message_deref!(i64, read_i64, write_i64);
-impl_message!(String, read_string, write_string);
+impl_message_deref!(bool, read_bool, write_bool, bool_len);
+impl_message_deref!(i8, read_i8, write_i8, i8_len);
+impl_message_deref!(i16, read_i16, write_i16, i16_len);
+impl_message_deref!(i32, read_i32, write_i32, i32_len);
+impl_message_deref!(i64, read_i64, write_i64, i64_len);
+impl_message_deref!([u8; 16], read_uuid, write_uuid, uuid_len);
+impl_message!(String, read_string, write_string, string_len);
+impl_message!(Bytes, read_binary, write_bytes, bytes_len);
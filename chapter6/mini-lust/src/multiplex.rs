@@ -0,0 +1,403 @@
+//! Apache style multiplexing: several services behind one server, the method
+//! name on the wire being `"ServiceName:method"`.
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use tower::{Service, ServiceExt};
+
+use crate::codec::MessageDecoder;
+use crate::context::MsgContext;
+use crate::message::Message;
+use crate::protocol::{TInputProtocol, TLengthProtocol, TMessageType, TOutputProtocol, TType};
+use crate::utils::BoxFuture;
+use crate::{
+    new_protocol_error, ApplicationError, ApplicationErrorKind, ApplicationResult,
+    ProtocolErrorKind,
+};
+
+/// Separates the service name from the method name.
+pub const MULTIPLEXED_SEPARATOR: &str = ":";
+
+type DecodeFn = fn(&mut MsgContext, &mut dyn TInputProtocol) -> crate::Result<Box<dyn Any + Send>>;
+
+fn decode_erased<Req>(
+    cx: &mut MsgContext,
+    mut protocol: &mut dyn TInputProtocol,
+) -> crate::Result<Box<dyn Any + Send>>
+where
+    Req: Message + Send + 'static,
+{
+    Ok(Box::new(Req::decode(cx, &mut protocol)?))
+}
+
+/// Request routed by a `MultiplexedServer`, holding the decoded request of
+/// the registered service.
+pub struct MultiplexedRequest {
+    service: String,
+    inner: Box<dyn Any + Send>,
+}
+
+impl MultiplexedRequest {
+    /// The service the request is sent to.
+    pub fn service(&self) -> &str {
+        &self.service
+    }
+}
+
+/// Mini-lust note: requests are decoded by the `MultiplexedServer` which knows
+/// the registered request types, so this is never used.
+impl Message for MultiplexedRequest {
+    fn encode<T: TOutputProtocol>(&self, _cx: &MsgContext, _protocol: &mut T) -> crate::Result<()> {
+        Err(new_protocol_error(
+            ProtocolErrorKind::NotImplemented,
+            "multiplexed request cannot be encoded",
+        ))
+    }
+
+    fn decode<T: TInputProtocol>(_cx: &mut MsgContext, _protocol: &mut T) -> crate::Result<Self> {
+        Err(new_protocol_error(
+            ProtocolErrorKind::NotImplemented,
+            "multiplexed request must be decoded by its MultiplexedServer",
+        ))
+    }
+
+    fn encoded_size<T: TLengthProtocol>(&self, _cx: &MsgContext, _protocol: &mut T) -> usize {
+        0
+    }
+}
+
+trait ErasedMessage: Send {
+    fn encode_erased(
+        &self,
+        cx: &MsgContext,
+        protocol: &mut dyn TOutputProtocol,
+    ) -> crate::Result<()>;
+    fn encoded_size_erased(&self, cx: &MsgContext, protocol: &mut dyn TLengthProtocol) -> usize;
+}
+
+impl<M> ErasedMessage for M
+where
+    M: Message + Send,
+{
+    fn encode_erased(
+        &self,
+        cx: &MsgContext,
+        mut protocol: &mut dyn TOutputProtocol,
+    ) -> crate::Result<()> {
+        self.encode(cx, &mut protocol)
+    }
+
+    fn encoded_size_erased(
+        &self,
+        cx: &MsgContext,
+        mut protocol: &mut dyn TLengthProtocol,
+    ) -> usize {
+        self.encoded_size(cx, &mut protocol)
+    }
+}
+
+/// Response of any service registered in a `MultiplexedServer`.
+pub struct MultiplexedResponse(Box<dyn ErasedMessage>);
+
+impl Message for MultiplexedResponse {
+    fn encode<T: TOutputProtocol>(&self, cx: &MsgContext, protocol: &mut T) -> crate::Result<()> {
+        self.0.encode_erased(cx, protocol)
+    }
+
+    fn decode<T: TInputProtocol>(_cx: &mut MsgContext, _protocol: &mut T) -> crate::Result<Self> {
+        Err(new_protocol_error(
+            ProtocolErrorKind::NotImplemented,
+            "multiplexed response cannot be decoded",
+        ))
+    }
+
+    fn encoded_size<T: TLengthProtocol>(&self, cx: &MsgContext, protocol: &mut T) -> usize {
+        self.0.encoded_size_erased(cx, protocol)
+    }
+}
+
+type MultiplexedResult = Option<(MsgContext, ApplicationResult<MultiplexedResponse>)>;
+
+trait ErasedService: Send {
+    fn call(
+        &self,
+        cx: MsgContext,
+        req: Box<dyn Any + Send>,
+    ) -> BoxFuture<MultiplexedResult, crate::Error>;
+}
+
+struct ServiceEntry<S, Req, Resp> {
+    inner: S,
+    _marker: PhantomData<fn(Req, Resp)>,
+}
+
+impl<S, Req, Resp> ErasedService for ServiceEntry<S, Req, Resp>
+where
+    S: Service<
+            (MsgContext, ApplicationResult<Req>),
+            Response = Option<(MsgContext, ApplicationResult<Resp>)>,
+            Error = crate::Error,
+        > + Clone
+        + Send
+        + 'static,
+    S::Future: Send + 'static,
+    Req: Send + 'static,
+    Resp: Message + Send + 'static,
+{
+    fn call(
+        &self,
+        cx: MsgContext,
+        req: Box<dyn Any + Send>,
+    ) -> BoxFuture<MultiplexedResult, crate::Error> {
+        let req = *req
+            .downcast::<Req>()
+            .expect("request is decoded with the type of its service");
+        // Each call waits for its own service to be ready, a busy service
+        // does not hold back the others.
+        let service = self.inner.clone();
+        Box::pin(async move {
+            let resp = service.oneshot((cx, Ok(req))).await?;
+            Ok(resp.map(|(cx, resp)| {
+                let resp = resp.map(|r| MultiplexedResponse(Box::new(r)));
+                (cx, resp)
+            }))
+        })
+    }
+}
+
+/// Routes `"ServiceName:method"` requests to the registered services, which
+/// then see the bare method name.
+///
+/// Serve it with `Server::multiplexed`.
+#[derive(Default)]
+pub struct MultiplexedServer {
+    decoders: HashMap<String, DecodeFn>,
+    services: HashMap<String, Box<dyn ErasedService>>,
+}
+
+impl MultiplexedServer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a service, usually a generated `*Server`, under the given name.
+    /// Each call is handled by a clone of it.
+    pub fn register<S, Req, Resp>(mut self, name: impl Into<String>, service: S) -> Self
+    where
+        S: Service<
+                (MsgContext, ApplicationResult<Req>),
+                Response = Option<(MsgContext, ApplicationResult<Resp>)>,
+                Error = crate::Error,
+            > + Clone
+            + Send
+            + 'static,
+        S::Future: Send + 'static,
+        Req: Message + Send + 'static,
+        Resp: Message + Send + 'static,
+    {
+        let name = name.into();
+        self.decoders.insert(name.clone(), decode_erased::<Req>);
+        self.services.insert(
+            name,
+            Box::new(ServiceEntry {
+                inner: service,
+                _marker: PhantomData,
+            }),
+        );
+        self
+    }
+
+    pub(crate) fn decoder(&self) -> Arc<dyn MessageDecoder<MultiplexedRequest>> {
+        Arc::new(MultiplexedDecoder {
+            decoders: self.decoders.clone(),
+        })
+    }
+}
+
+impl Service<(MsgContext, ApplicationResult<MultiplexedRequest>)> for MultiplexedServer {
+    type Response = MultiplexedResult;
+    type Error = crate::Error;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        // The service a request goes to is only known once it is called.
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(
+        &mut self,
+        (cx, req): (MsgContext, ApplicationResult<MultiplexedRequest>),
+    ) -> Self::Future {
+        let err = match req {
+            Ok(req) => match self.services.get(&req.service) {
+                Some(service) => return service.call(cx, req.inner),
+                None => ApplicationError::new(
+                    ApplicationErrorKind::UnknownMethod,
+                    format!("unknown service {}", req.service),
+                ),
+            },
+            Err(e) => e,
+        };
+        Box::pin(async move {
+            // oneway does not need response
+            if cx.identifier.message_type == TMessageType::OneWay {
+                return Ok(None);
+            }
+            Ok(Some((cx, Err(err))))
+        })
+    }
+}
+
+struct MultiplexedDecoder {
+    decoders: HashMap<String, DecodeFn>,
+}
+
+impl MessageDecoder<MultiplexedRequest> for MultiplexedDecoder {
+    fn decode(
+        &self,
+        cx: &mut MsgContext,
+        protocol: &mut dyn TInputProtocol,
+    ) -> crate::Result<ApplicationResult<MultiplexedRequest>> {
+        let name = std::mem::take(&mut cx.identifier.name);
+        let (service, method) = match name.split_once(MULTIPLEXED_SEPARATOR) {
+            Some((service, method)) => (service.to_string(), method.to_string()),
+            None => (String::new(), name),
+        };
+        cx.identifier.name = method;
+        match self.decoders.get(&service) {
+            Some(decode) => Ok(Ok(MultiplexedRequest {
+                inner: decode(cx, protocol)?,
+                service,
+            })),
+            None => {
+                // Consume the arguments and let the server reply with an exception.
+                protocol.skip(TType::Struct)?;
+                Ok(Err(ApplicationError::new(
+                    ApplicationErrorKind::UnknownMethod,
+                    format!("unknown service {:?}", service),
+                )))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::task::{Context, Poll};
+
+    use bytes::BytesMut;
+    use tokio_util::codec::{Decoder, Encoder};
+    use tower::{service_fn, Service, ServiceExt};
+
+    use crate::binary::TBinaryProtocolFactory;
+    use crate::codec::MessageCodec;
+    use crate::context::MsgContext;
+    use crate::multiplex::{MultiplexedRequest, MultiplexedResponse, MultiplexedServer};
+    use crate::protocol::{TMessageIdentifier, TMessageType};
+    use crate::utils::BoxFuture;
+    use crate::{ApplicationError, ApplicationErrorKind, ApplicationResult};
+
+    fn registry() -> MultiplexedServer {
+        MultiplexedServer::new()
+            .register(
+                "Double",
+                service_fn(
+                    |(cx, req): (MsgContext, ApplicationResult<i32>)| async move {
+                        assert_eq!(cx.identifier.name, "call");
+                        Ok::<_, crate::Error>(Some((cx, req.map(|r| r * 2))))
+                    },
+                ),
+            )
+            .register(
+                "Negate",
+                service_fn(
+                    |(cx, req): (MsgContext, ApplicationResult<bool>)| async move {
+                        Ok::<_, crate::Error>(Some((cx, req.map(|r| !r))))
+                    },
+                ),
+            )
+    }
+
+    async fn round_trip<Req, Resp>(
+        server: &mut MultiplexedServer,
+        method: &str,
+        req: Req,
+    ) -> ApplicationResult<Resp>
+    where
+        Req: crate::Message,
+        Resp: crate::Message,
+    {
+        let protocol = Arc::new(TBinaryProtocolFactory::default());
+        let mut client = MessageCodec::<Req, Resp>::new(protocol.clone());
+        let mut codec = MessageCodec::<MultiplexedResponse, MultiplexedRequest>::new(protocol)
+            .with_decoder(Some(server.decoder()));
+
+        let cx = MsgContext {
+            identifier: TMessageIdentifier::new(method, TMessageType::Call, 1),
+            ..MsgContext::default()
+        };
+        let mut buf = BytesMut::new();
+        client.encode((cx, Ok(req)), &mut buf).unwrap();
+        let req = codec.decode(&mut buf).unwrap().unwrap();
+        assert!(buf.is_empty());
+
+        let resp = server.ready().await.unwrap().call(req).await.unwrap();
+        codec.encode(resp.unwrap(), &mut buf).unwrap();
+        let (cx, resp) = client.decode(&mut buf).unwrap().unwrap();
+        assert!(buf.is_empty());
+        // the service name is not echoed back
+        assert!(!cx.identifier.name.contains(':'));
+        resp
+    }
+
+    #[tokio::test]
+    async fn test_route_by_service_name() {
+        let mut server = registry();
+        let resp: ApplicationResult<i32> = round_trip(&mut server, "Double:call", 21).await;
+        assert_eq!(resp.unwrap(), 42);
+        let resp: ApplicationResult<bool> = round_trip(&mut server, "Negate:call", true).await;
+        assert!(!resp.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_unknown_service() {
+        let mut server = registry();
+        // the arguments, always a struct, are skipped
+        let args = ApplicationError::new(ApplicationErrorKind::Unknown, "args");
+        let resp: ApplicationResult<bool> =
+            round_trip(&mut server, "Unknown:call", args.clone()).await;
+        assert_eq!(resp.unwrap_err().kind, ApplicationErrorKind::UnknownMethod);
+        let resp: ApplicationResult<bool> = round_trip(&mut server, "call", args).await;
+        assert_eq!(resp.unwrap_err().kind, ApplicationErrorKind::UnknownMethod);
+    }
+
+    /// Never ready to take a call.
+    #[derive(Clone)]
+    struct Busy;
+
+    impl Service<(MsgContext, ApplicationResult<i32>)> for Busy {
+        type Response = Option<(MsgContext, ApplicationResult<i32>)>;
+        type Error = crate::Error;
+        type Future = BoxFuture<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Pending
+        }
+
+        fn call(&mut self, _req: (MsgContext, ApplicationResult<i32>)) -> Self::Future {
+            unreachable!("called before being ready")
+        }
+    }
+
+    #[tokio::test]
+    async fn test_busy_service() {
+        let mut server = registry().register("Busy", Busy);
+        let resp: ApplicationResult<i32> = round_trip(&mut server, "Double:call", 21).await;
+        assert_eq!(resp.unwrap(), 42);
+    }
+}
@@ -0,0 +1,422 @@
+//! Pipelining of concurrent calls over one connection per target.
+//!
+//! Calls are written as they come, replies are routed back to their caller
+//! by sequence number whatever their order.
+
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use futures::{Sink, TryStream};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::Semaphore;
+use tokio_tower::multiplex::{Client as MultiplexClient, MultiplexTransport, TagStore};
+use tower::{Service, ServiceExt};
+
+use crate::client::{verify_reply, Transport, TransportClient};
+use crate::codec::MakeCodec;
+use crate::connection::SocketOrUnix;
+use crate::context::MsgContext;
+use crate::pool::Close;
+use crate::protocol::TMessageType;
+use crate::utils::BoxFuture;
+use crate::{
+    new_protocol_error, new_transport_error, ApplicationResult, ProtocolErrorKind,
+    TransportErrorKind,
+};
+
+/// Matches replies to calls by sequence number, numbered by their
+/// connection.
+#[derive(Debug, Default)]
+pub(crate) struct SequenceTagger;
+
+impl<Req, Resp> TagStore<(MsgContext, Req), (MsgContext, Resp)> for SequenceTagger {
+    type Tag = i32;
+
+    fn assign_tag(self: Pin<&mut Self>, (cx, _): &mut (MsgContext, Req)) -> i32 {
+        cx.identifier.sequence_number
+    }
+
+    fn finish_tag(self: Pin<&mut Self>, (cx, _): &(MsgContext, Resp)) -> i32 {
+        cx.identifier.sequence_number
+    }
+}
+
+impl<T, I> From<tokio_tower::Error<T, I>> for crate::Error
+where
+    T: Sink<I, Error = crate::Error> + TryStream<Error = crate::Error>,
+{
+    fn from(e: tokio_tower::Error<T, I>) -> Self {
+        match e {
+            tokio_tower::Error::BrokenTransportSend(e) => e,
+            tokio_tower::Error::BrokenTransportRecv(Some(e)) => e,
+            tokio_tower::Error::BrokenTransportRecv(None) => new_transport_error(
+                TransportErrorKind::EndOfFile,
+                "connection closed with calls in flight",
+            ),
+            tokio_tower::Error::TransportFull | tokio_tower::Error::ClientDropped => {
+                new_transport_error(
+                    TransportErrorKind::NotOpen,
+                    "connection closed before the reply",
+                )
+            }
+            tokio_tower::Error::Desynchronized => new_protocol_error(
+                ProtocolErrorKind::InvalidData,
+                "reply to a call that is not in flight",
+            ),
+        }
+    }
+}
+
+type Multiplexed<T, Req> =
+    MultiplexClient<MultiplexTransport<T, SequenceTagger>, crate::Error, Req>;
+
+/// A connection shared by the calls to one target.
+struct Connection<T, Req>
+where
+    T: Sink<Req> + TryStream,
+{
+    client: tokio::sync::Mutex<Multiplexed<T, Req>>,
+    in_flight: Arc<Semaphore>,
+    broken: Arc<AtomicBool>,
+    seq_id: AtomicI32,
+}
+
+impl<T, Req> Connection<T, Req>
+where
+    T: Sink<Req, Error = crate::Error> + TryStream<Error = crate::Error> + Send + 'static,
+    T::Ok: Send + 'static,
+    Req: Send + 'static,
+    SequenceTagger: TagStore<Req, T::Ok, Tag = i32>,
+{
+    fn new(transport: T, max_in_flight: usize) -> Self {
+        let broken = Arc::new(AtomicBool::new(false));
+        let client = MultiplexClient::with_error_handler(
+            MultiplexTransport::new(transport, SequenceTagger),
+            {
+                let broken = broken.clone();
+                move |e: crate::Error| {
+                    // Calls in flight fail as their reply will not come.
+                    broken.store(true, Ordering::Release);
+                    log::error!("pipelined connection failed: {}", e);
+                }
+            },
+        );
+        Self {
+            client: tokio::sync::Mutex::new(client),
+            in_flight: Arc::new(Semaphore::new(max_in_flight)),
+            broken,
+            seq_id: AtomicI32::new(0),
+        }
+    }
+
+    fn next_seq_id(&self) -> i32 {
+        self.seq_id.fetch_add(1, Ordering::Relaxed).wrapping_add(1)
+    }
+
+    fn is_broken(&self) -> bool {
+        self.broken.load(Ordering::Acquire)
+    }
+}
+
+/// The connections of a pipelined client, one per target.
+pub(crate) struct Connections<T, Req>
+where
+    T: Sink<Req> + TryStream,
+{
+    targets: Mutex<HashMap<SocketOrUnix, Arc<Connection<T, Req>>>>,
+    closed: AtomicBool,
+    // Oneway calls go through the pool, as no reply frees their sequence
+    // number.
+    pool: Arc<dyn Close>,
+}
+
+impl<T, Req> Close for Connections<T, Req>
+where
+    T: Sink<Req> + TryStream + Send,
+    T::Ok: Send,
+    Req: Send,
+{
+    /// Drop the connections, each is closed once its calls in flight are
+    /// done. Later calls fail.
+    fn close(&self) {
+        let mut targets = self.targets.lock().unwrap();
+        self.closed.store(true, Ordering::Release);
+        targets.clear();
+        self.pool.close();
+    }
+
+    fn close_target(&self, target: &SocketOrUnix) {
+        self.targets.lock().unwrap().remove(target);
+        self.pool.close_target(target);
+    }
+}
+
+/// Shares one connection per target between all calls in flight, up to
+/// `max_in_flight` of them.
+pub(crate) struct PipelinedClient<MCN, MCC>
+where
+    MCN: Service<SocketOrUnix>,
+    MCC: MakeCodec,
+    Transport<MCN, MCC>: Sink<MCC::EncodeItem> + TryStream,
+{
+    pooled: TransportClient<MCN, MCC>,
+    connections: Arc<Connections<Transport<MCN, MCC>, MCC::EncodeItem>>,
+    max_in_flight: usize,
+}
+
+impl<MCN, MCC> PipelinedClient<MCN, MCC>
+where
+    MCN: Service<SocketOrUnix>,
+    MCC: MakeCodec,
+    Transport<MCN, MCC>: Sink<MCC::EncodeItem> + TryStream,
+{
+    pub(crate) fn new(pooled: TransportClient<MCN, MCC>, max_in_flight: usize) -> Self
+    where
+        Transport<MCN, MCC>: Send + 'static,
+    {
+        let connections = Connections {
+            targets: Mutex::new(HashMap::new()),
+            closed: AtomicBool::new(false),
+            pool: pooled.pool(),
+        };
+        Self {
+            pooled,
+            connections: Arc::new(connections),
+            max_in_flight,
+        }
+    }
+
+    pub(crate) fn connections(&self) -> Arc<dyn Close>
+    where
+        Transport<MCN, MCC>: Send + 'static,
+        <Transport<MCN, MCC> as TryStream>::Ok: Send,
+        MCC::EncodeItem: Send + 'static,
+    {
+        self.connections.clone()
+    }
+}
+
+impl<MCN, MCC, Req, Resp> Service<(MsgContext, ApplicationResult<Req>)>
+    for PipelinedClient<MCN, MCC>
+where
+    MCN: Service<SocketOrUnix> + Clone + Send + 'static,
+    MCN::Response: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    MCN::Error: std::error::Error + Send + Sync + 'static,
+    MCN::Future: Send,
+    Req: Send + 'static,
+    Resp: Send + 'static,
+    MCC: MakeCodec<
+        EncodeItem = (MsgContext, ApplicationResult<Req>),
+        DecodeItem = (MsgContext, ApplicationResult<Resp>),
+        Error = crate::Error,
+    >,
+    MCC::Codec: Send + 'static,
+    crate::Error: From<<MCN as tower::Service<SocketOrUnix>>::Error>,
+{
+    type Response = Option<(MsgContext, ApplicationResult<Resp>)>;
+    type Error = crate::Error;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Service::<(MsgContext, ApplicationResult<Req>)>::poll_ready(&mut self.pooled, cx)
+    }
+
+    fn call(&mut self, (mut cx, req): (MsgContext, ApplicationResult<Req>)) -> Self::Future {
+        if cx.identifier.message_type == TMessageType::OneWay {
+            return self.pooled.call((cx, req));
+        }
+        let target = cx
+            .target
+            .clone()
+            .expect("unable to retrieve target from context");
+        let connections = self.connections.clone();
+        let max_in_flight = self.max_in_flight;
+        let transport_fut = self.pooled.connect(target.clone());
+
+        Box::pin(async move {
+            let current = || -> crate::Result<_> {
+                if connections.closed.load(Ordering::Acquire) {
+                    return Err(new_transport_error(
+                        TransportErrorKind::NotOpen,
+                        "the client is closed",
+                    ));
+                }
+                let targets = connections.targets.lock().unwrap();
+                Ok(targets.get(&target).filter(|c| !c.is_broken()).cloned())
+            };
+            let conn = match current()? {
+                Some(conn) => conn,
+                None => {
+                    let conn = Arc::new(Connection::new(transport_fut.await?, max_in_flight));
+                    // Another call may have connected meanwhile, the first
+                    // connection wins.
+                    let mut targets = connections.targets.lock().unwrap();
+                    match targets.get(&target).filter(|c| !c.is_broken()) {
+                        Some(current) => current.clone(),
+                        None => {
+                            targets.insert(target, conn.clone());
+                            conn
+                        }
+                    }
+                }
+            };
+
+            let _permit = conn.in_flight.acquire().await.map_err(|_| {
+                new_transport_error(TransportErrorKind::NotOpen, "the client is closed")
+            })?;
+            cx.identifier.sequence_number = conn.next_seq_id();
+            let call = cx.identifier.clone();
+            let resp = {
+                let mut client = conn.client.lock().await;
+                match client.ready().await {
+                    Ok(client) => client.call((cx, req)),
+                    Err(e) => {
+                        conn.broken.store(true, Ordering::Release);
+                        return Err(e);
+                    }
+                }
+            };
+            let resp = resp.await?;
+            verify_reply(&call, &resp.0.identifier)?;
+            Ok(Some(resp))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use futures::{SinkExt, StreamExt, TryStreamExt};
+    use tokio::io::DuplexStream;
+    use tokio_util::codec::Framed;
+
+    use crate::codec::{DefaultCodec, MakeCodec};
+    use crate::context::MsgContext;
+    use crate::memory::MemoryAddr;
+    use crate::protocol::TMessageType;
+    use crate::server::Listenable;
+    use crate::{ApplicationResult, Client, ClientBuilder, DefaultMakeCodec, SocketOrUnix};
+
+    type ServerConn = Framed<DuplexStream, DefaultCodec<String, String>>;
+
+    /// Accepts connections on `name`, returned along with their count.
+    async fn listen(name: &str) -> (tokio::sync::mpsc::Receiver<ServerConn>, Arc<AtomicUsize>) {
+        let mut listener = MemoryAddr::new(name).bind().await.unwrap();
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let (sender, receiver) = tokio::sync::mpsc::channel(4);
+        {
+            let accepted = accepted.clone();
+            tokio::spawn(async move {
+                while let Some(conn) = listener.next().await {
+                    accepted.fetch_add(1, Ordering::SeqCst);
+                    let codec = DefaultMakeCodec::<String, String>::new().make_codec();
+                    if sender
+                        .send(Framed::new(conn.unwrap(), codec))
+                        .await
+                        .is_err()
+                    {
+                        return;
+                    }
+                }
+            });
+        }
+        (receiver, accepted)
+    }
+
+    /// Reply to `req` in upper case, returns it.
+    async fn reply(
+        conn: &mut ServerConn,
+        (mut cx, req): (MsgContext, ApplicationResult<String>),
+    ) -> String {
+        let req = req.unwrap();
+        cx.identifier.message_type = TMessageType::Reply;
+        conn.send((cx, Ok(req.to_uppercase()))).await.unwrap();
+        req
+    }
+
+    fn client(name: &str, max_in_flight: usize) -> Client<String, String> {
+        ClientBuilder::new(SocketOrUnix::Memory(name.to_string()))
+            .pipelined(max_in_flight)
+            .build()
+    }
+
+    fn spawn_call(
+        client: &Client<String, String>,
+        req: &str,
+    ) -> tokio::task::JoinHandle<crate::Result<String>> {
+        let mut client = client.clone();
+        let req = req.to_string();
+        tokio::spawn(async move { client.call("shout", req).await })
+    }
+
+    #[tokio::test]
+    async fn test_out_of_order_replies() {
+        let (mut conns, accepted) = listen("pipelined").await;
+        let client = client("pipelined", 2);
+        let calls: Vec<_> = ["a", "b", "c"]
+            .iter()
+            .map(|req| spawn_call(&client, req))
+            .collect();
+
+        let mut conn = conns.recv().await.unwrap();
+        let mut first = Vec::new();
+        for _ in 0..2 {
+            first.push(conn.try_next().await.unwrap().unwrap());
+        }
+        // no more than max_in_flight calls are sent
+        let third = tokio::time::timeout(Duration::from_millis(20), conn.next()).await;
+        assert!(third.is_err());
+
+        // reply in reverse order
+        let mut seen = Vec::new();
+        for req in first.into_iter().rev() {
+            seen.push(reply(&mut conn, req).await);
+        }
+        let req = conn.try_next().await.unwrap().unwrap();
+        seen.push(reply(&mut conn, req).await);
+        seen.sort();
+        assert_eq!(seen, ["a", "b", "c"]);
+
+        for (call, req) in calls.into_iter().zip(["a", "b", "c"]) {
+            assert_eq!(call.await.unwrap().unwrap(), req.to_uppercase());
+        }
+        assert_eq!(accepted.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_pending_calls_fail_with_connection() {
+        let (mut conns, accepted) = listen("pipelined-dies").await;
+        let client = client("pipelined-dies", 8);
+        let calls: Vec<_> = ["a", "b"]
+            .iter()
+            .map(|req| spawn_call(&client, req))
+            .collect();
+
+        let mut conn = conns.recv().await.unwrap();
+        for _ in 0..2 {
+            let (_, req) = conn.try_next().await.unwrap().unwrap();
+            assert!(req.is_ok());
+        }
+        drop(conn);
+        for call in calls {
+            assert!(call.await.unwrap().is_err());
+        }
+
+        // the next call connects again
+        let call = spawn_call(&client, "c");
+        let mut conn = conns.recv().await.unwrap();
+        let req = conn.try_next().await.unwrap().unwrap();
+        reply(&mut conn, req).await;
+        assert_eq!(call.await.unwrap().unwrap(), "C");
+        assert_eq!(accepted.load(Ordering::SeqCst), 2);
+
+        client.close();
+        assert!(spawn_call(&client, "d").await.unwrap().is_err());
+    }
+}
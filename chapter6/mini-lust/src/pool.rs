@@ -0,0 +1,434 @@
+//! Pool of the connections a client keeps to its targets.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+use tokio::sync::{Notify, OwnedSemaphorePermit, Semaphore};
+
+use crate::connection::SocketOrUnix;
+use crate::{new_transport_error, TransportErrorKind};
+
+// The max number of permits of a tokio semaphore.
+const UNLIMITED: usize = usize::MAX >> 3;
+
+/// Limits of the connections a client keeps to each of its targets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolConfig {
+    /// Idle connections kept per target, those returned beyond are closed.
+    pub max_idle: usize,
+    /// Connections open at once per target, idle or not. Calls wait for a
+    /// connection once reached.
+    pub max_total: Option<usize>,
+    /// Idle connections are closed after this long unused, by a task of the
+    /// runtime the client is first used on.
+    pub idle_timeout: Duration,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            max_idle: 16,
+            max_total: None,
+            idle_timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+/// A connection checked out of the pool, counted in `max_total` until
+/// dropped.
+pub(crate) struct Pooled<T> {
+    conn: T,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl<T> Deref for Pooled<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.conn
+    }
+}
+
+impl<T> DerefMut for Pooled<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.conn
+    }
+}
+
+struct Idle<T> {
+    pooled: Pooled<T>,
+    since: Instant,
+}
+
+/// The connections to one target.
+pub(crate) struct TargetPool<T> {
+    config: PoolConfig,
+    idle: Mutex<Vec<Idle<T>>>,
+    permits: Arc<Semaphore>,
+    returned: Notify,
+    // Wakes the reaper up when a connection becomes idle.
+    idled: Arc<Notify>,
+    closed: AtomicBool,
+}
+
+impl<T> TargetPool<T> {
+    fn new(config: PoolConfig) -> Self {
+        Self {
+            config,
+            idle: Mutex::new(Vec::new()),
+            permits: Arc::new(Semaphore::new(config.max_total.unwrap_or(UNLIMITED))),
+            returned: Notify::new(),
+            idled: Arc::new(Notify::new()),
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    /// Close the expired idle connections, returns when the next one expires.
+    fn reap(&self) -> Option<Instant> {
+        let mut idle = self.idle.lock().unwrap();
+        let timeout = self.config.idle_timeout;
+        idle.retain(|conn| conn.since.elapsed() < timeout);
+        idle.iter().map(|conn| conn.since + timeout).min()
+    }
+
+    fn take_idle(&self) -> Option<Pooled<T>> {
+        let mut idle = self.idle.lock().unwrap();
+        // The most recently used connection is the least likely to have been
+        // closed by the peer.
+        while let Some(conn) = idle.pop() {
+            if conn.since.elapsed() < self.config.idle_timeout {
+                return Some(conn.pooled);
+            }
+        }
+        None
+    }
+
+    /// An idle connection, or a new one made with `connect` once `max_total`
+    /// allows it.
+    pub(crate) async fn checkout<F, E>(&self, connect: F) -> crate::Result<Pooled<T>>
+    where
+        F: Future<Output = Result<T, E>>,
+        crate::Error: From<E>,
+    {
+        let permit = loop {
+            if self.closed.load(Ordering::Acquire) {
+                return Err(closed());
+            }
+            if let Some(pooled) = self.take_idle() {
+                return Ok(pooled);
+            }
+            tokio::select! {
+                permit = self.permits.clone().acquire_owned() => {
+                    break permit.map_err(|_| closed())?;
+                }
+                _ = self.returned.notified() => {}
+            }
+        };
+        let conn = connect.await?;
+        Ok(Pooled {
+            conn,
+            _permit: permit,
+        })
+    }
+
+    /// Return a connection that is ready for another call.
+    pub(crate) fn checkin(&self, pooled: Pooled<T>) {
+        if self.closed.load(Ordering::Acquire) {
+            return;
+        }
+        self.reap();
+        let mut idle = self.idle.lock().unwrap();
+        if idle.len() < self.config.max_idle {
+            idle.push(Idle {
+                pooled,
+                since: Instant::now(),
+            });
+            drop(idle);
+            self.returned.notify_one();
+            self.idled.notify_one();
+        }
+    }
+
+    fn close(&self) {
+        self.closed.store(true, Ordering::Release);
+        self.permits.close();
+        self.idle.lock().unwrap().clear();
+        self.returned.notify_waiters();
+        self.idled.notify_one();
+    }
+}
+
+impl<T> Drop for TargetPool<T> {
+    fn drop(&mut self) {
+        // the reaper finds the pool gone and stops
+        self.idled.notify_one();
+    }
+}
+
+/// Close the idle connections of `pool` as they expire, until the pool is
+/// closed or dropped.
+async fn reap_idle<T>(pool: Weak<TargetPool<T>>, idled: Arc<Notify>) {
+    loop {
+        let next_expiry = match pool.upgrade() {
+            Some(pool) if !pool.closed.load(Ordering::Acquire) => pool.reap(),
+            _ => return,
+        };
+        match next_expiry {
+            Some(at) => tokio::time::sleep_until(at.into()).await,
+            None => idled.notified().await,
+        }
+    }
+}
+
+fn closed() -> crate::Error {
+    new_transport_error(TransportErrorKind::NotOpen, "the client is closed")
+}
+
+/// Close the connections of a pool, whatever their type.
+pub(crate) trait Close: Send + Sync {
+    fn close(&self);
+
+    /// Close the connections to `target` alone, as `close` does. Later calls
+    /// to `target` open new ones.
+    fn close_target(&self, target: &SocketOrUnix);
+}
+
+pub(crate) struct Pool<T> {
+    config: PoolConfig,
+    targets: Mutex<HashMap<SocketOrUnix, Arc<TargetPool<T>>>>,
+    closed: AtomicBool,
+}
+
+impl<T> Pool<T> {
+    pub(crate) fn new(config: PoolConfig) -> Self {
+        Self {
+            config,
+            targets: Mutex::new(HashMap::new()),
+            closed: AtomicBool::new(false),
+        }
+    }
+}
+
+impl<T: Send + 'static> Pool<T> {
+    pub(crate) fn target(&self, target: &SocketOrUnix) -> Arc<TargetPool<T>> {
+        let mut targets = self.targets.lock().unwrap();
+        targets
+            .entry(target.clone())
+            .or_insert_with(|| {
+                let pool = TargetPool::new(self.config);
+                if self.closed.load(Ordering::Acquire) {
+                    pool.close();
+                }
+                let pool = Arc::new(pool);
+                if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+                    runtime.spawn(reap_idle(Arc::downgrade(&pool), pool.idled.clone()));
+                }
+                pool
+            })
+            .clone()
+    }
+}
+
+impl<T: Send> Close for Pool<T> {
+    /// Close the idle connections, the others once their call is done.
+    /// Later calls fail.
+    fn close(&self) {
+        let targets = self.targets.lock().unwrap();
+        self.closed.store(true, Ordering::Release);
+        for pool in targets.values() {
+            pool.close();
+        }
+    }
+
+    fn close_target(&self, target: &SocketOrUnix) {
+        let pool = self.targets.lock().unwrap().remove(target);
+        if let Some(pool) = pool {
+            pool.close();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::pin::Pin;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use futures::stream::{Stream, StreamExt};
+    use tokio::io::DuplexStream;
+    use tower::service_fn;
+
+    use crate::connection::SocketOrUnix;
+    use crate::context::MsgContext;
+    use crate::memory::MemoryAddr;
+    use crate::pool::{Close, Pool, PoolConfig};
+    use crate::server::Listenable;
+    use crate::testing::serve_in_memory_on;
+    use crate::{ApplicationResult, ClientBuilder, Server};
+
+    fn target() -> SocketOrUnix {
+        SocketOrUnix::Memory("pool".to_string())
+    }
+
+    #[tokio::test]
+    async fn test_reuse_and_limits() {
+        let config = PoolConfig {
+            max_idle: 1,
+            max_total: Some(2),
+            idle_timeout: Duration::from_millis(50),
+        };
+        let pool = Pool::<usize>::new(config);
+        let pool = pool.target(&target());
+        let opened = Arc::new(AtomicUsize::new(0));
+        let connect = || {
+            let opened = opened.clone();
+            async move { Ok::<_, crate::Error>(opened.fetch_add(1, Ordering::SeqCst)) }
+        };
+
+        let first = pool.checkout(connect()).await.unwrap();
+        let second = pool.checkout(connect()).await.unwrap();
+        assert_eq!((*first, *second), (0, 1));
+
+        // max_total is reached, the third call waits for a connection
+        let mut third = {
+            let pool = pool.clone();
+            let connect = connect();
+            tokio::spawn(async move { *pool.checkout(connect).await.unwrap() })
+        };
+        let waited = tokio::time::timeout(Duration::from_millis(10), &mut third).await;
+        assert!(waited.is_err());
+        pool.checkin(first);
+        assert_eq!(third.await.unwrap(), 0);
+
+        // only max_idle connections are kept
+        let third = pool.checkout(connect()).await.unwrap();
+        assert_eq!(*third, 2);
+        pool.checkin(second);
+        pool.checkin(third);
+        let reused = pool.checkout(connect()).await.unwrap();
+        assert_eq!(*reused, 1);
+        let fresh = pool.checkout(connect()).await.unwrap();
+        assert_eq!(*fresh, 3);
+        drop(fresh);
+        pool.checkin(reused);
+
+        // idle connections expire
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        let fresh = pool.checkout(connect()).await.unwrap();
+        assert_eq!(*fresh, 4);
+    }
+
+    #[tokio::test]
+    async fn test_idle_reaped() {
+        let config = PoolConfig {
+            idle_timeout: Duration::from_millis(20),
+            ..PoolConfig::default()
+        };
+        let pool = Pool::<Arc<()>>::new(config);
+        let target_pool = pool.target(&target());
+        let conn = Arc::new(());
+        let pooled = target_pool
+            .checkout(async { Ok::<_, crate::Error>(conn.clone()) })
+            .await
+            .unwrap();
+        target_pool.checkin(pooled);
+        assert_eq!(Arc::strong_count(&conn), 2);
+
+        // closed without the pool being used again
+        tokio::time::sleep(Duration::from_millis(40)).await;
+        assert_eq!(Arc::strong_count(&conn), 1);
+    }
+
+    #[tokio::test]
+    async fn test_close() {
+        let pool = Pool::<usize>::new(PoolConfig::default());
+        let target_pool = pool.target(&target());
+        let conn = target_pool
+            .checkout(async { Ok::<_, crate::Error>(0) })
+            .await
+            .unwrap();
+        pool.close();
+        target_pool.checkin(conn);
+        assert!(target_pool
+            .checkout(async { Ok::<_, crate::Error>(1) })
+            .await
+            .is_err());
+        // targets first used after closing are closed too
+        let other = pool.target(&SocketOrUnix::Memory("other".to_string()));
+        assert!(other
+            .checkout(async { Ok::<_, crate::Error>(2) })
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_close_target() {
+        let pool = Pool::<usize>::new(PoolConfig::default());
+        let target_pool = pool.target(&target());
+        let other = pool.target(&SocketOrUnix::Memory("other".to_string()));
+        pool.close_target(&target());
+        assert!(target_pool
+            .checkout(async { Ok::<_, crate::Error>(0) })
+            .await
+            .is_err());
+        assert!(other
+            .checkout(async { Ok::<_, crate::Error>(1) })
+            .await
+            .is_ok());
+        let reopened = pool.target(&target());
+        assert!(reopened
+            .checkout(async { Ok::<_, crate::Error>(2) })
+            .await
+            .is_ok());
+    }
+
+    /// Counts the connections accepted on a memory address.
+    struct CountingAddr(MemoryAddr, Arc<AtomicUsize>);
+
+    #[async_trait::async_trait]
+    impl Listenable for CountingAddr {
+        type Conn = DuplexStream;
+        type Stream = Pin<Box<dyn Stream<Item = io::Result<DuplexStream>> + Send>>;
+
+        async fn bind(&self) -> io::Result<Self::Stream> {
+            let accepted = self.1.clone();
+            let stream = self.0.bind().await?.inspect(move |_| {
+                accepted.fetch_add(1, Ordering::SeqCst);
+            });
+            Ok(Box::pin(stream))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_client_reuses_connections() {
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let service = service_fn(
+            |(cx, req): (MsgContext, ApplicationResult<String>)| async move {
+                Ok::<_, crate::Error>(Some((cx, req)))
+            },
+        );
+        let addr = CountingAddr(MemoryAddr::new("pooled"), accepted.clone());
+        let server = Server::<_, CountingAddr, String, String>::new(service);
+        serve_in_memory_on(server, addr, "pooled").await;
+        // the probing connection of serve_in_memory_on
+        while accepted.load(Ordering::SeqCst) == 0 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+
+        let mut client = ClientBuilder::new(SocketOrUnix::Memory("pooled".to_string())).build();
+        for _ in 0..3 {
+            let resp: String = client.call("echo", "hello".to_string()).await.unwrap();
+            assert_eq!(resp, "hello");
+        }
+        assert_eq!(accepted.load(Ordering::SeqCst), 2);
+
+        client.close();
+        assert!(client.call("echo", "hello".to_string()).await.is_err());
+    }
+}
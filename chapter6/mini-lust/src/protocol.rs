@@ -21,7 +21,11 @@
 use std::convert::{From, TryFrom, TryInto};
 use std::fmt;
 use std::fmt::{Display, Formatter};
+use std::sync::Arc;
 
+use bytes::{Bytes, BytesMut};
+
+use crate::limits::DecodeLimits;
 use crate::{new_protocol_error, ProtocolError, ProtocolErrorKind};
 
 #[cfg(test)]
@@ -83,6 +87,14 @@ pub trait TInputProtocol {
     fn read_bool(&mut self) -> crate::Result<bool>;
     /// Read a fixed-length byte array.
     fn read_bytes(&mut self) -> crate::Result<Vec<u8>>;
+    /// Read a fixed-length byte array as `Bytes`.
+    ///
+    /// Protocols reading from a `BytesMut` split the payload off the buffer
+    /// instead of copying it. The default implementation copies through
+    /// `read_bytes`.
+    fn read_binary(&mut self) -> crate::Result<Bytes> {
+        self.read_bytes().map(Bytes::from)
+    }
     /// Read a word.
     fn read_i8(&mut self) -> crate::Result<i8>;
     /// Read a 16-bit signed integer.
@@ -95,6 +107,8 @@ pub trait TInputProtocol {
     fn read_double(&mut self) -> crate::Result<f64>;
     /// Read a fixed-length string (not null terminated).
     fn read_string(&mut self) -> crate::Result<String>;
+    /// Read a UUID as its 16 bytes in network order.
+    fn read_uuid(&mut self) -> crate::Result<[u8; 16]>;
     /// Read the beginning of a list.
     fn read_list_begin(&mut self) -> crate::Result<TListIdentifier>;
     /// Read the end of a list.
@@ -129,6 +143,7 @@ pub trait TInputProtocol {
             TType::I64 => self.read_i64().map(|_| ()),
             TType::Double => self.read_double().map(|_| ()),
             TType::String => self.read_string().map(|_| ()),
+            TType::Uuid => self.read_uuid().map(|_| ()),
             TType::Struct => {
                 self.read_struct_begin()?;
                 loop {
@@ -137,6 +152,7 @@ pub trait TInputProtocol {
                         break;
                     }
                     self.skip_till_depth(field_ident.field_type, depth - 1)?;
+                    self.read_field_end()?;
                 }
                 self.read_struct_end()
             }
@@ -182,6 +198,10 @@ pub trait TInputProtocol {
     ///
     /// This method should **never** be used in generated code.
     fn read_byte(&mut self) -> crate::Result<u8>;
+
+    /// Refuse length prefixes beyond `limits` from now on, before anything is
+    /// allocated for them. Protocols without length prefixes may ignore it.
+    fn set_decode_limits(&mut self, _limits: DecodeLimits) {}
 }
 
 /// Converts Thrift identifiers, primitives, containers or structs into a
@@ -230,6 +250,8 @@ pub trait TOutputProtocol {
     fn write_double(&mut self, d: f64) -> crate::Result<()>;
     /// Write a fixed-length string.
     fn write_string(&mut self, s: &str) -> crate::Result<()>;
+    /// Write a UUID given as its 16 bytes in network order.
+    fn write_uuid(&mut self, uuid: [u8; 16]) -> crate::Result<()>;
     /// Write the beginning of a list.
     fn write_list_begin(&mut self, identifier: &TListIdentifier) -> crate::Result<()>;
     /// Write the end of a list.
@@ -254,6 +276,96 @@ pub trait TOutputProtocol {
     fn write_byte(&mut self, b: u8) -> crate::Result<()>; // FIXME: REMOVE
 }
 
+/// Computes how many bytes a `TOutputProtocol` writes, without writing them.
+///
+/// Each method returns the length of the matching `write_*` call of
+/// `TOutputProtocol`. Calls must be made in the order the writes would be,
+/// since a protocol like compact encodes fields relative to the previous one.
+pub trait TLengthProtocol {
+    /// Length of the beginning of a Thrift message.
+    fn message_begin_len(&mut self, identifier: &TMessageIdentifier) -> usize;
+    /// Length of the end of a Thrift message.
+    fn message_end_len(&mut self) -> usize;
+    /// Length of the beginning of a Thrift struct.
+    fn struct_begin_len(&mut self, identifier: &TStructIdentifier) -> usize;
+    /// Length of the end of a Thrift struct.
+    fn struct_end_len(&mut self) -> usize;
+    /// Length of the beginning of a Thrift field.
+    fn field_begin_len(&mut self, identifier: &TFieldIdentifier) -> usize;
+    /// Length of the end of a Thrift field.
+    fn field_end_len(&mut self) -> usize;
+    /// Length of a STOP field.
+    fn field_stop_len(&mut self) -> usize;
+    /// Length of a bool.
+    fn bool_len(&mut self, b: bool) -> usize;
+    /// Length of a fixed-length byte array.
+    fn bytes_len(&mut self, b: &[u8]) -> usize;
+    /// Length of an 8-bit signed integer.
+    fn i8_len(&mut self, i: i8) -> usize;
+    /// Length of a 16-bit signed integer.
+    fn i16_len(&mut self, i: i16) -> usize;
+    /// Length of a 32-bit signed integer.
+    fn i32_len(&mut self, i: i32) -> usize;
+    /// Length of a 64-bit signed integer.
+    fn i64_len(&mut self, i: i64) -> usize;
+    /// Length of a 64-bit float.
+    fn double_len(&mut self, d: f64) -> usize;
+    /// Length of a fixed-length string.
+    fn string_len(&mut self, s: &str) -> usize;
+    /// Length of a UUID.
+    fn uuid_len(&mut self, uuid: [u8; 16]) -> usize;
+    /// Length of the beginning of a list.
+    fn list_begin_len(&mut self, identifier: &TListIdentifier) -> usize;
+    /// Length of the end of a list.
+    fn list_end_len(&mut self) -> usize;
+    /// Length of the beginning of a set.
+    fn set_begin_len(&mut self, identifier: &TSetIdentifier) -> usize;
+    /// Length of the end of a set.
+    fn set_end_len(&mut self) -> usize;
+    /// Length of the beginning of a map.
+    fn map_begin_len(&mut self, identifier: &TMapIdentifier) -> usize;
+    /// Length of the end of a map.
+    fn map_end_len(&mut self) -> usize;
+
+    // utility (DO NOT USE IN GENERATED CODE!!!!)
+    //
+
+    /// Length of an unsigned byte.
+    fn byte_len(&mut self, b: u8) -> usize;
+}
+
+/// Helper type used by clients and servers to create protocol instances over
+/// a codec buffer.
+///
+/// This is how a codec is told which encoding to speak without being generic
+/// over it: the codec hands its buffer to the factory and drives whatever
+/// `TInputProtocol` or `TOutputProtocol` comes back.
+pub trait TProtocolFactory: Send + Sync {
+    /// Create a `TInputProtocol` that reads from `buf`.
+    fn input_protocol<'a>(&self, buf: &'a mut BytesMut) -> Box<dyn TInputProtocol + 'a>;
+    /// Create a `TInputProtocol` that reads from a borrowed view, advancing
+    /// `buf` past what it reads.
+    fn slice_input_protocol<'a>(&self, buf: &'a mut &[u8]) -> Box<dyn TInputProtocol + 'a>;
+    /// Create a `TOutputProtocol` that writes to `buf`.
+    fn output_protocol<'a>(&self, buf: &'a mut BytesMut) -> Box<dyn TOutputProtocol + 'a>;
+    /// Create a `TLengthProtocol` computing the lengths written by the
+    /// `TOutputProtocol`.
+    fn length_protocol(&self) -> Box<dyn TLengthProtocol>;
+    /// Protocol id announced in THeader frames, `None` if THeader has no id for it.
+    fn header_protocol_id(&self) -> Option<i32> {
+        None
+    }
+    /// The same protocol with the given strictness, `None` keeping the
+    /// current one. Returns `None` if the protocol has no strict mode.
+    fn strictness(
+        &self,
+        _strict_read: Option<bool>,
+        _strict_write: Option<bool>,
+    ) -> Option<Arc<dyn TProtocolFactory>> {
+        None
+    }
+}
+
 impl<P> TInputProtocol for Box<P>
 where
     P: TInputProtocol + ?Sized,
@@ -290,6 +402,10 @@ where
         (**self).read_bytes()
     }
 
+    fn read_binary(&mut self) -> crate::Result<Bytes> {
+        (**self).read_binary()
+    }
+
     fn read_i8(&mut self) -> crate::Result<i8> {
         (**self).read_i8()
     }
@@ -314,6 +430,10 @@ where
         (**self).read_string()
     }
 
+    fn read_uuid(&mut self) -> crate::Result<[u8; 16]> {
+        (**self).read_uuid()
+    }
+
     fn read_list_begin(&mut self) -> crate::Result<TListIdentifier> {
         (**self).read_list_begin()
     }
@@ -341,6 +461,10 @@ where
     fn read_byte(&mut self) -> crate::Result<u8> {
         (**self).read_byte()
     }
+
+    fn set_decode_limits(&mut self, limits: DecodeLimits) {
+        (**self).set_decode_limits(limits)
+    }
 }
 
 impl<P> TOutputProtocol for Box<P>
@@ -407,6 +531,212 @@ where
         (**self).write_string(s)
     }
 
+    fn write_uuid(&mut self, uuid: [u8; 16]) -> crate::Result<()> {
+        (**self).write_uuid(uuid)
+    }
+
+    fn write_list_begin(&mut self, identifier: &TListIdentifier) -> crate::Result<()> {
+        (**self).write_list_begin(identifier)
+    }
+
+    fn write_list_end(&mut self) -> crate::Result<()> {
+        (**self).write_list_end()
+    }
+
+    fn write_set_begin(&mut self, identifier: &TSetIdentifier) -> crate::Result<()> {
+        (**self).write_set_begin(identifier)
+    }
+
+    fn write_set_end(&mut self) -> crate::Result<()> {
+        (**self).write_set_end()
+    }
+
+    fn write_map_begin(&mut self, identifier: &TMapIdentifier) -> crate::Result<()> {
+        (**self).write_map_begin(identifier)
+    }
+
+    fn write_map_end(&mut self) -> crate::Result<()> {
+        (**self).write_map_end()
+    }
+
+    fn flush(&mut self) -> crate::Result<()> {
+        (**self).flush()
+    }
+
+    fn write_byte(&mut self, b: u8) -> crate::Result<()> {
+        (**self).write_byte(b)
+    }
+}
+
+impl<P> TInputProtocol for &mut P
+where
+    P: TInputProtocol + ?Sized,
+{
+    fn read_message_begin(&mut self) -> crate::Result<TMessageIdentifier> {
+        (**self).read_message_begin()
+    }
+
+    fn read_message_end(&mut self) -> crate::Result<()> {
+        (**self).read_message_end()
+    }
+
+    fn read_struct_begin(&mut self) -> crate::Result<Option<TStructIdentifier>> {
+        (**self).read_struct_begin()
+    }
+
+    fn read_struct_end(&mut self) -> crate::Result<()> {
+        (**self).read_struct_end()
+    }
+
+    fn read_field_begin(&mut self) -> crate::Result<TFieldIdentifier> {
+        (**self).read_field_begin()
+    }
+
+    fn read_field_end(&mut self) -> crate::Result<()> {
+        (**self).read_field_end()
+    }
+
+    fn read_bool(&mut self) -> crate::Result<bool> {
+        (**self).read_bool()
+    }
+
+    fn read_bytes(&mut self) -> crate::Result<Vec<u8>> {
+        (**self).read_bytes()
+    }
+
+    fn read_binary(&mut self) -> crate::Result<Bytes> {
+        (**self).read_binary()
+    }
+
+    fn read_i8(&mut self) -> crate::Result<i8> {
+        (**self).read_i8()
+    }
+
+    fn read_i16(&mut self) -> crate::Result<i16> {
+        (**self).read_i16()
+    }
+
+    fn read_i32(&mut self) -> crate::Result<i32> {
+        (**self).read_i32()
+    }
+
+    fn read_i64(&mut self) -> crate::Result<i64> {
+        (**self).read_i64()
+    }
+
+    fn read_double(&mut self) -> crate::Result<f64> {
+        (**self).read_double()
+    }
+
+    fn read_string(&mut self) -> crate::Result<String> {
+        (**self).read_string()
+    }
+
+    fn read_uuid(&mut self) -> crate::Result<[u8; 16]> {
+        (**self).read_uuid()
+    }
+
+    fn read_list_begin(&mut self) -> crate::Result<TListIdentifier> {
+        (**self).read_list_begin()
+    }
+
+    fn read_list_end(&mut self) -> crate::Result<()> {
+        (**self).read_list_end()
+    }
+
+    fn read_set_begin(&mut self) -> crate::Result<TSetIdentifier> {
+        (**self).read_set_begin()
+    }
+
+    fn read_set_end(&mut self) -> crate::Result<()> {
+        (**self).read_set_end()
+    }
+
+    fn read_map_begin(&mut self) -> crate::Result<TMapIdentifier> {
+        (**self).read_map_begin()
+    }
+
+    fn read_map_end(&mut self) -> crate::Result<()> {
+        (**self).read_map_end()
+    }
+
+    fn read_byte(&mut self) -> crate::Result<u8> {
+        (**self).read_byte()
+    }
+
+    fn set_decode_limits(&mut self, limits: DecodeLimits) {
+        (**self).set_decode_limits(limits)
+    }
+}
+
+impl<P> TOutputProtocol for &mut P
+where
+    P: TOutputProtocol + ?Sized,
+{
+    fn write_message_begin(&mut self, identifier: &TMessageIdentifier) -> crate::Result<()> {
+        (**self).write_message_begin(identifier)
+    }
+
+    fn write_message_end(&mut self) -> crate::Result<()> {
+        (**self).write_message_end()
+    }
+
+    fn write_struct_begin(&mut self, identifier: &TStructIdentifier) -> crate::Result<()> {
+        (**self).write_struct_begin(identifier)
+    }
+
+    fn write_struct_end(&mut self) -> crate::Result<()> {
+        (**self).write_struct_end()
+    }
+
+    fn write_field_begin(&mut self, identifier: &TFieldIdentifier) -> crate::Result<()> {
+        (**self).write_field_begin(identifier)
+    }
+
+    fn write_field_end(&mut self) -> crate::Result<()> {
+        (**self).write_field_end()
+    }
+
+    fn write_field_stop(&mut self) -> crate::Result<()> {
+        (**self).write_field_stop()
+    }
+
+    fn write_bool(&mut self, b: bool) -> crate::Result<()> {
+        (**self).write_bool(b)
+    }
+
+    fn write_bytes(&mut self, b: &[u8]) -> crate::Result<()> {
+        (**self).write_bytes(b)
+    }
+
+    fn write_i8(&mut self, i: i8) -> crate::Result<()> {
+        (**self).write_i8(i)
+    }
+
+    fn write_i16(&mut self, i: i16) -> crate::Result<()> {
+        (**self).write_i16(i)
+    }
+
+    fn write_i32(&mut self, i: i32) -> crate::Result<()> {
+        (**self).write_i32(i)
+    }
+
+    fn write_i64(&mut self, i: i64) -> crate::Result<()> {
+        (**self).write_i64(i)
+    }
+
+    fn write_double(&mut self, d: f64) -> crate::Result<()> {
+        (**self).write_double(d)
+    }
+
+    fn write_string(&mut self, s: &str) -> crate::Result<()> {
+        (**self).write_string(s)
+    }
+
+    fn write_uuid(&mut self, uuid: [u8; 16]) -> crate::Result<()> {
+        (**self).write_uuid(uuid)
+    }
+
     fn write_list_begin(&mut self, identifier: &TListIdentifier) -> crate::Result<()> {
         (**self).write_list_begin(identifier)
     }
@@ -440,6 +770,200 @@ where
     }
 }
 
+impl<P> TLengthProtocol for Box<P>
+where
+    P: TLengthProtocol + ?Sized,
+{
+    fn message_begin_len(&mut self, identifier: &TMessageIdentifier) -> usize {
+        (**self).message_begin_len(identifier)
+    }
+
+    fn message_end_len(&mut self) -> usize {
+        (**self).message_end_len()
+    }
+
+    fn struct_begin_len(&mut self, identifier: &TStructIdentifier) -> usize {
+        (**self).struct_begin_len(identifier)
+    }
+
+    fn struct_end_len(&mut self) -> usize {
+        (**self).struct_end_len()
+    }
+
+    fn field_begin_len(&mut self, identifier: &TFieldIdentifier) -> usize {
+        (**self).field_begin_len(identifier)
+    }
+
+    fn field_end_len(&mut self) -> usize {
+        (**self).field_end_len()
+    }
+
+    fn field_stop_len(&mut self) -> usize {
+        (**self).field_stop_len()
+    }
+
+    fn bool_len(&mut self, b: bool) -> usize {
+        (**self).bool_len(b)
+    }
+
+    fn bytes_len(&mut self, b: &[u8]) -> usize {
+        (**self).bytes_len(b)
+    }
+
+    fn i8_len(&mut self, i: i8) -> usize {
+        (**self).i8_len(i)
+    }
+
+    fn i16_len(&mut self, i: i16) -> usize {
+        (**self).i16_len(i)
+    }
+
+    fn i32_len(&mut self, i: i32) -> usize {
+        (**self).i32_len(i)
+    }
+
+    fn i64_len(&mut self, i: i64) -> usize {
+        (**self).i64_len(i)
+    }
+
+    fn double_len(&mut self, d: f64) -> usize {
+        (**self).double_len(d)
+    }
+
+    fn string_len(&mut self, s: &str) -> usize {
+        (**self).string_len(s)
+    }
+
+    fn uuid_len(&mut self, uuid: [u8; 16]) -> usize {
+        (**self).uuid_len(uuid)
+    }
+
+    fn list_begin_len(&mut self, identifier: &TListIdentifier) -> usize {
+        (**self).list_begin_len(identifier)
+    }
+
+    fn list_end_len(&mut self) -> usize {
+        (**self).list_end_len()
+    }
+
+    fn set_begin_len(&mut self, identifier: &TSetIdentifier) -> usize {
+        (**self).set_begin_len(identifier)
+    }
+
+    fn set_end_len(&mut self) -> usize {
+        (**self).set_end_len()
+    }
+
+    fn map_begin_len(&mut self, identifier: &TMapIdentifier) -> usize {
+        (**self).map_begin_len(identifier)
+    }
+
+    fn map_end_len(&mut self) -> usize {
+        (**self).map_end_len()
+    }
+
+    fn byte_len(&mut self, b: u8) -> usize {
+        (**self).byte_len(b)
+    }
+}
+
+impl<P> TLengthProtocol for &mut P
+where
+    P: TLengthProtocol + ?Sized,
+{
+    fn message_begin_len(&mut self, identifier: &TMessageIdentifier) -> usize {
+        (**self).message_begin_len(identifier)
+    }
+
+    fn message_end_len(&mut self) -> usize {
+        (**self).message_end_len()
+    }
+
+    fn struct_begin_len(&mut self, identifier: &TStructIdentifier) -> usize {
+        (**self).struct_begin_len(identifier)
+    }
+
+    fn struct_end_len(&mut self) -> usize {
+        (**self).struct_end_len()
+    }
+
+    fn field_begin_len(&mut self, identifier: &TFieldIdentifier) -> usize {
+        (**self).field_begin_len(identifier)
+    }
+
+    fn field_end_len(&mut self) -> usize {
+        (**self).field_end_len()
+    }
+
+    fn field_stop_len(&mut self) -> usize {
+        (**self).field_stop_len()
+    }
+
+    fn bool_len(&mut self, b: bool) -> usize {
+        (**self).bool_len(b)
+    }
+
+    fn bytes_len(&mut self, b: &[u8]) -> usize {
+        (**self).bytes_len(b)
+    }
+
+    fn i8_len(&mut self, i: i8) -> usize {
+        (**self).i8_len(i)
+    }
+
+    fn i16_len(&mut self, i: i16) -> usize {
+        (**self).i16_len(i)
+    }
+
+    fn i32_len(&mut self, i: i32) -> usize {
+        (**self).i32_len(i)
+    }
+
+    fn i64_len(&mut self, i: i64) -> usize {
+        (**self).i64_len(i)
+    }
+
+    fn double_len(&mut self, d: f64) -> usize {
+        (**self).double_len(d)
+    }
+
+    fn string_len(&mut self, s: &str) -> usize {
+        (**self).string_len(s)
+    }
+
+    fn uuid_len(&mut self, uuid: [u8; 16]) -> usize {
+        (**self).uuid_len(uuid)
+    }
+
+    fn list_begin_len(&mut self, identifier: &TListIdentifier) -> usize {
+        (**self).list_begin_len(identifier)
+    }
+
+    fn list_end_len(&mut self) -> usize {
+        (**self).list_end_len()
+    }
+
+    fn set_begin_len(&mut self, identifier: &TSetIdentifier) -> usize {
+        (**self).set_begin_len(identifier)
+    }
+
+    fn set_end_len(&mut self) -> usize {
+        (**self).set_end_len()
+    }
+
+    fn map_begin_len(&mut self, identifier: &TMapIdentifier) -> usize {
+        (**self).map_begin_len(identifier)
+    }
+
+    fn map_end_len(&mut self) -> usize {
+        (**self).map_end_len()
+    }
+
+    fn byte_len(&mut self, b: u8) -> usize {
+        (**self).byte_len(b)
+    }
+}
+
 /// Thrift message identifier.
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct TMessageIdentifier {
@@ -688,6 +1212,8 @@ pub enum TType {
     Utf8 = 14,
     /// UTF-16 string. *Unsupported*.
     Utf16 = 15,
+    /// UUID, 16 bytes.
+    Uuid = 16,
 }
 
 #[inline]
@@ -721,6 +1247,7 @@ impl Display for TType {
             TType::List => write!(f, "list"),
             TType::Utf8 => write!(f, "UTF8"),
             TType::Utf16 => write!(f, "UTF16"),
+            TType::Uuid => write!(f, "uuid"),
         }
     }
 }
@@ -0,0 +1,205 @@
+//! Resolution of host name targets.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::utils::BoxFuture;
+
+// How long resolved addresses are used before resolving the name again.
+pub(crate) const DEFAULT_RE_RESOLVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Resolves a host name to the addresses of its A and AAAA records.
+pub trait Resolve: Send + Sync + 'static {
+    fn resolve(&self, host: &str, port: u16) -> BoxFuture<Vec<SocketAddr>, io::Error>;
+}
+
+/// Resolves with the resolver of the system, `getaddrinfo`.
+#[derive(Debug, Clone, Default)]
+pub struct DnsResolver;
+
+impl Resolve for DnsResolver {
+    fn resolve(&self, host: &str, port: u16) -> BoxFuture<Vec<SocketAddr>, io::Error> {
+        let host = host.to_string();
+        Box::pin(async move {
+            let addrs = tokio::net::lookup_host((host.as_str(), port)).await?;
+            Ok(addrs.collect())
+        })
+    }
+}
+
+struct Resolved {
+    addrs: Vec<SocketAddr>,
+    at: Instant,
+    next: usize,
+}
+
+/// Resolves host names again once their addresses are older than the
+/// re-resolve interval, and hands out the addresses in turn so that
+/// connections are spread across all of them.
+#[derive(Clone)]
+pub(crate) struct CachingResolver {
+    resolver: Arc<dyn Resolve>,
+    interval: Duration,
+    cache: Arc<Mutex<HashMap<(String, u16), Resolved>>>,
+}
+
+impl CachingResolver {
+    pub(crate) fn new(resolver: Arc<dyn Resolve>, interval: Duration) -> Self {
+        Self {
+            resolver,
+            interval,
+            cache: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// The next address to connect to for `host:port`.
+    pub(crate) async fn next_addr(&self, host: &str, port: u16) -> io::Result<SocketAddr> {
+        let key = (host.to_string(), port);
+        {
+            let mut cache = self.cache.lock().unwrap();
+            if let Some(resolved) = cache.get_mut(&key) {
+                if resolved.at.elapsed() < self.interval {
+                    return Ok(resolved.next_addr());
+                }
+            }
+        }
+
+        let resolved = self.resolver.resolve(host, port).await;
+        let mut cache = self.cache.lock().unwrap();
+        let e = match resolved {
+            Ok(addrs) if !addrs.is_empty() => {
+                let resolved = cache.entry(key).or_insert_with(|| Resolved {
+                    addrs: Vec::new(),
+                    at: Instant::now(),
+                    next: 0,
+                });
+                resolved.addrs = addrs;
+                resolved.at = Instant::now();
+                return Ok(resolved.next_addr());
+            }
+            Ok(_) => io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no address found for {}", host),
+            ),
+            Err(e) => e,
+        };
+        // Better stale addresses than none when the resolver fails, they are
+        // kept for another interval instead of resolving on every connection.
+        match cache.get_mut(&key) {
+            Some(resolved) => {
+                log::warn!("keep addresses of {}, resolve error: {}", host, e);
+                resolved.at = Instant::now();
+                Ok(resolved.next_addr())
+            }
+            None => Err(e),
+        }
+    }
+}
+
+impl Resolved {
+    fn next_addr(&mut self) -> SocketAddr {
+        let addr = self.addrs[self.next % self.addrs.len()];
+        self.next = self.next.wrapping_add(1);
+        addr
+    }
+}
+
+impl Default for CachingResolver {
+    fn default() -> Self {
+        Self::new(Arc::new(DnsResolver), DEFAULT_RE_RESOLVE_INTERVAL)
+    }
+}
+
+impl fmt::Debug for CachingResolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CachingResolver")
+            .field("interval", &self.interval)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::net::SocketAddr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use crate::resolve::{CachingResolver, Resolve};
+    use crate::utils::BoxFuture;
+
+    #[derive(Default)]
+    struct StubResolver {
+        records: Mutex<Vec<SocketAddr>>,
+        calls: AtomicUsize,
+    }
+
+    impl Resolve for Arc<StubResolver> {
+        fn resolve(&self, host: &str, _port: u16) -> BoxFuture<Vec<SocketAddr>, io::Error> {
+            assert_eq!(host, "thrift.example");
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            let records = self.records.lock().unwrap().clone();
+            Box::pin(async move { Ok(records) })
+        }
+    }
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_round_robin_and_re_resolve() {
+        let stub = Arc::new(StubResolver::default());
+        *stub.records.lock().unwrap() = vec![addr("10.0.0.1:9090"), addr("[fd00::1]:9090")];
+        let resolver = CachingResolver::new(Arc::new(stub.clone()), Duration::from_millis(50));
+
+        let mut picked = Vec::new();
+        for _ in 0..4 {
+            picked.push(resolver.next_addr("thrift.example", 9090).await.unwrap());
+        }
+        assert_eq!(
+            picked,
+            [
+                addr("10.0.0.1:9090"),
+                addr("[fd00::1]:9090"),
+                addr("10.0.0.1:9090"),
+                addr("[fd00::1]:9090"),
+            ]
+        );
+        assert_eq!(stub.calls.load(Ordering::SeqCst), 1);
+
+        // records change, they are picked up after the interval
+        *stub.records.lock().unwrap() = vec![addr("10.0.0.2:9090")];
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        let picked = resolver.next_addr("thrift.example", 9090).await.unwrap();
+        assert_eq!(picked, addr("10.0.0.2:9090"));
+        assert_eq!(stub.calls.load(Ordering::SeqCst), 2);
+
+        // a failed resolution keeps the previous records
+        stub.records.lock().unwrap().clear();
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        let picked = resolver.next_addr("thrift.example", 9090).await.unwrap();
+        assert_eq!(picked, addr("10.0.0.2:9090"));
+        assert_eq!(stub.calls.load(Ordering::SeqCst), 3);
+        // and it is not retried before the interval
+        let picked = resolver.next_addr("thrift.example", 9090).await.unwrap();
+        assert_eq!(picked, addr("10.0.0.2:9090"));
+        assert_eq!(stub.calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_no_record() {
+        let stub = Arc::new(StubResolver::default());
+        let resolver = CachingResolver::new(Arc::new(stub), Duration::from_secs(1));
+        let err = resolver
+            .next_addr("thrift.example", 9090)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+}
@@ -0,0 +1,409 @@
+//! Retries of failed calls.
+//!
+//! A call that failed before its request was written, while connecting, is
+//! retried whatever its method. Once the request may have reached the server,
+//! only calls to methods marked idempotent are.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::ready;
+use tokio::time::Sleep;
+use tower::retry::budget::Budget;
+use tower::retry::Policy;
+use tower::BoxError;
+
+use crate::context::MsgContext;
+use crate::multiplex::MULTIPLEXED_SEPARATOR;
+use crate::utils::random;
+use crate::ApplicationResult;
+
+/// Which failed calls are tried again, and when.
+#[derive(Clone)]
+pub struct RetryPolicy {
+    max_attempts: usize,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    budget: Arc<Budget>,
+    idempotent: Arc<HashSet<String>>,
+    transient: fn(&crate::Error) -> bool,
+}
+
+impl RetryPolicy {
+    /// Up to 3 attempts, backing off from 20 milliseconds up to 1 second,
+    /// with the default budget of tower: 20% of the calls of the last 10
+    /// seconds plus 10 retries a second.
+    pub fn new() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(20),
+            max_backoff: Duration::from_secs(1),
+            budget: Arc::new(Budget::default()),
+            idempotent: Arc::new(HashSet::new()),
+            transient: is_transient,
+        }
+    }
+
+    /// Try a call at most `max_attempts` times, the first one included.
+    pub fn max_attempts(mut self, max_attempts: usize) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    /// Wait `initial` before the first retry, twice as long before each next
+    /// one up to `max`. The delays are cut by up to half at random so that
+    /// clients failing together do not retry together.
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    /// Retry no more than `retry_percent` of the calls of the last `ttl`,
+    /// plus `min_per_sec` retries a second, see `tower::retry::budget::Budget`
+    /// for the bounds of the arguments. The budget is shared by the clones of
+    /// the client.
+    pub fn budget(mut self, ttl: Duration, min_per_sec: u32, retry_percent: f32) -> Self {
+        self.budget = Arc::new(Budget::new(ttl, min_per_sec, retry_percent));
+        self
+    }
+
+    /// Methods to retry even when the request may have reached the server,
+    /// calling them twice doing no harm.
+    pub fn idempotent<I, S>(mut self, methods: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.idempotent = Arc::new(methods.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Which errors are worth trying again, `is_transient` by default.
+    pub fn classify(mut self, transient: fn(&crate::Error) -> bool) -> Self {
+        self.transient = transient;
+        self
+    }
+
+    fn is_idempotent(&self, name: &str) -> bool {
+        // The name is prefixed with the service name by multiplexed clients.
+        let method = match name.split_once(MULTIPLEXED_SEPARATOR) {
+            Some((_, method)) => method,
+            None => name,
+        };
+        self.idempotent.contains(name) || self.idempotent.contains(method)
+    }
+
+    /// The delay before the retry following attempt number `attempt`.
+    fn backoff_delay(&self, attempt: usize) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(31) as u32;
+        let delay = self
+            .initial_backoff
+            .checked_mul(1 << exponent)
+            .unwrap_or(self.max_backoff)
+            .min(self.max_backoff);
+        let jitter = delay.as_nanos() as u64 / 2;
+        let jitter = match jitter {
+            0 => 0,
+            jitter => random() % jitter,
+        };
+        delay - Duration::from_nanos(jitter)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for RetryPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RetryPolicy")
+            .field("max_attempts", &self.max_attempts)
+            .field("initial_backoff", &self.initial_backoff)
+            .field("max_backoff", &self.max_backoff)
+            .field("idempotent", &self.idempotent)
+            .finish()
+    }
+}
+
+/// Transport errors, failures of the connection rather than of the call,
+/// are worth trying again.
+pub fn is_transient(e: &crate::Error) -> bool {
+    matches!(e, crate::Error::Transport(_))
+}
+
+/// Error of a call that failed before its request was written.
+pub(crate) struct Unsent(crate::Error);
+
+impl fmt::Debug for Unsent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.0, f)
+    }
+}
+
+impl fmt::Display for Unsent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl std::error::Error for Unsent {}
+
+/// Mark `e` as the error of a call whose request was not written.
+pub(crate) fn unsent(e: crate::Error) -> crate::Error {
+    crate::Error::User(Box::new(Unsent(e)))
+}
+
+/// The error marked by `unsent`, or `e` when not marked.
+pub(crate) fn unmarked(e: &crate::Error) -> &crate::Error {
+    match e {
+        crate::Error::User(user) => match user.downcast_ref::<Unsent>() {
+            Some(Unsent(e)) => e,
+            None => e,
+        },
+        e => e,
+    }
+}
+
+/// Remove the mark of `unsent`, if any.
+pub(crate) fn strip_unsent(e: crate::Error) -> crate::Error {
+    match e {
+        crate::Error::User(e) => match e.downcast::<Unsent>() {
+            Ok(unsent) => unsent.0,
+            Err(e) => crate::Error::User(e),
+        },
+        e => e,
+    }
+}
+
+/// The policy of a call, counting its attempts.
+pub(crate) struct Attempt<Req> {
+    policy: RetryPolicy,
+    clone_request: fn(&Req) -> Req,
+    attempt: usize,
+}
+
+impl<Req> Attempt<Req> {
+    pub(crate) fn new(policy: RetryPolicy, clone_request: fn(&Req) -> Req) -> Self {
+        Self {
+            policy,
+            clone_request,
+            attempt: 1,
+        }
+    }
+}
+
+impl<Req> Clone for Attempt<Req> {
+    fn clone(&self) -> Self {
+        Self {
+            policy: self.policy.clone(),
+            clone_request: self.clone_request,
+            attempt: self.attempt,
+        }
+    }
+}
+
+/// Waits out the backoff delay before the next attempt.
+pub(crate) struct Backoff<Req> {
+    sleep: Pin<Box<Sleep>>,
+    next: Option<Attempt<Req>>,
+}
+
+impl<Req> Future for Backoff<Req> {
+    type Output = Attempt<Req>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        ready!(self.sleep.as_mut().poll(cx));
+        Poll::Ready(self.next.take().expect("backoff polled after completion"))
+    }
+}
+
+impl<Req, Res> Policy<(MsgContext, ApplicationResult<Req>), Res, BoxError> for Attempt<Req> {
+    type Future = Backoff<Req>;
+
+    fn retry(
+        &self,
+        (cx, _): &(MsgContext, ApplicationResult<Req>),
+        result: Result<&Res, &BoxError>,
+    ) -> Option<Self::Future> {
+        if self.attempt == 1 {
+            self.policy.budget.deposit();
+        }
+        let (e, unsent) = match result.err()?.downcast_ref::<crate::Error>()? {
+            crate::Error::User(e) => match e.downcast_ref::<Unsent>() {
+                Some(Unsent(e)) => (e, true),
+                None => return None,
+            },
+            e => (e, false),
+        };
+        if self.attempt >= self.policy.max_attempts
+            || !(self.policy.transient)(e)
+            || !(unsent || self.policy.is_idempotent(&cx.identifier.name))
+        {
+            return None;
+        }
+        self.policy.budget.withdraw().ok()?;
+
+        log::debug!(
+            "retry {} after attempt {}: {}",
+            cx.identifier.name,
+            self.attempt,
+            e
+        );
+        let delay = self.policy.backoff_delay(self.attempt);
+        Some(Backoff {
+            sleep: Box::pin(tokio::time::sleep(delay)),
+            next: Some(Self {
+                attempt: self.attempt + 1,
+                ..self.clone()
+            }),
+        })
+    }
+
+    fn clone_request(
+        &self,
+        (cx, req): &(MsgContext, ApplicationResult<Req>),
+    ) -> Option<(MsgContext, ApplicationResult<Req>)> {
+        let req = req.as_ref().ok()?;
+        Some((cx.clone(), Ok((self.clone_request)(req))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::net::SocketAddr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use futures::{StreamExt, TryStreamExt};
+    use tokio_util::codec::Framed;
+    use tower::service_fn;
+
+    use crate::codec::MakeCodec;
+    use crate::context::MsgContext;
+    use crate::memory::MemoryAddr;
+    use crate::retry::RetryPolicy;
+    use crate::server::Listenable;
+    use crate::testing::serve_on_loopback;
+    use crate::utils::BoxFuture;
+    use crate::{
+        ApplicationResult, Client, ClientBuilder, DefaultMakeCodec, Resolve, Server, SocketOrUnix,
+    };
+
+    #[test]
+    fn test_backoff_delay() {
+        let policy =
+            RetryPolicy::new().backoff(Duration::from_millis(10), Duration::from_millis(50));
+        let ms = Duration::from_millis;
+        for _ in 0..100 {
+            let delay = policy.backoff_delay(1);
+            assert!(delay >= ms(5) && delay <= ms(10));
+            let delay = policy.backoff_delay(3);
+            assert!(delay >= ms(20) && delay <= ms(40));
+            let delay = policy.backoff_delay(100);
+            assert!(delay >= ms(25) && delay <= ms(50));
+        }
+    }
+
+    /// Fails to resolve until `failures` is down to zero.
+    struct FlakyResolver {
+        addr: SocketAddr,
+        failures: AtomicUsize,
+        calls: AtomicUsize,
+    }
+
+    impl Resolve for Arc<FlakyResolver> {
+        fn resolve(&self, _host: &str, _port: u16) -> BoxFuture<Vec<SocketAddr>, io::Error> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            let failed = self
+                .failures
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+                .is_ok();
+            let addr = self.addr;
+            Box::pin(async move {
+                if failed {
+                    return Err(io::ErrorKind::ConnectionRefused.into());
+                }
+                Ok(vec![addr])
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retry_unsent_calls() {
+        let service = service_fn(
+            |(cx, req): (MsgContext, ApplicationResult<String>)| async move {
+                Ok::<_, crate::Error>(Some((cx, req)))
+            },
+        );
+        let addr = serve_on_loopback(Server::new(service));
+
+        let policy = RetryPolicy::new().backoff(Duration::from_millis(1), Duration::from_millis(1));
+        for (max_attempts, succeeds) in [(2, false), (3, true)] {
+            let resolver = Arc::new(FlakyResolver {
+                addr,
+                failures: AtomicUsize::new(2),
+                calls: AtomicUsize::new(0),
+            });
+            let mut client: Client<String, String> =
+                ClientBuilder::new(SocketOrUnix::Host("flaky.example".to_string(), addr.port()))
+                    .resolver(resolver.clone())
+                    .build()
+                    .with_retry(policy.clone().max_attempts(max_attempts));
+            // not idempotent, but the request never left
+            let resp = client.call("put", "hello".to_string()).await;
+            assert_eq!(resp.is_ok(), succeeds);
+            assert_eq!(resolver.calls.load(Ordering::SeqCst), max_attempts);
+            if let Err(e) = resp {
+                assert!(matches!(e, crate::Error::Transport(_)));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retry_idempotent_calls() {
+        // the server hangs up on every call
+        let mut listener = MemoryAddr::new("hang-up").bind().await.unwrap();
+        let received = Arc::new(AtomicUsize::new(0));
+        {
+            let received = received.clone();
+            tokio::spawn(async move {
+                while let Some(conn) = listener.next().await {
+                    let codec = DefaultMakeCodec::<String, String>::new().make_codec();
+                    let mut conn = Framed::new(conn.unwrap(), codec);
+                    if let Ok(Some(_)) = conn.try_next().await {
+                        received.fetch_add(1, Ordering::SeqCst);
+                    }
+                }
+            });
+        }
+        let client = |policy: RetryPolicy| -> Client<String, String> {
+            ClientBuilder::new(SocketOrUnix::Memory("hang-up".to_string()))
+                .build()
+                .with_retry(policy)
+        };
+        let policy = RetryPolicy::new()
+            .backoff(Duration::from_millis(1), Duration::from_millis(1))
+            .idempotent(["get"]);
+
+        let mut retried = client(policy.clone());
+        assert!(retried.call("put", "a".to_string()).await.is_err());
+        assert_eq!(received.load(Ordering::SeqCst), 1);
+        assert!(retried.call("get", "a".to_string()).await.is_err());
+        assert_eq!(received.load(Ordering::SeqCst), 4);
+
+        // no retry left in the budget
+        let mut broke = client(policy.budget(Duration::from_secs(1), 0, 0.0));
+        assert!(broke.call("get", "a".to_string()).await.is_err());
+        assert_eq!(received.load(Ordering::SeqCst), 5);
+    }
+}
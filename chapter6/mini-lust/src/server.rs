@@ -2,6 +2,7 @@ use std::io;
 use std::marker::PhantomData;
 use std::net::SocketAddr;
 use std::pin::Pin;
+use std::sync::Arc;
 use std::task::{Context, Poll};
 
 use futures::SinkExt;
@@ -16,10 +17,16 @@ use tokio_stream::StreamExt;
 use tokio_util::codec::Framed;
 use tower::{Service, ServiceBuilder, ServiceExt};
 
-use crate::codec::{DefaultMakeCodec, MakeCodec};
+use crate::binary::TBinaryProtocolFactory;
+use crate::codec::{
+    framed, CodecOptions, DefaultCodec, DefaultMakeCodec, Framing, MakeCodec, MessageDecoder,
+};
 use crate::context::MsgContext;
+use crate::limits::DecodeLimits;
 use crate::message::Message;
-use crate::protocol::TMessageType;
+use crate::multiplex::{MultiplexedRequest, MultiplexedResponse, MultiplexedServer};
+use crate::protocol::{TMessageType, TProtocolFactory};
+use crate::tls::{PeerIdentity, ServerTlsConfig, TlsListener};
 use crate::{ApplicationError, ApplicationErrorKind, ApplicationResult};
 
 #[async_trait::async_trait]
@@ -28,6 +35,11 @@ pub trait Listenable {
     type Stream: Stream<Item = io::Result<Self::Conn>> + Unpin;
 
     async fn bind(&self) -> io::Result<Self::Stream>;
+
+    /// Verified identity of the peer of an accepted connection.
+    fn peer_identity(_conn: &Self::Conn) -> Option<Arc<PeerIdentity>> {
+        None
+    }
 }
 
 #[async_trait::async_trait]
@@ -84,7 +96,7 @@ where
         let codec = this.make_codec;
         match ready!(listener_stream.as_mut().try_poll_next(cx)) {
             Some(Ok(conn)) => {
-                let f = Framed::new(conn, codec.make_codec());
+                let f = framed(conn, codec.make_codec(), codec.buffer_capacity());
                 Poll::Ready(Some(Ok(f)))
             }
             Some(Err(e)) => Poll::Ready(Some(Err(e))),
@@ -105,6 +117,14 @@ const DEFAULT_CONCURRENCY_LIMIT: usize = 1e3 as usize; // FIXME
 pub struct Server<S, Addr, Req, Resp> {
     concurrency_limit: Option<usize>,
     buffer: Option<usize>,
+    protocol: Arc<dyn TProtocolFactory>,
+    framing: Framing,
+    auto_detect: bool,
+    decode_limits: DecodeLimits,
+    codec_options: CodecOptions,
+    tls: Option<ServerTlsConfig>,
+    http_path: String,
+    decoder: Option<Arc<dyn MessageDecoder<Req>>>,
     inner: S,
     _marker: PhantomData<fn(Addr, Req, Resp)>,
 }
@@ -123,15 +143,88 @@ where
         Self {
             concurrency_limit: None,
             buffer: None,
+            protocol: Arc::new(TBinaryProtocolFactory::default()),
+            framing: Framing::default(),
+            auto_detect: false,
+            decode_limits: DecodeLimits::default(),
+            codec_options: CodecOptions::default(),
+            tls: None,
+            http_path: "/".to_string(),
+            decoder: None,
             inner,
             _marker: PhantomData,
         }
     }
+
+    /// Speak the given protocol instead of the default strict binary protocol.
+    pub fn protocol<P: TProtocolFactory + 'static>(mut self, protocol: P) -> Self {
+        self.protocol = Arc::new(protocol);
+        self
+    }
+
+    /// Delimit messages with the given framing instead of the default framed transport.
+    pub fn framing(mut self, framing: Framing) -> Self {
+        self.framing = framing;
+        self
+    }
+
+    /// Detect the framing and the protocol of each connection from its first
+    /// bytes, so that framed, unframed and THeader clients speaking binary,
+    /// strict or not, or compact are all served. The configured protocol and framing are then
+    /// ignored, as are the strictness and compression of `codec_options`.
+    pub fn auto_detect(mut self, auto_detect: bool) -> Self {
+        self.auto_detect = auto_detect;
+        self
+    }
+
+    /// Bound what clients may make the server decode.
+    pub fn decode_limits(mut self, limits: DecodeLimits) -> Self {
+        self.decode_limits = limits;
+        self
+    }
+
+    /// Frame size, binary strictness, buffer sizing and compression of the
+    /// connections.
+    ///
+    /// With compression enabled, plain and compressed frames are both served
+    /// and replies are only compressed for clients sending compressed frames.
+    ///
+    /// With `auto_detect`, only the frame size and the buffer sizes are used:
+    /// binary strictness follows each client and compression is ignored.
+    pub fn codec_options(mut self, options: CodecOptions) -> Self {
+        self.codec_options = options;
+        self
+    }
+
+    /// Serve calls posted to `path` over HTTP/1.1, as sent by `THttpClient`.
+    pub fn http(mut self, path: impl Into<String>) -> Self {
+        self.framing = Framing::Http;
+        self.http_path = path.into();
+        self
+    }
+
+    /// Accept TLS connections only. The verified client certificate, if
+    /// any, is set as the peer identity of every request of the connection.
+    pub fn tls(mut self, config: ServerTlsConfig) -> Self {
+        self.tls = Some(config);
+        self
+    }
+}
+
+impl<Addr> Server<MultiplexedServer, Addr, MultiplexedRequest, MultiplexedResponse> {
+    /// Serve all services registered in the `MultiplexedServer` on one address.
+    pub fn multiplexed(registry: MultiplexedServer) -> Self {
+        let decoder = registry.decoder();
+        let mut server = Self::new(registry);
+        server.decoder = Some(decoder);
+        server
+    }
 }
 
 impl<S, Addr, Req, Resp> Server<S, Addr, Req, Resp>
 where
-    Addr: Listenable,
+    Addr: Listenable + Send + Sync,
+    Addr::Stream: Send,
     S: Service<
             (MsgContext, ApplicationResult<Req>),
             Response = Option<(MsgContext, ApplicationResult<Resp>)>,
@@ -142,9 +235,23 @@ where
     Req: Send + Message + 'static,
     Resp: Send + Message + 'static,
 {
-    pub async fn serve(self, addr: Addr) -> Result<(), ServerError> {
-        let listen_stream = addr.bind().await?;
-        let make_codec = DefaultMakeCodec::<Resp, Req>::new();
+    pub async fn serve(mut self, addr: Addr) -> Result<(), ServerError> {
+        match self.tls.take() {
+            Some(config) => self.serve_on(TlsListener::new(addr, config)).await,
+            None => self.serve_on(addr).await,
+        }
+    }
+
+    async fn serve_on<L: Listenable>(self, listener: L) -> Result<(), ServerError> {
+        let listen_stream = listener.bind().await?;
+        let make_codec = DefaultMakeCodec::<Resp, Req>::with_shared_protocol(self.protocol)
+            .framing(self.framing)
+            .auto_detect(self.auto_detect)
+            .decode_limits(self.decode_limits)
+            .options(self.codec_options)
+            .http_path(self.http_path)
+            .server(true)
+            .decoder(self.decoder);
         let mut incoming = Incoming::new(listen_stream, make_codec);
 
         let buffer = self.buffer.unwrap_or(DEFAULT_BUFFER);
@@ -157,11 +264,13 @@ where
         loop {
             match incoming.try_next().await? {
                 Some(mut ts) => {
+                    let peer_identity = L::peer_identity(ts.get_ref());
                     let mut service = service.clone();
                     tokio::spawn(async move {
                         loop {
                             match ts.try_next().await {
-                                Ok(Some(req)) => {
+                                Ok(Some(mut req)) => {
+                                    req.0.peer_identity = peer_identity.clone();
                                     let ready_service = match service.ready().await {
                                         Ok(svc) => svc,
                                         Err(e) => {
@@ -172,18 +281,31 @@ where
 
                                     let mut cx = req.0.clone();
                                     match ready_service.call(req).await {
-                                        Ok(Some((cx, resp))) => {
+                                        Ok(Some((mut cx, resp))) => {
+                                            // Services may hand back the
+                                            // context of the call as is.
+                                            if cx.identifier.message_type == TMessageType::Call {
+                                                cx.identifier.message_type = TMessageType::Reply;
+                                            }
                                             if let Err(e) = ts.send((cx, resp)).await {
                                                 log::error!("send reply back error: {}", e);
                                                 return;
                                             }
                                         }
                                         Ok(None) => {
-                                            // oneway does not need response
+                                            // oneway does not need response,
+                                            // but HTTP peers wait for one.
+                                            if cx.identifier.message_type == TMessageType::OneWay {
+                                                if let Err(e) = answer_oneway(&mut ts).await {
+                                                    log::error!("send reply back error: {}", e);
+                                                    return;
+                                                }
+                                            }
                                         }
                                         Err(e) => {
                                             // if oneway, we just return
                                             if cx.identifier.message_type == TMessageType::OneWay {
+                                                let _ = answer_oneway(&mut ts).await;
                                                 return;
                                             }
                                             // if not oneway, we must send the exception back
@@ -205,6 +327,9 @@ where
                                 Err(e) => {
                                     // receive message error
                                     log::error!("error receiving message {}", e);
+                                    if let Some(response) = ts.codec_mut().take_rejection() {
+                                        let _ = write_raw(&mut ts, response).await;
+                                    }
                                     return;
                                 }
                             }
@@ -216,3 +341,29 @@ where
         }
     }
 }
+
+/// Answer a oneway call if the peer waits for a response.
+async fn answer_oneway<T, E, D>(ts: &mut Framed<T, DefaultCodec<E, D>>) -> Result<(), crate::Error>
+where
+    T: AsyncWrite + Unpin,
+    E: Message,
+{
+    match ts.codec().oneway_response() {
+        Some(response) => write_raw(ts, response).await,
+        None => Ok(()),
+    }
+}
+
+/// Write `response` as is to the peer, for the HTTP responses carrying no
+/// message.
+async fn write_raw<T, E, D>(
+    ts: &mut Framed<T, DefaultCodec<E, D>>,
+    response: &[u8],
+) -> Result<(), crate::Error>
+where
+    T: AsyncWrite + Unpin,
+    E: Message,
+{
+    ts.write_buffer_mut().extend_from_slice(response);
+    SinkExt::<(MsgContext, ApplicationResult<E>)>::flush(ts).await
+}
@@ -0,0 +1,419 @@
+use bytes::BufMut;
+
+use crate::context::MsgContext;
+use crate::json::{format_uuid, write_json_escaped};
+use crate::message::Message;
+use crate::protocol::{
+    TFieldIdentifier, TListIdentifier, TMapIdentifier, TMessageIdentifier, TOutputProtocol,
+    TSetIdentifier, TStructIdentifier, TType,
+};
+use crate::{new_protocol_error, ProtocolErrorKind};
+
+/// Where the protocol currently is inside the JSON document.
+#[derive(Debug, Clone, Copy)]
+enum SimpleJsonContext {
+    /// Top level, values are written back to back.
+    Base,
+    /// Inside an array, values are separated by `,`.
+    List { first: bool },
+    /// Inside an object, values alternate between keys and values.
+    Object { first: bool, key: bool },
+    /// A map whose keys cannot be object keys, written as `[[k,v],...]`.
+    Entries { first: bool, key: bool },
+}
+
+/// Write-only protocol rendering messages as human-readable JSON.
+///
+/// Structs are keyed by field name (or by field id when the name is unknown),
+/// maps with scalar keys become objects, lists and sets become arrays. It is
+/// meant for logs and debugging, there is no matching input protocol.
+///
+/// Mini-lust note: this is the counterpart of the official TSimpleJSONProtocol.
+#[derive(Debug)]
+pub struct TSimpleJSONOutputProtocol<T> {
+    buf: T,
+    context: SimpleJsonContext,
+    context_stack: Vec<SimpleJsonContext>,
+}
+
+impl<T> TSimpleJSONOutputProtocol<T> {
+    pub fn new(buf: T) -> TSimpleJSONOutputProtocol<T> {
+        TSimpleJSONOutputProtocol {
+            buf,
+            context: SimpleJsonContext::Base,
+            context_stack: Vec::new(),
+        }
+    }
+
+    /// Consume the protocol and return the underlying buffer.
+    pub fn into_inner(self) -> T {
+        self.buf
+    }
+
+    #[inline]
+    fn push_context(&mut self, context: SimpleJsonContext) {
+        let parent = std::mem::replace(&mut self.context, context);
+        self.context_stack.push(parent);
+    }
+
+    #[inline]
+    fn pop_context(&mut self) -> crate::Result<SimpleJsonContext> {
+        let parent = self.context_stack.pop().ok_or_else(|| {
+            new_protocol_error(ProtocolErrorKind::InvalidData, "unbalanced json context")
+        })?;
+        Ok(std::mem::replace(&mut self.context, parent))
+    }
+}
+
+impl<T> TSimpleJSONOutputProtocol<T>
+where
+    T: BufMut,
+{
+    /// Write the separator before the next value and tell whether the value
+    /// is an object key, in which case it must be quoted.
+    #[inline]
+    fn begin_value(&mut self) -> bool {
+        match &mut self.context {
+            SimpleJsonContext::Base => false,
+            SimpleJsonContext::List { first } => {
+                if !std::mem::replace(first, false) {
+                    self.buf.put_u8(b',');
+                }
+                false
+            }
+            SimpleJsonContext::Object { first, key } => {
+                let is_key = *key;
+                if !std::mem::replace(first, false) {
+                    self.buf.put_u8(if is_key { b',' } else { b':' });
+                }
+                *key = !is_key;
+                is_key
+            }
+            SimpleJsonContext::Entries { first, key } => {
+                if !*key {
+                    self.buf.put_u8(b',');
+                } else if std::mem::replace(first, false) {
+                    self.buf.put_u8(b'[');
+                } else {
+                    self.buf.put_slice(b"],[");
+                }
+                *key = !*key;
+                false
+            }
+        }
+    }
+
+    #[inline]
+    fn write_json_scalar(&mut self, s: &str) {
+        if self.begin_value() {
+            write_json_escaped(&mut self.buf, s.as_bytes());
+        } else {
+            self.buf.put_slice(s.as_bytes());
+        }
+    }
+
+    #[inline]
+    fn write_json_string(&mut self, b: &[u8]) {
+        self.begin_value();
+        write_json_escaped(&mut self.buf, b);
+    }
+
+    #[inline]
+    fn write_json_object_start(&mut self) {
+        self.begin_value();
+        self.buf.put_u8(b'{');
+        self.push_context(SimpleJsonContext::Object {
+            first: true,
+            key: true,
+        });
+    }
+
+    #[inline]
+    fn write_json_object_end(&mut self) -> crate::Result<()> {
+        self.pop_context()?;
+        self.buf.put_u8(b'}');
+        Ok(())
+    }
+
+    #[inline]
+    fn write_json_array_start(&mut self, context: SimpleJsonContext) {
+        self.begin_value();
+        self.buf.put_u8(b'[');
+        self.push_context(context);
+    }
+
+    #[inline]
+    fn write_json_array_end(&mut self) -> crate::Result<()> {
+        if let SimpleJsonContext::Entries { first: false, .. } = self.pop_context()? {
+            self.buf.put_u8(b']');
+        }
+        self.buf.put_u8(b']');
+        Ok(())
+    }
+}
+
+impl<T> TOutputProtocol for TSimpleJSONOutputProtocol<T>
+where
+    T: BufMut,
+{
+    #[inline]
+    fn write_message_begin(&mut self, identifier: &TMessageIdentifier) -> crate::Result<()> {
+        self.write_json_array_start(SimpleJsonContext::List { first: true });
+        self.write_json_string(identifier.name.as_bytes());
+        self.write_json_string(identifier.message_type.to_string().as_bytes());
+        self.write_json_scalar(&identifier.sequence_number.to_string());
+        Ok(())
+    }
+
+    #[inline]
+    fn write_message_end(&mut self) -> crate::Result<()> {
+        self.write_json_array_end()
+    }
+
+    #[inline]
+    fn write_struct_begin(&mut self, _: &TStructIdentifier) -> crate::Result<()> {
+        self.write_json_object_start();
+        Ok(())
+    }
+
+    #[inline]
+    fn write_struct_end(&mut self) -> crate::Result<()> {
+        self.write_json_object_end()
+    }
+
+    #[inline]
+    fn write_field_begin(&mut self, identifier: &TFieldIdentifier) -> crate::Result<()> {
+        match (&identifier.name, identifier.id) {
+            (Some(name), _) => self.write_json_string(name.as_bytes()),
+            (None, Some(id)) => self.write_json_scalar(&id.to_string()),
+            (None, None) => {
+                return Err(new_protocol_error(
+                    ProtocolErrorKind::Unknown,
+                    format!(
+                        "cannot write identifier {:?} without name or sequence number",
+                        &identifier
+                    ),
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn write_field_end(&mut self) -> crate::Result<()> {
+        Ok(())
+    }
+
+    #[inline]
+    fn write_field_stop(&mut self) -> crate::Result<()> {
+        Ok(())
+    }
+
+    #[inline]
+    fn write_bool(&mut self, b: bool) -> crate::Result<()> {
+        self.write_json_scalar(if b { "true" } else { "false" });
+        Ok(())
+    }
+
+    #[inline]
+    fn write_bytes(&mut self, b: &[u8]) -> crate::Result<()> {
+        let encoded = base64::encode(b);
+        self.write_json_string(encoded.as_bytes());
+        Ok(())
+    }
+
+    #[inline]
+    fn write_i8(&mut self, i: i8) -> crate::Result<()> {
+        self.write_json_scalar(&i.to_string());
+        Ok(())
+    }
+
+    #[inline]
+    fn write_i16(&mut self, i: i16) -> crate::Result<()> {
+        self.write_json_scalar(&i.to_string());
+        Ok(())
+    }
+
+    #[inline]
+    fn write_i32(&mut self, i: i32) -> crate::Result<()> {
+        self.write_json_scalar(&i.to_string());
+        Ok(())
+    }
+
+    #[inline]
+    fn write_i64(&mut self, i: i64) -> crate::Result<()> {
+        self.write_json_scalar(&i.to_string());
+        Ok(())
+    }
+
+    #[inline]
+    fn write_double(&mut self, d: f64) -> crate::Result<()> {
+        if d.is_finite() {
+            self.write_json_scalar(&format!("{:?}", d));
+        } else {
+            // NaN and infinities are not valid JSON numbers.
+            let s = if d.is_nan() {
+                "NaN"
+            } else if d > 0.0 {
+                "Infinity"
+            } else {
+                "-Infinity"
+            };
+            self.write_json_string(s.as_bytes());
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn write_string(&mut self, s: &str) -> crate::Result<()> {
+        self.write_json_string(s.as_bytes());
+        Ok(())
+    }
+
+    #[inline]
+    fn write_uuid(&mut self, uuid: [u8; 16]) -> crate::Result<()> {
+        self.write_json_string(format_uuid(&uuid).as_bytes());
+        Ok(())
+    }
+
+    #[inline]
+    fn write_list_begin(&mut self, _: &TListIdentifier) -> crate::Result<()> {
+        self.write_json_array_start(SimpleJsonContext::List { first: true });
+        Ok(())
+    }
+
+    #[inline]
+    fn write_list_end(&mut self) -> crate::Result<()> {
+        self.write_json_array_end()
+    }
+
+    #[inline]
+    fn write_set_begin(&mut self, _: &TSetIdentifier) -> crate::Result<()> {
+        self.write_json_array_start(SimpleJsonContext::List { first: true });
+        Ok(())
+    }
+
+    #[inline]
+    fn write_set_end(&mut self) -> crate::Result<()> {
+        self.write_json_array_end()
+    }
+
+    #[inline]
+    fn write_map_begin(&mut self, identifier: &TMapIdentifier) -> crate::Result<()> {
+        match identifier.key_type {
+            Some(TType::Struct) | Some(TType::Map) | Some(TType::Set) | Some(TType::List) => {
+                self.write_json_array_start(SimpleJsonContext::Entries {
+                    first: true,
+                    key: true,
+                });
+            }
+            _ => self.write_json_object_start(),
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn write_map_end(&mut self) -> crate::Result<()> {
+        match self.context {
+            SimpleJsonContext::Entries { .. } => self.write_json_array_end(),
+            _ => self.write_json_object_end(),
+        }
+    }
+
+    #[inline]
+    fn flush(&mut self) -> crate::Result<()> {
+        Ok(())
+    }
+
+    // utility
+    //
+
+    #[inline]
+    fn write_byte(&mut self, b: u8) -> crate::Result<()> {
+        self.write_json_scalar(&b.to_string());
+        Ok(())
+    }
+}
+
+/// Render a message as human-readable JSON, e.g. for logging.
+pub fn to_simple_json<M: Message>(message: &M) -> crate::Result<String> {
+    let mut protocol = TSimpleJSONOutputProtocol::new(Vec::new());
+    message.encode(&MsgContext::default(), &mut protocol)?;
+    String::from_utf8(protocol.into_inner()).map_err(From::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::protocol::{
+        TFieldIdentifier, TListIdentifier, TMapIdentifier, TMessageIdentifier, TMessageType,
+        TOutputProtocol, TStructIdentifier, TType,
+    };
+    use crate::simple_json::{to_simple_json, TSimpleJSONOutputProtocol};
+    use crate::{ApplicationError, ApplicationErrorKind};
+
+    #[test]
+    fn test_nested_containers() {
+        let mut protocol = TSimpleJSONOutputProtocol::new(Vec::new());
+        protocol
+            .write_message_begin(&TMessageIdentifier::new("foo", TMessageType::Call, 3))
+            .unwrap();
+        protocol
+            .write_struct_begin(&TStructIdentifier::new("args"))
+            .unwrap();
+        protocol
+            .write_field_begin(&TFieldIdentifier::new("tags", TType::List, 1))
+            .unwrap();
+        protocol
+            .write_list_begin(&TListIdentifier::new(TType::String, 2))
+            .unwrap();
+        protocol.write_string("a\"b").unwrap();
+        protocol.write_string("c").unwrap();
+        protocol.write_list_end().unwrap();
+        protocol.write_field_end().unwrap();
+        protocol
+            .write_field_begin(&TFieldIdentifier::new("scores", TType::Map, 2))
+            .unwrap();
+        protocol
+            .write_map_begin(&TMapIdentifier::new(TType::I32, TType::Bool, 2))
+            .unwrap();
+        protocol.write_i32(1).unwrap();
+        protocol.write_bool(true).unwrap();
+        protocol.write_i32(2).unwrap();
+        protocol.write_bool(false).unwrap();
+        protocol.write_map_end().unwrap();
+        protocol.write_field_end().unwrap();
+        protocol
+            .write_field_begin(&TFieldIdentifier::new::<Option<String>, String, i16>(
+                None,
+                TType::Map,
+                3,
+            ))
+            .unwrap();
+        protocol
+            .write_map_begin(&TMapIdentifier::new(TType::List, TType::Double, 1))
+            .unwrap();
+        protocol
+            .write_list_begin(&TListIdentifier::new(TType::I64, 1))
+            .unwrap();
+        protocol.write_i64(7).unwrap();
+        protocol.write_list_end().unwrap();
+        protocol.write_double(f64::NAN).unwrap();
+        protocol.write_map_end().unwrap();
+        protocol.write_field_end().unwrap();
+        protocol.write_field_stop().unwrap();
+        protocol.write_struct_end().unwrap();
+        protocol.write_message_end().unwrap();
+
+        let expected = r#"["foo","Call",3,{"tags":["a\"b","c"],"scores":{"1":true,"2":false},"3":[[[7],"NaN"]]}]"#;
+        assert_eq!(String::from_utf8(protocol.into_inner()).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_to_simple_json() {
+        let error = ApplicationError::new(ApplicationErrorKind::UnknownMethod, "no such method");
+        assert_eq!(
+            to_simple_json(&error).unwrap(),
+            r#"{"message":"no such method","type":1}"#
+        );
+    }
+}
@@ -0,0 +1,110 @@
+//! Fixtures shared by the tests of several modules.
+
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::net::TcpStream;
+use tokio_stream::wrappers::TcpListenerStream;
+use tower::Service;
+
+use crate::binary::TBinaryProtocolFactory;
+use crate::codec::MessageCodec;
+use crate::context::MsgContext;
+use crate::memory::MemoryAddr;
+use crate::protocol::{TMessageIdentifier, TMessageType};
+use crate::server::Listenable;
+use crate::{ApplicationResult, Server};
+
+pub(crate) fn message_codec() -> MessageCodec<String, String> {
+    MessageCodec::new(Arc::new(TBinaryProtocolFactory::default()))
+}
+
+pub(crate) fn cx() -> MsgContext {
+    MsgContext {
+        identifier: TMessageIdentifier::new("echo", TMessageType::Call, 1),
+        ..MsgContext::default()
+    }
+}
+
+/// A loopback port bound up front, no other test can take it before the
+/// server listens on it.
+pub(crate) struct LoopbackAddr(Mutex<Option<std::net::TcpListener>>);
+
+impl LoopbackAddr {
+    pub(crate) fn new() -> (Self, SocketAddr) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        (LoopbackAddr(Mutex::new(Some(listener))), addr)
+    }
+}
+
+#[async_trait::async_trait]
+impl Listenable for LoopbackAddr {
+    type Conn = TcpStream;
+    type Stream = TcpListenerStream;
+
+    async fn bind(&self) -> io::Result<Self::Stream> {
+        let listener = self.0.lock().unwrap().take().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::AddrInUse, "the loopback port is taken")
+        })?;
+        listener.set_nonblocking(true)?;
+        let listener = tokio::net::TcpListener::from_std(listener)?;
+        Ok(TcpListenerStream::new(listener))
+    }
+}
+
+/// Spawn `server` on a loopback port, connections queue until it accepts
+/// them.
+pub(crate) fn serve_on_loopback<S>(server: Server<S, LoopbackAddr, String, String>) -> SocketAddr
+where
+    S: Service<
+            (MsgContext, ApplicationResult<String>),
+            Response = Option<(MsgContext, ApplicationResult<String>)>,
+            Error = crate::Error,
+        > + Send
+        + 'static,
+    S::Future: Send,
+{
+    let (listener, addr) = LoopbackAddr::new();
+    tokio::spawn(server.serve(listener));
+    addr
+}
+
+/// Spawn `server` on `MemoryAddr::new(name)`, returns once it listens.
+pub(crate) async fn serve_in_memory<S>(server: Server<S, MemoryAddr, String, String>, name: &str)
+where
+    S: Service<
+            (MsgContext, ApplicationResult<String>),
+            Response = Option<(MsgContext, ApplicationResult<String>)>,
+            Error = crate::Error,
+        > + Send
+        + 'static,
+    S::Future: Send,
+{
+    serve_in_memory_on(server, MemoryAddr::new(name), name).await
+}
+
+/// Spawn `server` on `listener`, which listens in memory on `name`, returns
+/// once it listens.
+pub(crate) async fn serve_in_memory_on<S, L>(
+    server: Server<S, L, String, String>,
+    listener: L,
+    name: &str,
+) where
+    S: Service<
+            (MsgContext, ApplicationResult<String>),
+            Response = Option<(MsgContext, ApplicationResult<String>)>,
+            Error = crate::Error,
+        > + Send
+        + 'static,
+    S::Future: Send,
+    L: Listenable + Send + Sync + 'static,
+    L::Stream: Send,
+{
+    tokio::spawn(server.serve(listener));
+    while crate::memory::connect(name).is_err() {
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+}
@@ -0,0 +1,553 @@
+//! TLS for client connections and server listeners, based on rustls.
+
+use std::convert::TryFrom;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::stream::{FuturesUnordered, Stream, StreamExt};
+use tokio_rustls::rustls::server::{
+    AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient,
+};
+use tokio_rustls::rustls::sign::{any_supported_type, CertifiedKey};
+use tokio_rustls::rustls::{
+    self, Certificate, ClientConfig, PrivateKey, RootCertStore, ServerConfig, ServerName,
+    SignatureScheme,
+};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+use tower::Service;
+
+use crate::connection::{BoxedIo, DefaultMakeConnection, SocketOrUnix};
+use crate::server::Listenable;
+use crate::utils::BoxFuture;
+
+fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+fn parse_certificates(pem: &[u8]) -> io::Result<Vec<Certificate>> {
+    let certificates = rustls_pemfile::certs(&mut &*pem)?;
+    if certificates.is_empty() {
+        return Err(invalid_data("no certificate found in PEM"));
+    }
+    Ok(certificates.into_iter().map(Certificate).collect())
+}
+
+fn parse_private_key(pem: &[u8]) -> io::Result<PrivateKey> {
+    let mut reader = pem;
+    while let Some(item) = rustls_pemfile::read_one(&mut reader)? {
+        match item {
+            rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::ECKey(key) => return Ok(PrivateKey(key)),
+            _ => {}
+        }
+    }
+    Err(invalid_data("no private key found in PEM"))
+}
+
+fn parse_roots(pem: &[u8]) -> io::Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for certificate in parse_certificates(pem)? {
+        roots.add(&certificate).map_err(invalid_data)?;
+    }
+    Ok(roots)
+}
+
+fn certified_key(chain_pem: &[u8], key_pem: &[u8]) -> io::Result<CertifiedKey> {
+    let chain = parse_certificates(chain_pem)?;
+    let key = any_supported_type(&parse_private_key(key_pem)?).map_err(invalid_data)?;
+    Ok(CertifiedKey::new(chain, key))
+}
+
+/// How a client secures its connections.
+#[derive(Clone)]
+pub struct ClientTlsConfig {
+    roots: RootCertStore,
+    identity: Option<Arc<CertifiedKey>>,
+    server_name: Option<String>,
+}
+
+impl ClientTlsConfig {
+    /// A configuration trusting no certificate authority yet.
+    pub fn new() -> Self {
+        Self {
+            roots: RootCertStore::empty(),
+            identity: None,
+            server_name: None,
+        }
+    }
+
+    /// Trust the PEM encoded certificate authorities to sign server
+    /// certificates.
+    pub fn ca_certificates_pem(mut self, pem: &[u8]) -> io::Result<Self> {
+        for certificate in parse_certificates(pem)? {
+            self.roots.add(&certificate).map_err(invalid_data)?;
+        }
+        Ok(self)
+    }
+
+    /// Present the PEM encoded certificate chain and private key to servers
+    /// asking for a client certificate.
+    pub fn identity_pem(mut self, chain_pem: &[u8], key_pem: &[u8]) -> io::Result<Self> {
+        self.identity = Some(Arc::new(certified_key(chain_pem, key_pem)?));
+        Ok(self)
+    }
+
+    /// Name sent with SNI and checked against the server certificate.
+    ///
+    /// Defaults to the host name or the IP address of the target, a name must
+    /// be given for unix socket and in-memory targets.
+    pub fn server_name(mut self, server_name: impl Into<String>) -> Self {
+        self.server_name = Some(server_name.into());
+        self
+    }
+
+    fn connector(&self) -> TlsConnector {
+        let builder = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(self.roots.clone());
+        let config = match &self.identity {
+            Some(identity) => {
+                builder.with_client_cert_resolver(Arc::new(ClientIdentity(identity.clone())))
+            }
+            None => builder.with_no_client_auth(),
+        };
+        TlsConnector::from(Arc::new(config))
+    }
+}
+
+impl Default for ClientTlsConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Debug for ClientTlsConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ClientTlsConfig")
+            .field("roots", &self.roots.len())
+            .field("identity", &self.identity.is_some())
+            .field("server_name", &self.server_name)
+            .finish()
+    }
+}
+
+// Mini-lust note: the key is checked when configured, so that building the
+// rustls config later cannot fail.
+struct ClientIdentity(Arc<CertifiedKey>);
+
+impl rustls::client::ResolvesClientCert for ClientIdentity {
+    fn resolve(
+        &self,
+        _acceptable_issuers: &[&[u8]],
+        _sigschemes: &[SignatureScheme],
+    ) -> Option<Arc<CertifiedKey>> {
+        Some(self.0.clone())
+    }
+
+    fn has_certs(&self) -> bool {
+        true
+    }
+}
+
+// Time given to a client to complete its handshake by default.
+const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How a server secures the connections it accepts.
+#[derive(Clone)]
+pub struct ServerTlsConfig {
+    identity: Arc<CertifiedKey>,
+    client_auth: Option<(RootCertStore, bool)>,
+    handshake_timeout: Duration,
+}
+
+impl ServerTlsConfig {
+    /// Present the PEM encoded certificate chain and private key to clients.
+    pub fn new(chain_pem: &[u8], key_pem: &[u8]) -> io::Result<Self> {
+        Ok(Self {
+            identity: Arc::new(certified_key(chain_pem, key_pem)?),
+            client_auth: None,
+            handshake_timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+        })
+    }
+
+    /// Ask clients for a certificate signed by one of the PEM encoded
+    /// certificate authorities. When `required`, clients without one are
+    /// refused, otherwise they are served without a peer identity.
+    pub fn client_auth(mut self, ca_pem: &[u8], required: bool) -> io::Result<Self> {
+        self.client_auth = Some((parse_roots(ca_pem)?, required));
+        Ok(self)
+    }
+
+    /// Drop connections whose handshake is not done after `timeout`, 10
+    /// seconds by default.
+    pub fn handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.handshake_timeout = timeout;
+        self
+    }
+
+    fn acceptor(&self) -> TlsAcceptor {
+        let builder = ServerConfig::builder().with_safe_defaults();
+        let builder = match &self.client_auth {
+            Some((roots, true)) => builder
+                .with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots.clone()).boxed()),
+            Some((roots, false)) => builder.with_client_cert_verifier(
+                AllowAnyAnonymousOrAuthenticatedClient::new(roots.clone()).boxed(),
+            ),
+            None => builder.with_no_client_auth(),
+        };
+        let config = builder.with_cert_resolver(Arc::new(ServerIdentity(self.identity.clone())));
+        TlsAcceptor::from(Arc::new(config))
+    }
+}
+
+impl std::fmt::Debug for ServerTlsConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ServerTlsConfig")
+            .field(
+                "client_auth",
+                &self.client_auth.as_ref().map(|(_, required)| required),
+            )
+            .field("handshake_timeout", &self.handshake_timeout)
+            .finish()
+    }
+}
+
+struct ServerIdentity(Arc<CertifiedKey>);
+
+impl rustls::server::ResolvesServerCert for ServerIdentity {
+    fn resolve(&self, _client_hello: rustls::server::ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.0.clone())
+    }
+}
+
+/// The verified certificate of the peer of a connection.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PeerIdentity {
+    /// DER encoded certificate chain, the peer certificate first.
+    pub certificates: Vec<Vec<u8>>,
+    /// Common name of the certificate subject.
+    pub common_name: Option<String>,
+    /// DNS names of the certificate subject alternative names.
+    pub dns_names: Vec<String>,
+}
+
+impl PeerIdentity {
+    fn from_certificates(certificates: &[Certificate]) -> Option<Self> {
+        let (_, certificate) =
+            x509_parser::parse_x509_certificate(&certificates.first()?.0).ok()?;
+        let common_name = certificate
+            .subject()
+            .iter_common_name()
+            .next()
+            .and_then(|cn| cn.as_str().ok())
+            .map(str::to_string);
+        let dns_names = match certificate.subject_alternative_name() {
+            Ok(Some(san)) => san
+                .value
+                .general_names
+                .iter()
+                .filter_map(|name| match name {
+                    x509_parser::extensions::GeneralName::DNSName(name) => Some(name.to_string()),
+                    _ => None,
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+        Some(PeerIdentity {
+            certificates: certificates.iter().map(|c| c.0.clone()).collect(),
+            common_name,
+            dns_names,
+        })
+    }
+}
+
+/// Connects to targets with `DefaultMakeConnection` and runs a TLS handshake
+/// over the connection.
+#[derive(Clone)]
+pub struct TlsMakeConnection {
+    inner: DefaultMakeConnection,
+    connector: TlsConnector,
+    server_name: Option<String>,
+}
+
+impl TlsMakeConnection {
+    pub fn new(inner: DefaultMakeConnection, config: &ClientTlsConfig) -> Self {
+        Self {
+            inner,
+            connector: config.connector(),
+            server_name: config.server_name.clone(),
+        }
+    }
+}
+
+impl Service<SocketOrUnix> for TlsMakeConnection {
+    type Response = BoxedIo;
+    type Error = io::Error;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: SocketOrUnix) -> Self::Future {
+        let server_name = match (&self.server_name, &req) {
+            (Some(name), _) => ServerName::try_from(name.as_str()).map_err(invalid_data),
+            (None, SocketOrUnix::Socket(addr)) => Ok(ServerName::IpAddress(addr.ip())),
+            (None, SocketOrUnix::Host(host, _)) => {
+                ServerName::try_from(host.as_str()).map_err(invalid_data)
+            }
+            (None, _) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "a server name is required for TLS over unix sockets and memory pipes",
+            )),
+        };
+        let conn_fut = self.inner.call(req);
+        let connector = self.connector.clone();
+        Box::pin(async move {
+            let server_name = server_name?;
+            let conn = conn_fut.await?;
+            let tls = connector.connect(server_name, conn).await?;
+            Ok(BoxedIo::new(tls))
+        })
+    }
+}
+
+/// A listener whose connections are served over TLS.
+pub struct TlsListener<A> {
+    inner: A,
+    config: ServerTlsConfig,
+}
+
+impl<A> TlsListener<A> {
+    pub fn new(inner: A, config: ServerTlsConfig) -> Self {
+        Self { inner, config }
+    }
+}
+
+#[async_trait::async_trait]
+impl<A> Listenable for TlsListener<A>
+where
+    A: Listenable + Send + Sync,
+    A::Stream: Send,
+{
+    type Conn = TlsStream<A::Conn>;
+    type Stream = TlsIncoming<A::Stream, A::Conn>;
+
+    async fn bind(&self) -> io::Result<Self::Stream> {
+        let listener_stream = self.inner.bind().await?;
+        Ok(TlsIncoming {
+            listener_stream,
+            acceptor: self.config.acceptor(),
+            handshake_timeout: self.config.handshake_timeout,
+            handshakes: FuturesUnordered::new(),
+            done: false,
+        })
+    }
+
+    fn peer_identity(conn: &Self::Conn) -> Option<Arc<PeerIdentity>> {
+        let (_, session) = conn.get_ref();
+        session
+            .peer_certificates()
+            .and_then(PeerIdentity::from_certificates)
+            .map(Arc::new)
+    }
+}
+
+/// Accepted connections whose TLS handshake succeeded.
+///
+/// Handshakes run concurrently, so that a slow client does not hold up the
+/// others. Failed or timed out handshakes are logged and skipped, accept
+/// errors are passed through.
+pub struct TlsIncoming<S, C> {
+    listener_stream: S,
+    acceptor: TlsAcceptor,
+    handshake_timeout: Duration,
+    handshakes: FuturesUnordered<BoxFuture<TlsStream<C>, io::Error>>,
+    done: bool,
+}
+
+impl<S, C> Stream for TlsIncoming<S, C>
+where
+    S: Stream<Item = io::Result<C>> + Unpin,
+    C: tokio::io::AsyncRead + tokio::io::AsyncWrite + Send + Unpin + 'static,
+{
+    type Item = io::Result<TlsStream<C>>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        while !this.done {
+            match this.listener_stream.poll_next_unpin(cx) {
+                Poll::Ready(Some(Ok(conn))) => {
+                    let handshake =
+                        tokio::time::timeout(this.handshake_timeout, this.acceptor.accept(conn));
+                    this.handshakes.push(Box::pin(async move {
+                        handshake.await.map_err(|_| {
+                            io::Error::new(io::ErrorKind::TimedOut, "handshake timed out")
+                        })?
+                    }));
+                }
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Some(Err(e))),
+                Poll::Ready(None) => this.done = true,
+                Poll::Pending => break,
+            }
+        }
+        loop {
+            match this.handshakes.poll_next_unpin(cx) {
+                Poll::Ready(Some(Ok(conn))) => return Poll::Ready(Some(Ok(conn))),
+                Poll::Ready(Some(Err(e))) => {
+                    log::warn!("TLS handshake error: {}", e);
+                }
+                Poll::Ready(None) if this.done => return Poll::Ready(None),
+                Poll::Ready(None) | Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures::StreamExt;
+    use tokio::io::AsyncReadExt;
+    use tower::service_fn;
+
+    use crate::context::MsgContext;
+    use crate::memory::MemoryAddr;
+    use crate::server::Listenable;
+    use crate::testing::serve_in_memory;
+    use crate::tls::{ClientTlsConfig, ServerTlsConfig, TlsListener};
+    use crate::{ApplicationResult, Client, ClientBuilder, Server, SocketOrUnix};
+
+    struct Issued {
+        chain: String,
+        key: String,
+    }
+
+    fn ca() -> rcgen::Certificate {
+        let mut params = rcgen::CertificateParams::new(Vec::new());
+        params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "mini-lust test CA");
+        rcgen::Certificate::from_params(params).unwrap()
+    }
+
+    fn issue(ca: &rcgen::Certificate, name: &str) -> Issued {
+        let mut params = rcgen::CertificateParams::new(vec![name.to_string()]);
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, name);
+        let certificate = rcgen::Certificate::from_params(params).unwrap();
+        Issued {
+            chain: certificate.serialize_pem_with_signer(ca).unwrap(),
+            key: certificate.serialize_private_key_pem(),
+        }
+    }
+
+    async fn serve_whoami(name: &'static str, config: ServerTlsConfig) {
+        // replies with the common name of the client certificate
+        let service = service_fn(
+            |(cx, _req): (MsgContext, ApplicationResult<String>)| async move {
+                let name = cx
+                    .peer_identity
+                    .as_ref()
+                    .and_then(|identity| identity.common_name.clone())
+                    .unwrap_or_default();
+                Ok::<_, crate::Error>(Some((cx, Ok(name))))
+            },
+        );
+        let server = Server::<_, MemoryAddr, String, String>::new(service).tls(config);
+        serve_in_memory(server, name).await;
+    }
+
+    fn client(name: &str, config: ClientTlsConfig) -> Client<String, String> {
+        ClientBuilder::new(SocketOrUnix::Memory(name.to_string()))
+            .tls(config)
+            .build()
+    }
+
+    #[tokio::test]
+    async fn test_mutual_tls() {
+        let ca = ca();
+        let ca_pem = ca.serialize_pem().unwrap();
+        let server_cert = issue(&ca, "localhost");
+        let client_cert = issue(&ca, "client.example");
+
+        let config = ServerTlsConfig::new(server_cert.chain.as_bytes(), server_cert.key.as_bytes())
+            .unwrap()
+            .client_auth(ca_pem.as_bytes(), true)
+            .unwrap();
+        serve_whoami("tls-mutual", config).await;
+
+        let config = ClientTlsConfig::new()
+            .ca_certificates_pem(ca_pem.as_bytes())
+            .unwrap()
+            .server_name("localhost");
+        let mut anonymous = client("tls-mutual", config.clone());
+        assert!(anonymous.call("whoami", String::new()).await.is_err());
+
+        let config = config
+            .identity_pem(client_cert.chain.as_bytes(), client_cert.key.as_bytes())
+            .unwrap();
+        let mut authenticated = client("tls-mutual", config.clone());
+        let name = authenticated.call("whoami", String::new()).await.unwrap();
+        assert_eq!(name, "client.example");
+
+        // the server certificate is not valid for another name
+        let mut mismatched = client("tls-mutual", config.server_name("other.example"));
+        assert!(mismatched.call("whoami", String::new()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_optional_client_auth() {
+        let ca = ca();
+        let ca_pem = ca.serialize_pem().unwrap();
+        let server_cert = issue(&ca, "localhost");
+
+        let config = ServerTlsConfig::new(server_cert.chain.as_bytes(), server_cert.key.as_bytes())
+            .unwrap()
+            .client_auth(ca_pem.as_bytes(), false)
+            .unwrap();
+        serve_whoami("tls-optional", config).await;
+
+        let config = ClientTlsConfig::new()
+            .ca_certificates_pem(ca_pem.as_bytes())
+            .unwrap()
+            .server_name("localhost");
+        let mut anonymous = client("tls-optional", config);
+        let name = anonymous.call("whoami", String::new()).await.unwrap();
+        assert_eq!(name, "");
+    }
+
+    #[tokio::test]
+    async fn test_handshake_timeout() {
+        let ca = ca();
+        let server_cert = issue(&ca, "localhost");
+        let config = ServerTlsConfig::new(server_cert.chain.as_bytes(), server_cert.key.as_bytes())
+            .unwrap()
+            .handshake_timeout(Duration::from_millis(50));
+        let listener = TlsListener::new(MemoryAddr::new("tls-handshake-timeout"), config);
+        let mut incoming = listener.bind().await.unwrap();
+
+        // a client that never sends its hello
+        let mut silent = crate::memory::connect("tls-handshake-timeout").unwrap();
+        let accepted = tokio::time::timeout(Duration::from_millis(200), incoming.next()).await;
+        assert!(accepted.is_err(), "no connection must be accepted");
+        let mut buf = [0u8; 1];
+        assert_eq!(silent.read(&mut buf).await.unwrap(), 0);
+    }
+
+    #[test]
+    fn test_invalid_pem() {
+        assert!(ClientTlsConfig::new()
+            .ca_certificates_pem(b"garbage")
+            .is_err());
+        assert!(ServerTlsConfig::new(b"garbage", b"garbage").is_err());
+    }
+}
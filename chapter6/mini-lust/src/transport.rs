@@ -4,7 +4,7 @@ use tokio_util::codec::Framed;
 use tower::make::MakeConnection;
 use tower::Service;
 
-use crate::codec::MakeCodec;
+use crate::codec::{framed, MakeCodec};
 use crate::utils::BoxFuture;
 
 pub struct FramedMakeTransport<MCC, MCN> {
@@ -40,9 +40,10 @@ where
     fn call(&mut self, target: TG) -> Self::Future {
         let conn_fut = self.make_connection.make_connection(target);
         let codec = self.make_codec.make_codec();
+        let capacity = self.make_codec.buffer_capacity();
         Box::pin(async move {
             let conn = conn_fut.await?;
-            Ok(Framed::new(conn, codec))
+            Ok(framed(conn, codec, capacity))
         })
     }
 }
@@ -1,4 +1,6 @@
+use std::collections::hash_map::RandomState;
 use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
 use std::pin::Pin;
 
 pub type BoxFuture<T, E> = Pin<Box<dyn Future<Output = std::result::Result<T, E>> + Send>>;
@@ -12,4 +14,9 @@ pub fn ttype_comparing(x: crate::protocol::TType, y: crate::protocol::TType) ->
         ));
     }
     Ok(())
-}
\ No newline at end of file
+}
+
+pub(crate) fn random() -> u64 {
+    // Each `RandomState` is seeded differently.
+    RandomState::new().build_hasher().finish()
+}
@@ -9,7 +9,7 @@ use crate::basic::{Identifier, IdentifierRef, Literal, LiteralRef, Separator};
 use crate::Parser;
 
 // FieldType       ::=  Identifier | BaseType | ContainerType
-// BaseType        ::=  'bool' | 'byte' | 'i8' | 'i16' | 'i32' | 'i64' | 'double' | 'string' | 'binary'
+// BaseType        ::=  'bool' | 'byte' | 'i8' | 'i16' | 'i32' | 'i64' | 'double' | 'string' | 'binary' | 'uuid'
 // ContainerType   ::=  MapType | SetType | ListType
 // MapType         ::=  'map' CppType? '<' FieldType ',' FieldType '>'
 // SetType         ::=  'set' CppType? '<' FieldType '>'
@@ -28,6 +28,7 @@ pub enum FieldTypeRef<'a> {
     Double,
     String,
     Binary,
+    Uuid,
     Map(Box<FieldTypeRef<'a>>, Box<FieldTypeRef<'a>>),
     Set(Box<FieldTypeRef<'a>>),
     List(Box<FieldTypeRef<'a>>),
@@ -45,6 +46,7 @@ impl<'a> FieldTypeRef<'a> {
             map(tag("double"), |_| Self::Double),
             map(tag("string"), |_| Self::String),
             map(tag("binary"), |_| Self::Binary),
+            map(tag("uuid"), |_| Self::Uuid),
         ))(input)
     }
 
@@ -127,6 +129,7 @@ pub enum FieldType {
     Double,
     String,
     Binary,
+    Uuid,
     Map(Box<FieldType>, Box<FieldType>),
     Set(Box<FieldType>),
     List(Box<FieldType>),
@@ -145,6 +148,7 @@ impl<'a> From<FieldTypeRef<'a>> for FieldType {
             FieldTypeRef::Double => FieldType::Double,
             FieldTypeRef::String => FieldType::String,
             FieldTypeRef::Binary => FieldType::Binary,
+            FieldTypeRef::Uuid => FieldType::Uuid,
             FieldTypeRef::Map(k, v) => {
                 FieldType::Map(Box::new(k.as_ref().into()), Box::new(v.as_ref().into()))
             }
@@ -167,6 +171,7 @@ impl<'a> From<&FieldTypeRef<'a>> for FieldType {
             FieldTypeRef::Double => FieldType::Double,
             FieldTypeRef::String => FieldType::String,
             FieldTypeRef::Binary => FieldType::Binary,
+            FieldTypeRef::Uuid => FieldType::Uuid,
             FieldTypeRef::Map(k, v) => {
                 FieldType::Map(Box::new(k.as_ref().into()), Box::new(v.as_ref().into()))
             }
@@ -231,8 +236,8 @@ mod test {
     #[test]
     fn test_field_type() {
         assert_list_eq_with_f(
-            vec!["bool", "i16"],
-            vec![FieldTypeRef::Bool, FieldTypeRef::I16],
+            vec!["bool", "i16", "uuid"],
+            vec![FieldTypeRef::Bool, FieldTypeRef::I16, FieldTypeRef::Uuid],
             FieldTypeRef::parse,
             |x| x,
         );